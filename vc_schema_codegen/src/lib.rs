@@ -0,0 +1,306 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt::Write;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use vc_signing::VerifiableCredential;
+
+const KEYWORDS: [&str; 51] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while",
+];
+
+/// Generates Rust source for the credentialSubject of a schema credential
+///
+/// The schema credential is checked against its meta-schema, and against the public key if given
+pub fn generate(
+    schema: &Value,
+    public_key: Option<&[u8]>,
+    type_name: &str,
+) -> Result<String, String> {
+    let schema = VerifiableCredential::new(schema.clone(), None)?;
+    if let Some(public_key) = public_key {
+        schema.verify(public_key)?;
+    }
+    let json_schema = schema.json_schema()?;
+    let mut codegen = Codegen {
+        root: json_schema,
+        items: Vec::new(),
+        used: BTreeSet::new(),
+        refs: BTreeMap::new(),
+        in_progress: BTreeSet::new(),
+    };
+    let name = codegen.unique(&pascal_case(type_name));
+    let root_type = codegen.type_for(json_schema, &name, true)?;
+    if root_type != name {
+        codegen
+            .items
+            .insert(0, format!("pub type {} = {};\n", name, root_type));
+    }
+    let mut source = "// Generated by vc_schema_codegen, do not edit\n".to_string();
+    for item in codegen.items {
+        source.push('\n');
+        source.push_str(&item);
+    }
+    Ok(source)
+}
+
+/// Generates Rust types for a schema credential file into `$OUT_DIR/<file_name>`, for use from a build script
+pub fn build(
+    schema_path: &Path,
+    public_key: Option<&[u8]>,
+    type_name: &str,
+    file_name: &str,
+) -> Result<(), String> {
+    println!("cargo:rerun-if-changed={}", schema_path.display());
+    let schema: Value = serde_json::from_str(
+        &read_to_string(schema_path).map_err(|e| format!("{}: {}", schema_path.display(), e))?,
+    )
+    .map_err(|e| format!("{}: {}", schema_path.display(), e))?;
+    let source = generate(&schema, public_key, type_name)
+        .map_err(|e| format!("{}: {}", schema_path.display(), e))?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR").map_err(|e| format!("OUT_DIR: {}", e))?);
+    write(out_dir.join(file_name), source).map_err(|e| e.to_string())
+}
+
+struct Codegen<'a> {
+    root: &'a Value,
+    items: Vec<String>,
+    used: BTreeSet<String>,
+    refs: BTreeMap<String, String>,
+    in_progress: BTreeSet<String>,
+}
+
+impl Codegen<'_> {
+    /// Returns an unused type name based on the given one
+    fn unique(&mut self, name: &str) -> String {
+        let mut unique = name.to_string();
+        let mut suffix = 2;
+        while self.used.contains(&unique) {
+            unique = format!("{}{}", name, suffix);
+            suffix += 1;
+        }
+        self.used.insert(unique.clone());
+        unique
+    }
+    /// Returns the Rust type for a subschema, emitting any structs or enums it needs
+    ///
+    /// `named` is true when `name` has already been reserved for this subschema
+    fn type_for(&mut self, schema: &Value, name: &str, named: bool) -> Result<String, String> {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.reference(reference);
+        }
+        let hint = name;
+        let name = |codegen: &mut Self| match named {
+            true => hint.to_string(),
+            false => codegen.unique(hint),
+        };
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let values: Option<Vec<_>> = values.iter().map(Value::as_str).collect();
+            return match values {
+                Some(values) if !values.is_empty() => {
+                    let name = name(self);
+                    self.emit_enum(schema, &name, &values);
+                    Ok(name)
+                }
+                _ => Ok("::serde_json::Value".into()),
+            };
+        }
+        let types: Vec<_> = match schema.get("type") {
+            Some(Value::String(single)) => vec![single.as_str()],
+            Some(Value::Array(multiple)) => multiple.iter().filter_map(Value::as_str).collect(),
+            _ if schema.get("properties").is_some() => vec!["object"],
+            _ => vec![],
+        };
+        let (nullable, types): (Vec<_>, Vec<_>) = types.into_iter().partition(|t| *t == "null");
+        let rust_type = match types.as_slice() {
+            ["object"] if schema.get("properties").is_some() => {
+                let name = name(self);
+                self.emit_struct(schema, &name)?;
+                name
+            }
+            ["object"] => "::serde_json::Map<String, ::serde_json::Value>".into(),
+            ["array"] => match schema.get("items") {
+                Some(items) if items.is_object() => {
+                    format!(
+                        "Vec<{}>",
+                        self.type_for(items, &format!("{}Item", hint), false)?
+                    )
+                }
+                _ => "Vec<::serde_json::Value>".into(),
+            },
+            ["string"] => "String".into(),
+            ["integer"] => "i64".into(),
+            ["number"] => "f64".into(),
+            ["boolean"] => "bool".into(),
+            [] if !nullable.is_empty() => return Ok("()".into()),
+            _ => return Ok("::serde_json::Value".into()),
+        };
+        Ok(match nullable.is_empty() {
+            true => rust_type,
+            false => format!("Option<{}>", rust_type),
+        })
+    }
+    /// Returns the type for a `$ref`, generating the referenced definition the first time it is used
+    fn reference(&mut self, reference: &str) -> Result<String, String> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(match self.in_progress.contains(name) {
+                true => format!("Box<{}>", name),
+                false => name.clone(),
+            });
+        }
+        let Some(target) = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+        else {
+            return Ok("::serde_json::Value".into());
+        };
+        let definition = reference.rsplit('/').next().unwrap_or_default();
+        let name = self.unique(&pascal_case(definition));
+        self.refs.insert(reference.to_string(), name.clone());
+        self.in_progress.insert(name.clone());
+        let rust_type = self.type_for(target, &name, true)?;
+        self.in_progress.remove(&name);
+        if rust_type != name {
+            self.items
+                .push(format!("pub type {} = {};\n", name, rust_type));
+        }
+        Ok(name)
+    }
+    fn emit_struct(&mut self, schema: &Value, name: &str) -> Result<(), String> {
+        let required: Vec<_> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut fields = String::new();
+        let mut field_names = BTreeSet::new();
+        for (property, property_schema) in schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+        {
+            let hint = format!("{}{}", name, pascal_case(property));
+            let mut rust_type = self.type_for(property_schema, &hint, false)?;
+            let mut field = snake_case(property);
+            while !field_names.insert(field.clone()) {
+                field.push('_');
+            }
+            doc(&mut fields, property_schema, "    ");
+            if field != *property {
+                let _ = writeln!(fields, "    #[serde(rename = {:?})]", property);
+            }
+            if !required.contains(&property.as_str()) {
+                if !rust_type.starts_with("Option<") {
+                    rust_type = format!("Option<{}>", rust_type);
+                }
+                fields
+                    .push_str("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n");
+            }
+            let _ = writeln!(fields, "    pub {}: {},", field, rust_type);
+        }
+        let mut item = String::new();
+        doc(&mut item, schema, "");
+        item.push_str(
+            "#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]\n",
+        );
+        if schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+            item.push_str("#[serde(deny_unknown_fields)]\n");
+        }
+        let _ = write!(item, "pub struct {} {{\n{}}}\n", name, fields);
+        self.items.push(item);
+        Ok(())
+    }
+    fn emit_enum(&mut self, schema: &Value, name: &str, values: &[&str]) {
+        let mut variants = String::new();
+        let mut variant_names = BTreeSet::new();
+        for value in values {
+            let mut variant = pascal_case(value);
+            if variant.is_empty() || variant.starts_with(|c: char| c.is_ascii_digit()) {
+                variant.insert(0, 'V');
+            }
+            while !variant_names.insert(variant.clone()) {
+                variant.push('_');
+            }
+            let _ = writeln!(
+                variants,
+                "    #[serde(rename = {:?})]\n    {},",
+                value, variant
+            );
+        }
+        let mut item = String::new();
+        doc(&mut item, schema, "");
+        item.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ::serde::Serialize, ::serde::Deserialize)]\n");
+        let _ = write!(item, "pub enum {} {{\n{}}}\n", name, variants);
+        self.items.push(item);
+    }
+}
+
+/// Writes a schema's `description` (or `title`) as a doc comment
+fn doc(out: &mut String, schema: &Value, indent: &str) {
+    if let Some(description) = schema
+        .get("description")
+        .or_else(|| schema.get("title"))
+        .and_then(Value::as_str)
+    {
+        for line in description.lines() {
+            let _ = writeln!(out, "{}/// {}", indent, line);
+        }
+    }
+}
+
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if !c.is_ascii_alphanumeric() {
+            words.push(std::mem::take(&mut word));
+            previous_lowercase = false;
+            continue;
+        }
+        if c.is_ascii_uppercase() && previous_lowercase {
+            words.push(std::mem::take(&mut word));
+        }
+        previous_lowercase = c.is_ascii_lowercase() || c.is_ascii_digit();
+        word.push(c);
+    }
+    words.push(word);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+fn pascal_case(name: &str) -> String {
+    words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                })
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = words(name)
+        .iter()
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("_");
+    if snake.is_empty() || snake.starts_with(|c: char| c.is_ascii_digit()) {
+        snake.insert(0, '_');
+    }
+    if KEYWORDS.contains(&snake.as_str()) {
+        snake.push('_');
+    }
+    snake
+}
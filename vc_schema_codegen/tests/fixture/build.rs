@@ -0,0 +1,9 @@
+fn main() {
+    vc_schema_codegen::build(
+        "schemas/example.json".as_ref(),
+        None,
+        "ExampleSubject",
+        "example.rs",
+    )
+    .unwrap();
+}
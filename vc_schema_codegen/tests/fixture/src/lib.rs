@@ -0,0 +1,3 @@
+//! Types generated by `vc_schema_codegen` from a build script, compiled and exercised by its tests
+
+include!(concat!(env!("OUT_DIR"), "/example.rs"));
@@ -0,0 +1,66 @@
+use serde_json::{from_str, from_value, json, to_value, Value};
+use vc_schema_codegen_fixture::{ExampleSubject, ExampleSubjectType};
+use vc_signing::typed::TypedVerifiableCredential;
+use vc_signing::verifiable_credential::SignedSchema;
+use vc_signing::{SignatureKeyPair, VerifiableCredential};
+
+fn subject() -> Value {
+    json!({
+        "id": "example_id",
+        "type": "read-only",
+        "level": 3,
+        "addresses": [{"postCode": "SW1A 1AA"}],
+        "extra": 7
+    })
+}
+
+#[test]
+fn subject_round_trip() {
+    let example: ExampleSubject = from_value(subject()).unwrap();
+    assert_eq!(example.id, "example_id");
+    assert!(matches!(example.type_, ExampleSubjectType::ReadOnly));
+    assert_eq!(example.level, Some(3));
+    assert_eq!(example.addresses.as_ref().unwrap()[0].post_code, "SW1A 1AA");
+    assert_eq!(to_value(&example).unwrap(), subject());
+    assert!(from_value::<ExampleSubject>(json!({"id": "example_id"})).is_err());
+    assert!(from_value::<ExampleSubject>(json!({
+        "id": "example_id",
+        "type": "admin",
+        "addresses": [{"postCode": "SW1A 1AA", "street": "Downing Street"}]
+    }))
+    .is_err());
+}
+
+#[test]
+fn typed_credential() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema = VerifiableCredential::new(
+        from_str(include_str!("../schemas/example.json")).unwrap(),
+        None,
+    )
+    .unwrap()
+    .sign(&private_key)
+    .unwrap();
+    let vc = json!({
+        "@context": ["https://www.w3.org/ns/credentials/v2"],
+        "credentialSchema": {
+            "id": "urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3",
+            "type": "JsonSchema"
+        },
+        "credentialSubject": subject(),
+        "issuer": "urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671",
+        "type": ["VerifiableCredential", "Example"]
+    });
+    let typed = TypedVerifiableCredential::<ExampleSubject>::new(
+        vc,
+        vec![SignedSchema::new(schema, &public_key)],
+    )
+    .unwrap();
+    assert!(matches!(
+        typed.subject().type_,
+        ExampleSubjectType::ReadOnly
+    ));
+}
@@ -0,0 +1,88 @@
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::Command;
+use vc_schema_codegen::generate;
+use vc_signing::{SignatureKeyPair, VerifiableCredential};
+
+fn schema() -> Value {
+    json!({
+        "$id": "urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3",
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "description": "An example schema",
+        "$defs": {
+            "address": {
+                "type": "object",
+                "properties": {"postCode": {"type": "string"}},
+                "required": ["postCode"],
+                "additionalProperties": false
+            }
+        },
+        "properties": {
+            "id": {"description": "id", "type": "string"},
+            "type": {"enum": ["admin", "read-only"]},
+            "level": {"type": ["integer", "null"]},
+            "addresses": {"type": "array", "items": {"$ref": "#/$defs/address"}},
+            "extra": {"anyOf": [{"type": "string"}, {"type": "integer"}]}
+        },
+        "required": ["id", "type"],
+        "title": "example",
+        "type": "object"
+    })
+}
+
+#[test]
+fn generate_types() {
+    let schema_vc = VerifiableCredential::create_schema(schema()).unwrap();
+    let source = generate(
+        &serde_json::to_value(schema_vc).unwrap(),
+        None,
+        "example_subject",
+    )
+    .unwrap();
+    assert!(source.contains("/// An example schema\n"));
+    assert!(source.contains("pub struct ExampleSubject {"));
+    assert!(source.contains("    /// id\n    pub id: String,\n"));
+    assert!(
+        source.contains("    #[serde(rename = \"type\")]\n    pub type_: ExampleSubjectType,\n")
+    );
+    assert!(source.contains("pub enum ExampleSubjectType {"));
+    assert!(source.contains("    #[serde(rename = \"read-only\")]\n    ReadOnly,\n"));
+    assert!(source.contains("    pub level: Option<i64>,\n"));
+    assert!(source.contains("    pub addresses: Option<Vec<Address>>,\n"));
+    assert!(source.contains("    pub extra: Option<::serde_json::Value>,\n"));
+    assert!(source.contains("#[serde(deny_unknown_fields)]\npub struct Address {"));
+    assert!(source.contains("    #[serde(rename = \"postCode\")]\n    pub post_code: String,\n"));
+}
+
+#[test]
+fn signed_schema_key() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = serde_json::to_value(
+        VerifiableCredential::create_schema(schema())
+            .unwrap()
+            .sign(&private_key)
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(generate(&schema_vc, Some(&public_key), "Example").is_ok());
+    let wrong_key = SignatureKeyPair::new().unwrap().public_key;
+    assert!(generate(&schema_vc, Some(&wrong_key), "Example").is_err());
+    assert!(generate(&json!({}), None, "Example").is_err());
+}
+
+#[test]
+fn generated_types_compile() {
+    let status = Command::new(env!("CARGO"))
+        .args(["test", "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixture/Cargo.toml"))
+        .env(
+            "CARGO_TARGET_DIR",
+            Path::new(env!("CARGO_TARGET_TMPDIR")).join("fixture"),
+        )
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
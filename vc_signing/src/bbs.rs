@@ -0,0 +1,676 @@
+use crate::selective_disclosure::{
+    absolute_mandatory_pointers, canonicalize_and_group, decode_proof_value, derive_document,
+    encode_proof_value, generate_hmac_key, hash_nquads, hmac_labels, label_index, new_proof,
+    partition, proof_hash, replace_labels, selects_subject, subject_pointers, unsecured_document,
+    verification_method, verifier_label_map, Cbor, LabelMap,
+};
+use crate::{Proof, VerifiableCredential};
+use bls12_381::hash_to_curve::{ExpandMessageState, ExpandMsgXmd, HashToCurve, InitExpandMessage};
+use bls12_381::{
+    multi_miller_loop, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt, Scalar,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use sha2::Sha256;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Name of the BBS Data Integrity cryptosuite
+pub const BBS_CRYPTOSUITE: &str = "bbs-2023";
+
+/// api_id of the BLS12-381-SHA-256 BBS ciphersuite
+const API_ID: &[u8] = b"BBS_BLS12381G1_XMD:SHA-256_SSWU_RO_H2G_HM2S_";
+/// Length of the output of expand_message when hashing to a scalar
+const EXPAND_LEN: usize = 48;
+const POINT_LEN: usize = 48;
+const SCALAR_LEN: usize = 32;
+/// Prefix of a base proof value, the CBOR tag 0xd95d02
+const BASE_PROOF_HEADER: [u8; 3] = [0xd9, 0x5d, 0x02];
+/// Prefix of a derived proof value, the CBOR tag 0xd95d03
+const DERIVED_PROOF_HEADER: [u8; 3] = [0xd9, 0x5d, 0x03];
+
+fn dst(suffix: &[u8]) -> Vec<u8> {
+    [API_ID, suffix].concat()
+}
+
+fn expand_message(message: &[u8], dst: &[u8]) -> [u8; EXPAND_LEN] {
+    let mut uniform_bytes = [0; EXPAND_LEN];
+    <ExpandMsgXmd<Sha256> as InitExpandMessage>::init_expand(message, dst, EXPAND_LEN)
+        .read_into(&mut uniform_bytes);
+    uniform_bytes
+}
+
+fn hash_to_scalar(message: &[u8], dst: &[u8]) -> Scalar {
+    let mut wide = [0; 64];
+    for (wide, byte) in wide
+        .iter_mut()
+        .zip(expand_message(message, dst).iter().rev())
+    {
+        *wide = *byte;
+    }
+    Scalar::from_bytes_wide(&wide)
+}
+
+fn random_scalar(rng: &SystemRandom) -> Result<Scalar, String> {
+    let mut wide = [0; 64];
+    rng.fill(&mut wide)
+        .map_err(|_| "Error generating random scalar")?;
+    Ok(Scalar::from_bytes_wide(&wide))
+}
+
+fn scalar_to_bytes(scalar: &Scalar) -> [u8; SCALAR_LEN] {
+    let mut bytes = scalar.to_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn scalar_from_bytes(bytes: &[u8]) -> Result<Scalar, String> {
+    let mut bytes: [u8; SCALAR_LEN] = bytes.try_into().map_err(|_| "Invalid scalar length")?;
+    bytes.reverse();
+    Option::from(Scalar::from_bytes(&bytes)).ok_or_else(|| "Invalid scalar".to_string())
+}
+
+fn g1_from_bytes(bytes: &[u8]) -> Result<G1Affine, String> {
+    let bytes: [u8; POINT_LEN] = bytes.try_into().map_err(|_| "Invalid point length")?;
+    Option::<G1Affine>::from(G1Affine::from_compressed(&bytes))
+        .filter(|point| !bool::from(point.is_identity()))
+        .ok_or_else(|| "Invalid point".to_string())
+}
+
+fn public_key_from_bytes(bytes: &[u8]) -> Result<G2Affine, String> {
+    let bytes: [u8; 96] = bytes
+        .try_into()
+        .map_err(|_| "Not a BLS12-381 G2 public key")?;
+    Option::<G2Affine>::from(G2Affine::from_compressed(&bytes))
+        .filter(|point| !bool::from(point.is_identity()))
+        .ok_or_else(|| "Not a BLS12-381 G2 public key".to_string())
+}
+
+/// Creates `count` generators from a seed, as in the BBS `create_generators` operation
+fn create_generators(count: usize, seed: &[u8]) -> Vec<G1Affine> {
+    let seed_dst = dst(b"SIG_GENERATOR_SEED_");
+    let generator_dst = dst(b"SIG_GENERATOR_DST_");
+    let mut v = expand_message(&dst(seed), &seed_dst);
+    (1..=count as u64)
+        .map(|i| {
+            v = expand_message(&[v.as_slice(), &i.to_be_bytes()].concat(), &seed_dst);
+            <G1Projective as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(v, &generator_dst)
+                .into()
+        })
+        .collect()
+}
+
+/// Returns the base point P1 and the generators Q_1, H_1, ..., H_count
+fn generators(count: usize) -> (G1Projective, G1Affine, Vec<G1Affine>) {
+    let p1 = create_generators(1, b"BP_MESSAGE_GENERATOR_SEED")[0];
+    let mut generators = create_generators(count + 1, b"MESSAGE_GENERATOR_SEED");
+    let q1 = generators.remove(0);
+    (p1.into(), q1, generators)
+}
+
+fn calculate_domain(public_key: &[u8], q1: &G1Affine, h: &[G1Affine], header: &[u8]) -> Scalar {
+    let mut input = public_key.to_vec();
+    input.extend_from_slice(&(h.len() as u64).to_be_bytes());
+    input.extend_from_slice(&q1.to_compressed());
+    for generator in h {
+        input.extend_from_slice(&generator.to_compressed());
+    }
+    input.extend_from_slice(API_ID);
+    input.extend_from_slice(&(header.len() as u64).to_be_bytes());
+    input.extend_from_slice(header);
+    hash_to_scalar(&input, &dst(b"H2S_"))
+}
+
+/// Maps messages to scalars with the BBS `map_to_scalar` hash
+fn messages_to_scalars(messages: &[impl AsRef<[u8]>]) -> Vec<Scalar> {
+    let map_dst = dst(b"MAP_MSG_TO_SCALAR_AS_HASH_");
+    messages
+        .iter()
+        .map(|message| hash_to_scalar(message.as_ref(), &map_dst))
+        .collect()
+}
+
+/// Generates a random BBS secret key and its compressed G2 public key
+pub(crate) fn generate_key_pair() -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut key_material = [0; 32];
+    SystemRandom::new()
+        .fill(&mut key_material)
+        .map_err(|_| "Error generating key pair")?;
+    key_gen(&key_material, &[])
+}
+
+/// Derives a BBS secret key and its compressed G2 public key from at least 32 bytes of key material, with the
+/// BBS `KeyGen` operation
+pub fn key_gen(key_material: &[u8], key_info: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if key_material.len() < 32 {
+        return Err("Key material must be at least 32 bytes".into());
+    }
+    let key_info_len = u16::try_from(key_info.len()).map_err(|_| "Key info is too long")?;
+    let secret_key = hash_to_scalar(
+        &[key_material, &key_info_len.to_be_bytes(), key_info].concat(),
+        &dst(b"KEYGEN_DST_"),
+    );
+    if secret_key == Scalar::zero() {
+        return Err("Error generating key pair".into());
+    }
+    let public_key = G2Affine::from(G2Affine::generator() * secret_key);
+    Ok((
+        scalar_to_bytes(&secret_key).to_vec(),
+        public_key.to_compressed().to_vec(),
+    ))
+}
+
+/// Returns the compressed G2 public key of a secret key, with the BBS `SkToPk` operation
+fn sk_to_pk(secret_key: &[u8]) -> Result<Vec<u8>, String> {
+    let secret_key = scalar_from_bytes(secret_key)?;
+    Ok(G2Affine::from(G2Affine::generator() * secret_key)
+        .to_compressed()
+        .to_vec())
+}
+
+/// Signs messages with the BBS `Sign` operation
+fn sign(
+    secret_key: &[u8],
+    header: &[u8],
+    messages: &[Scalar],
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let secret_key = scalar_from_bytes(secret_key)?;
+    let public_key = G2Affine::from(G2Affine::generator() * secret_key).to_compressed();
+    let (p1, q1, h) = generators(messages.len());
+    let domain = calculate_domain(&public_key, &q1, &h, header);
+    let mut input = scalar_to_bytes(&secret_key).to_vec();
+    for message in messages {
+        input.extend_from_slice(&scalar_to_bytes(message));
+    }
+    input.extend_from_slice(&scalar_to_bytes(&domain));
+    let e = hash_to_scalar(&input, &dst(b"H2S_"));
+    let b = h
+        .iter()
+        .zip(messages)
+        .fold(p1 + q1 * domain, |b, (h, message)| b + h * message);
+    let a = G1Affine::from(
+        b * Option::<Scalar>::from((secret_key + e).invert()).ok_or("Invalid secret key")?,
+    );
+    Ok((
+        [a.to_compressed().as_slice(), &scalar_to_bytes(&e)].concat(),
+        public_key.to_vec(),
+    ))
+}
+
+/// Signs messages with the BBS `Sign` operation of the BLS12-381-SHA-256 ciphersuite, returning the signature
+pub fn sign_messages(
+    secret_key: &[u8],
+    header: &[u8],
+    messages: &[Vec<u8>],
+) -> Result<Vec<u8>, String> {
+    sign(secret_key, header, &messages_to_scalars(messages)).map(|(signature, _)| signature)
+}
+
+/// Verifies a signature over messages with the BBS `Verify` operation of the BLS12-381-SHA-256 ciphersuite
+pub fn verify_messages(
+    public_key: &[u8],
+    signature: &[u8],
+    header: &[u8],
+    messages: &[Vec<u8>],
+) -> bool {
+    verify(
+        public_key,
+        signature,
+        header,
+        &messages_to_scalars(messages),
+    )
+}
+
+/// Checks e(left, right) * e(left_2, -P2) is the identity
+fn pairing_check(left: &G1Affine, right: &G2Affine, left_2: &G1Affine) -> bool {
+    multi_miller_loop(&[
+        (left, &G2Prepared::from(*right)),
+        (left_2, &G2Prepared::from(-G2Affine::generator())),
+    ])
+    .final_exponentiation()
+        == Gt::identity()
+}
+
+/// Verifies a signature with the BBS `Verify` operation
+fn verify(public_key: &[u8], signature: &[u8], header: &[u8], messages: &[Scalar]) -> bool {
+    let (Ok(w), Some(a), Some(e)) = (
+        public_key_from_bytes(public_key),
+        signature
+            .get(..POINT_LEN)
+            .and_then(|a| g1_from_bytes(a).ok()),
+        signature
+            .get(POINT_LEN..)
+            .and_then(|e| scalar_from_bytes(e).ok()),
+    ) else {
+        return false;
+    };
+    let (p1, q1, h) = generators(messages.len());
+    let domain = calculate_domain(public_key, &q1, &h, header);
+    let b = h
+        .iter()
+        .zip(messages)
+        .fold(p1 + q1 * domain, |b, (h, message)| b + h * message);
+    pairing_check(
+        &a,
+        &G2Affine::from(w + G2Projective::generator() * e),
+        &G1Affine::from(b),
+    )
+}
+
+/// Calculates the challenge of a proof, as in the BBS `ProofChallengeCalculate` operation
+fn challenge(
+    points: [&G1Affine; 5],
+    domain: &Scalar,
+    disclosed: &[(usize, Scalar)],
+    presentation_header: &[u8],
+) -> Scalar {
+    let mut input = (disclosed.len() as u64).to_be_bytes().to_vec();
+    for (index, message) in disclosed {
+        input.extend_from_slice(&(*index as u64).to_be_bytes());
+        input.extend_from_slice(&scalar_to_bytes(message));
+    }
+    for point in points {
+        input.extend_from_slice(&point.to_compressed());
+    }
+    input.extend_from_slice(&scalar_to_bytes(domain));
+    input.extend_from_slice(&(presentation_header.len() as u64).to_be_bytes());
+    input.extend_from_slice(presentation_header);
+    hash_to_scalar(&input, &dst(b"H2S_"))
+}
+
+/// Proves knowledge of a signature, disclosing only the messages at the given indexes, with the BBS `ProofGen` operation
+fn proof_gen(
+    public_key: &[u8],
+    signature: &[u8],
+    header: &[u8],
+    presentation_header: &[u8],
+    messages: &[Scalar],
+    disclosed_indexes: &[usize],
+) -> Result<Vec<u8>, String> {
+    let a = g1_from_bytes(signature.get(..POINT_LEN).ok_or("Invalid BBS signature")?)?;
+    let e = scalar_from_bytes(&signature[POINT_LEN..])?;
+    let undisclosed: Vec<usize> = (0..messages.len())
+        .filter(|index| !disclosed_indexes.contains(index))
+        .collect();
+    let rng = SystemRandom::new();
+    let [r1, r2, e_tilde, r1_tilde, r3_tilde] = [(); 5].map(|_| random_scalar(&rng));
+    let (r1, r2, e_tilde, r1_tilde, r3_tilde) = (r1?, r2?, e_tilde?, r1_tilde?, r3_tilde?);
+    let m_tilde = undisclosed
+        .iter()
+        .map(|_| random_scalar(&rng))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let (p1, q1, h) = generators(messages.len());
+    let domain = calculate_domain(public_key, &q1, &h, header);
+    let b = h
+        .iter()
+        .zip(messages)
+        .fold(p1 + q1 * domain, |b, (h, message)| b + h * message);
+    let d = G1Affine::from(b * r2);
+    let a_bar = G1Affine::from(a * (r1 * r2));
+    let b_bar = G1Affine::from(d * r1 - a_bar * e);
+    let t1 = G1Affine::from(a_bar * e_tilde + d * r1_tilde);
+    let t2 = G1Affine::from(
+        undisclosed
+            .iter()
+            .zip(&m_tilde)
+            .fold(d * r3_tilde, |t2, (index, m_tilde)| {
+                t2 + h[*index] * m_tilde
+            }),
+    );
+    let disclosed: Vec<(usize, Scalar)> = disclosed_indexes
+        .iter()
+        .map(|index| (*index, messages[*index]))
+        .collect();
+    let c = challenge(
+        [&a_bar, &b_bar, &d, &t1, &t2],
+        &domain,
+        &disclosed,
+        presentation_header,
+    );
+
+    let r3 = Option::<Scalar>::from(r2.invert()).ok_or("Error generating proof")?;
+    let mut proof = [a_bar, b_bar, d]
+        .iter()
+        .flat_map(|point| point.to_compressed())
+        .collect::<Vec<u8>>();
+    for scalar in [e_tilde + e * c, r1_tilde - r1 * c, r3_tilde - r3 * c]
+        .into_iter()
+        .chain(
+            undisclosed
+                .iter()
+                .zip(&m_tilde)
+                .map(|(index, m_tilde)| m_tilde + messages[*index] * c),
+        )
+        .chain([c])
+    {
+        proof.extend_from_slice(&scalar_to_bytes(&scalar));
+    }
+    Ok(proof)
+}
+
+/// Verifies a proof of knowledge of a signature over the disclosed messages, with the BBS `ProofVerify` operation
+fn proof_verify(
+    public_key: &[u8],
+    proof: &[u8],
+    header: &[u8],
+    presentation_header: &[u8],
+    disclosed: &[(usize, Scalar)],
+) -> Result<bool, String> {
+    let w = public_key_from_bytes(public_key)?;
+    let (points, scalars) = proof
+        .split_at_checked(3 * POINT_LEN)
+        .ok_or("Invalid BBS proof length")?;
+    if scalars.len() % SCALAR_LEN != 0 || scalars.len() < 4 * SCALAR_LEN {
+        return Err("Invalid BBS proof length".into());
+    }
+    let [a_bar, b_bar, d] = [0, 1, 2].map(|i| g1_from_bytes(&points[i * POINT_LEN..][..POINT_LEN]));
+    let (a_bar, b_bar, d) = (a_bar?, b_bar?, d?);
+    let scalars = scalars
+        .chunks(SCALAR_LEN)
+        .map(scalar_from_bytes)
+        .collect::<Result<Vec<_>, String>>()?;
+    let (e_hat, r1_hat, r3_hat, m_hat, c) = (
+        scalars[0],
+        scalars[1],
+        scalars[2],
+        &scalars[3..scalars.len() - 1],
+        scalars[scalars.len() - 1],
+    );
+    let count = disclosed.len() + m_hat.len();
+    if disclosed.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+        || disclosed.last().is_some_and(|(index, _)| *index >= count)
+    {
+        return Err("Invalid disclosed indexes".into());
+    }
+
+    let (p1, q1, h) = generators(count);
+    let domain = calculate_domain(public_key, &q1, &h, header);
+    let t1 = G1Affine::from(b_bar * c + a_bar * e_hat + d * r1_hat);
+    let b_v = disclosed
+        .iter()
+        .fold(p1 + q1 * domain, |b_v, (index, message)| {
+            b_v + h[*index] * message
+        });
+    let t2 = G1Affine::from(
+        (0..count)
+            .filter(|index| !disclosed.iter().any(|(disclosed, _)| disclosed == index))
+            .zip(m_hat)
+            .fold(b_v * c + d * r3_hat, |t2, (index, m_hat)| {
+                t2 + h[index] * m_hat
+            }),
+    );
+    if challenge(
+        [&a_bar, &b_bar, &d, &t1, &t2],
+        &domain,
+        disclosed,
+        presentation_header,
+    ) != c
+    {
+        return Ok(false);
+    }
+    Ok(pairing_check(&a_bar, &w, &b_bar))
+}
+
+/// Returns the bbs header, the hashes of the proof options and of the mandatory N-Quads
+fn bbs_header(
+    proof: &Proof,
+    document: &Value,
+    mandatory: &[impl AsRef<str>],
+) -> Result<Vec<u8>, String> {
+    Ok([proof_hash(proof, document)?, hash_nquads(mandatory)].concat())
+}
+
+fn messages(nquads: &[impl AsRef<str>]) -> Vec<Scalar> {
+    messages_to_scalars(
+        &nquads
+            .iter()
+            .map(|nquad| nquad.as_ref().as_bytes())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Labels blank nodes b0, b1, ... in the order of the HMACs of their canonical labels, so a selection's labels
+/// don't reveal where its blank nodes are in the canonical order
+fn shuffled_labels(hmac_key: &[u8], canonical: LabelMap) -> LabelMap {
+    let labels = hmac_labels(hmac_key, canonical);
+    let mut hmacs: Vec<&String> = labels.values().collect();
+    hmacs.sort();
+    labels
+        .iter()
+        .map(|(blank, hmac)| {
+            let index = hmacs.binary_search(&hmac).unwrap_or_default();
+            (blank.clone(), format!("b{}", index))
+        })
+        .collect()
+}
+
+/// Compresses the label map of a derived proof to the indexes of canonical labels and the labels they map to
+fn compress_label_map(label_map: &LabelMap) -> Result<Cbor, String> {
+    let mut entries = label_map
+        .iter()
+        .map(|(canonical, label)| Ok((label_index(canonical, "c14n")?, label_index(label, "b")?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    entries.sort();
+    Ok(Cbor::Map(
+        entries
+            .into_iter()
+            .map(|(index, label)| (Cbor::Unsigned(index), Cbor::Unsigned(label)))
+            .collect(),
+    ))
+}
+
+fn decompress_label_map(label_map: Cbor) -> Result<LabelMap, String> {
+    label_map
+        .into_map()?
+        .into_iter()
+        .map(|(index, label)| {
+            Ok((
+                format!("c14n{}", index.into_unsigned()?),
+                format!("b{}", label.into_unsigned()?),
+            ))
+        })
+        .collect()
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiableCredential {
+    /// Signs a VerifiableCredential with a bbs-2023 base proof, using the given BLS12-381 private key
+    ///
+    /// Claims outside the credentialSubject, and subject claims named by a JSON pointer in `mandatory_pointers`,
+    /// are disclosed in every derived proof. Each other N-Quad of the credential is signed as its own BBS message,
+    /// so holders can choose to disclose it
+    pub fn sign_bbs(
+        mut self,
+        private_key: &[u8],
+        mandatory_pointers: Vec<String>,
+    ) -> Result<Self, String> {
+        self.proof = None;
+        let document = unsecured_document(&self)?;
+        let mandatory_pointers = absolute_mandatory_pointers(&document, &mandatory_pointers);
+        let hmac_key = generate_hmac_key()?;
+        let canonicalized = canonicalize_and_group(
+            &document,
+            |canonical| Ok(shuffled_labels(&hmac_key, canonical)),
+            &[&mandatory_pointers],
+        )?;
+        let group = &canonicalized.groups[0];
+        let (mandatory, non_mandatory) = partition(canonicalized.nquads.clone(), &group.matching)?;
+
+        let public_key = sk_to_pk(private_key)?;
+        let mut proof = new_proof(BBS_CRYPTOSUITE, verification_method(&public_key)?);
+        let header = bbs_header(&proof, &document, &mandatory)?;
+        let (signature, public_key) = sign(private_key, &header, &messages(&non_mandatory))?;
+        proof.proof_value = encode_proof_value(
+            BBS_CRYPTOSUITE,
+            BASE_PROOF_HEADER,
+            vec![
+                Cbor::Bytes(signature),
+                Cbor::Bytes(header),
+                Cbor::Bytes(public_key),
+                Cbor::Bytes(hmac_key),
+                Cbor::Array(mandatory_pointers.into_iter().map(Cbor::Text).collect()),
+            ],
+        );
+        self.proof = Some(proof);
+        Ok(self)
+    }
+    /// Derives a credential from one with a bbs-2023 base proof, disclosing only its mandatory claims and the
+    /// subject claims named by a JSON pointer in `selective_pointers`
+    ///
+    /// Each derived proof is freshly randomised, so presentations of the same credential cannot be linked by
+    /// their proofs. The presentation header, such as a verifier's nonce, is bound to the proof
+    pub fn derive_bbs(
+        &self,
+        selective_pointers: Vec<String>,
+        presentation_header: Vec<u8>,
+    ) -> Result<Self, String> {
+        let base_proof = self.proof.as_ref().ok_or("VC is unsigned")?;
+        let (BASE_PROOF_HEADER, components) = decode_proof_value(base_proof)? else {
+            return Err("Not a bbs-2023 base proof".into());
+        };
+        let Ok([signature, header, public_key, hmac_key, mandatory_pointers]) =
+            <[Cbor; 5]>::try_from(components)
+        else {
+            return Err("Invalid bbs-2023 base proof".into());
+        };
+        let hmac_key = hmac_key.into_bytes()?;
+        let mandatory_pointers = mandatory_pointers.into_texts()?;
+        let selective_pointers = subject_pointers(&selective_pointers);
+        let combined_pointers = [mandatory_pointers.as_slice(), &selective_pointers].concat();
+        if !selects_subject(&combined_pointers) {
+            return Err("A derived credential must disclose a subject claim".into());
+        }
+
+        let document = unsecured_document(self)?;
+        let canonicalized = canonicalize_and_group(
+            &document,
+            |canonical| Ok(shuffled_labels(&hmac_key, canonical)),
+            &[&mandatory_pointers, &selective_pointers, &combined_pointers],
+        )?;
+        let [mandatory, selective, combined] = &canonicalized.groups[..] else {
+            return Err("Error grouping N-Quads".into());
+        };
+        let non_mandatory: Vec<&String> = mandatory
+            .non_matching
+            .iter()
+            .map(|index| &canonicalized.nquads[*index])
+            .collect();
+        let selective_indexes: Vec<usize> = mandatory
+            .non_matching
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| selective.matching.contains(index))
+            .map(|(position, _)| position)
+            .collect();
+        let mandatory_indexes = combined
+            .matching
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| mandatory.matching.contains(index))
+            .map(|(position, _)| Cbor::Unsigned(position as u64))
+            .collect();
+        let bbs_proof = proof_gen(
+            &public_key.into_bytes()?,
+            &signature.into_bytes()?,
+            &header.into_bytes()?,
+            &presentation_header,
+            &messages(&non_mandatory),
+            &selective_indexes,
+        )?;
+        let label_map = verifier_label_map(&combined.quads, &canonicalized.labels)?;
+
+        let proof = Proof {
+            proof_value: encode_proof_value(
+                BBS_CRYPTOSUITE,
+                DERIVED_PROOF_HEADER,
+                vec![
+                    Cbor::Bytes(bbs_proof),
+                    compress_label_map(&label_map)?,
+                    Cbor::Array(mandatory_indexes),
+                    Cbor::Array(
+                        selective_indexes
+                            .into_iter()
+                            .map(|index| Cbor::Unsigned(index as u64))
+                            .collect(),
+                    ),
+                    Cbor::Bytes(presentation_header),
+                ],
+            ),
+            ..base_proof.clone()
+        };
+        derive_document(&document, &combined_pointers, proof)
+    }
+    /// Verifies a bbs-2023 base or derived proof was made with the given BLS12-381 public key
+    ///
+    /// A derived proof must have been made with the expected presentation header, such as the nonce the verifier
+    /// gave the holder, so it can't be replayed to another verifier
+    pub fn verify_bbs(
+        &self,
+        public_key: &[u8],
+        expected_presentation_header: &[u8],
+    ) -> Result<(), String> {
+        let proof = self.proof.as_ref().ok_or("VC is unsigned")?;
+        let (header, components) = decode_proof_value(proof)?;
+        let document = unsecured_document(self)?;
+        let verified = match (header, <[Cbor; 5]>::try_from(components)) {
+            (BASE_PROOF_HEADER, Ok([signature, _, _, hmac_key, mandatory_pointers])) => {
+                if !expected_presentation_header.is_empty() {
+                    return Err("Base proof has no presentation header".into());
+                }
+                let hmac_key = hmac_key.into_bytes()?;
+                let canonicalized = canonicalize_and_group(
+                    &document,
+                    |canonical| Ok(shuffled_labels(&hmac_key, canonical)),
+                    &[&mandatory_pointers.into_texts()?],
+                )?;
+                let mandatory_indexes = &canonicalized.groups[0].matching;
+                let (mandatory, non_mandatory) =
+                    partition(canonicalized.nquads, mandatory_indexes)?;
+                verify(
+                    public_key,
+                    &signature.into_bytes()?,
+                    &bbs_header(proof, &document, &mandatory)?,
+                    &messages(&non_mandatory),
+                )
+            }
+            (
+                DERIVED_PROOF_HEADER,
+                Ok(
+                    [bbs_proof, label_map, mandatory_indexes, selective_indexes, presentation_header],
+                ),
+            ) => {
+                if presentation_header.into_bytes()? != expected_presentation_header {
+                    return Err("Derived proof was made for another presentation header".into());
+                }
+                let label_map = decompress_label_map(label_map)?;
+                let canonicalized = canonicalize_and_group(
+                    &document,
+                    |canonical| replace_labels(&label_map, canonical),
+                    &[],
+                )?;
+                let (mandatory, selective) =
+                    partition(canonicalized.nquads, &mandatory_indexes.into_indexes()?)?;
+                let selective_indexes = selective_indexes.into_indexes()?;
+                if selective_indexes.len() != selective.len() {
+                    return Err("Derived proof does not match the credential".into());
+                }
+                proof_verify(
+                    public_key,
+                    &bbs_proof.into_bytes()?,
+                    &bbs_header(proof, &document, &mandatory)?,
+                    expected_presentation_header,
+                    &selective_indexes
+                        .into_iter()
+                        .zip(messages(&selective))
+                        .collect::<Vec<_>>(),
+                )?
+            }
+            _ => return Err("Invalid bbs-2023 proof".into()),
+        };
+        match verified {
+            true => Ok(()),
+            false => Err("Failed to verify".into()),
+        }
+    }
+}
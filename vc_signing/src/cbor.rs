@@ -1,40 +1,115 @@
+use crate::format::{read_envelope, write_envelope, EnvelopeKind};
 use crate::{VerifiableCredential, VerifiablePresentation};
+use ciborium::value::Value as Cbor;
 use serde::{de::DeserializeOwned, Serialize};
 
+/// Self-described CBOR tag, identifying a document as CBOR
+const SELF_DESCRIBED_TAG: u64 = 55799;
+/// Version of the CBOR encoding written in the envelope of serialized documents
+const CBOR_VERSION: u8 = 1;
+
+/// Encodes a value as RFC 8949 core deterministic cbor, tagged as self-described CBOR
+///
+/// Integers, floats and lengths use their shortest form, and map keys are sorted by their encoded bytes,
+/// so the same value always encodes to the same bytes
+pub(crate) fn to_deterministic_cbor(
+    value: &impl Serialize,
+) -> Result<Vec<u8>, ciborium::ser::Error<std::io::Error>> {
+    write_deterministic(Cbor::Tag(
+        SELF_DESCRIBED_TAG,
+        Box::new(Cbor::serialized(value).map_err(|e| ciborium::ser::Error::Value(e.to_string()))?),
+    ))
+}
+
+/// Encodes a cbor value as RFC 8949 core deterministic cbor
+pub(crate) fn write_deterministic(
+    value: Cbor,
+) -> Result<Vec<u8>, ciborium::ser::Error<std::io::Error>> {
+    let mut buf = Vec::new();
+    ciborium::into_writer(&sort_keys(value)?, &mut buf)?;
+    Ok(buf)
+}
+
+fn sort_keys(value: Cbor) -> Result<Cbor, ciborium::ser::Error<std::io::Error>> {
+    Ok(match value {
+        Cbor::Map(entries) => {
+            let mut entries = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = sort_keys(key)?;
+                    let mut encoded = Vec::new();
+                    ciborium::into_writer(&key, &mut encoded)?;
+                    Ok((encoded, key, sort_keys(value)?))
+                })
+                .collect::<Result<Vec<_>, ciborium::ser::Error<std::io::Error>>>()?;
+            entries.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+            Cbor::Map(
+                entries
+                    .into_iter()
+                    .map(|(_, key, value)| (key, value))
+                    .collect(),
+            )
+        }
+        Cbor::Array(array) => {
+            Cbor::Array(array.into_iter().map(sort_keys).collect::<Result<_, _>>()?)
+        }
+        Cbor::Tag(tag, value) => Cbor::Tag(tag, Box::new(sort_keys(*value)?)),
+        value => value,
+    })
+}
+
 impl VerifiablePresentation {
-    /// Serializes a VerifiablePresentation structure into cbor
+    /// Serializes a VerifiablePresentation structure into deterministic cbor in a versioned envelope
     pub fn serialize_cbor(&self) -> Result<Vec<u8>, String>
     where
         Self: Serialize,
     {
-        let mut buf = Vec::new();
-        ciborium::into_writer(self, &mut buf).map_err(|e| e.to_string())?;
-        Ok(buf)
+        Ok(write_envelope(
+            EnvelopeKind::PresentationCbor,
+            CBOR_VERSION,
+            to_deterministic_cbor(self).map_err(|e| e.to_string())?,
+        ))
     }
-    /// Deserializes cbor into a VerifiablePresentation structure
+    /// Deserializes cbor, with or without a versioned envelope, into a VerifiablePresentation structure
     pub fn deserialize_cbor(reader: Vec<u8>) -> Result<Self, String>
     where
         Self: DeserializeOwned + Sized,
     {
-        ciborium::from_reader(reader.as_slice()).map_err(|e| e.to_string())
+        let reader = read_envelope(&reader, EnvelopeKind::PresentationCbor, CBOR_VERSION)?;
+        ciborium::from_reader(reader.as_ref()).map_err(|e| e.to_string())
+    }
+    /// Verifies a cbor VerifiablePresentation was signed by the owner of the given public key, returning it decoded
+    pub fn verify_cbor(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vp = Self::deserialize_cbor(reader)?;
+        vp.verify(public_key)?;
+        Ok(vp)
     }
 }
 
 impl VerifiableCredential {
-    /// Serializes a VerifiableCredential structure into cbor
+    /// Serializes a VerifiableCredential structure into deterministic cbor in a versioned envelope
     pub fn serialize_cbor(&self) -> Result<Vec<u8>, ciborium::ser::Error<std::io::Error>>
     where
         Self: Serialize,
     {
-        let mut buf = Vec::new();
-        ciborium::into_writer(self, &mut buf)?;
-        Ok(buf)
+        Ok(write_envelope(
+            EnvelopeKind::CredentialCbor,
+            CBOR_VERSION,
+            to_deterministic_cbor(self)?,
+        ))
     }
-    /// Deserializes cbor into a VerifiableCredential structure
+    /// Deserializes cbor, with or without a versioned envelope, into a VerifiableCredential structure
     pub fn deserialize_cbor(reader: Vec<u8>) -> Result<Self, String>
     where
         Self: DeserializeOwned + Sized,
     {
-        ciborium::from_reader(reader.as_slice()).map_err(|e| e.to_string())
+        let reader = read_envelope(&reader, EnvelopeKind::CredentialCbor, CBOR_VERSION)?;
+        ciborium::from_reader(reader.as_ref()).map_err(|e| e.to_string())
+    }
+    /// Verifies a cbor VerifiableCredential was signed by the owner of the given public key, returning it decoded
+    pub fn verify_cbor(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vc = Self::deserialize_cbor(reader)?;
+        vc.verify(public_key)?;
+        Ok(vc)
     }
 }
@@ -0,0 +1,482 @@
+use crate::cbor::write_deterministic;
+use crate::format::{read_envelope, write_envelope, EnvelopeKind};
+use crate::{VerifiableCredential, VerifiablePresentation};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat};
+use ciborium::value::Value as Cbor;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Private CBOR tag ("VCLD") marking a document compressed in the style of CBOR-LD
+///
+/// Term ids come from this crate's own dictionaries rather than the CBOR-LD spec's per-context assignment,
+/// so the registered CBOR-LD tag 0xcb1d would have conforming decoders read the wrong terms
+const CBOR_LD_TAG: u64 = 0x5643_4c44;
+/// Version of the CBOR-LD encoding written in the envelope of serialized documents
+const CBOR_LD_VERSION: u8 = 1;
+/// Entry of a document whose payload is uncompressed, private to the tag above like the entry below
+const UNCOMPRESSED: u64 = 0;
+/// Entry of a document compressed with the dictionaries below
+const COMPRESSED: u64 = 1;
+/// CBOR tag of a date as seconds since the epoch
+const EPOCH_TAG: u64 = 1;
+/// CBOR tag of a date as seconds and nanoseconds since the epoch
+const EXTENDED_TIME_TAG: u64 = 1001;
+/// CBOR tag of a binary UUID
+const UUID_TAG: u64 = 37;
+/// Term ids are even, with the next odd id used when the term's value is an array
+const FIRST_TERM_ID: u64 = 100;
+
+const KEYWORDS: [(&str, u64); 26] = [
+    ("@context", 0),
+    ("@type", 2),
+    ("@id", 4),
+    ("@value", 6),
+    ("@direction", 8),
+    ("@graph", 10),
+    ("@included", 12),
+    ("@index", 14),
+    ("@json", 16),
+    ("@language", 18),
+    ("@list", 20),
+    ("@nest", 22),
+    ("@reverse", 24),
+    ("@base", 26),
+    ("@container", 28),
+    ("@default", 30),
+    ("@embed", 32),
+    ("@explicit", 34),
+    ("@none", 36),
+    ("@omitDefault", 38),
+    ("@prefix", 40),
+    ("@preserve", 42),
+    ("@protected", 44),
+    ("@requireAll", 46),
+    ("@set", 48),
+    ("@version", 50),
+];
+
+const DATA_INTEGRITY_TERMS: &[&str] = &[
+    "DataIntegrityProof",
+    "assertionMethod",
+    "authentication",
+    "capabilityDelegation",
+    "capabilityInvocation",
+    "challenge",
+    "created",
+    "cryptosuite",
+    "domain",
+    "expires",
+    "keyAgreement",
+    "nonce",
+    "previousProof",
+    "proof",
+    "proofPurpose",
+    "proofValue",
+    "verificationMethod",
+];
+
+/// Known contexts, with this crate's code for them and the terms they define
+///
+/// Codes and term lists are part of the encoding, so changing them needs a new entry
+const CONTEXTS: &[(&str, u64, &[&str])] = &[
+    (
+        "https://www.w3.org/2018/credentials/v1",
+        0x11,
+        &[
+            "VerifiableCredential",
+            "VerifiablePresentation",
+            "credentialSchema",
+            "credentialStatus",
+            "credentialSubject",
+            "evidence",
+            "expirationDate",
+            "holder",
+            "id",
+            "issuanceDate",
+            "issued",
+            "issuer",
+            "refreshService",
+            "termsOfUse",
+            "type",
+            "validFrom",
+            "validUntil",
+            "verifiableCredential",
+        ],
+    ),
+    (
+        "https://www.w3.org/ns/did/v1",
+        0x12,
+        &[
+            "alsoKnownAs",
+            "assertionMethod",
+            "authentication",
+            "capabilityDelegation",
+            "capabilityInvocation",
+            "controller",
+            "id",
+            "keyAgreement",
+            "service",
+            "serviceEndpoint",
+            "type",
+            "verificationMethod",
+        ],
+    ),
+    (
+        "https://www.w3.org/ns/credentials/v2",
+        0x21,
+        &[
+            "BitstringStatusListCredential",
+            "BitstringStatusListEntry",
+            "DataIntegrityProof",
+            "EnvelopedVerifiableCredential",
+            "EnvelopedVerifiablePresentation",
+            "JsonSchema",
+            "JsonSchemaCredential",
+            "VerifiableCredential",
+            "VerifiablePresentation",
+            "confidenceMethod",
+            "credentialSchema",
+            "credentialStatus",
+            "credentialSubject",
+            "description",
+            "digestMultibase",
+            "digestSRI",
+            "evidence",
+            "holder",
+            "id",
+            "issuer",
+            "jsonSchema",
+            "name",
+            "proof",
+            "refreshService",
+            "relatedResource",
+            "renderMethod",
+            "statusListCredential",
+            "statusListIndex",
+            "statusMessage",
+            "statusPurpose",
+            "statusReference",
+            "statusSize",
+            "termsOfUse",
+            "type",
+            "validFrom",
+            "validUntil",
+            "verifiableCredential",
+        ],
+    ),
+    (
+        "https://w3id.org/security/data-integrity/v1",
+        0x30,
+        DATA_INTEGRITY_TERMS,
+    ),
+    (
+        "https://w3id.org/security/data-integrity/v2",
+        0x31,
+        DATA_INTEGRITY_TERMS,
+    ),
+    (
+        "https://w3id.org/security/multikey/v1",
+        0x32,
+        &[
+            "Multikey",
+            "controller",
+            "expires",
+            "id",
+            "publicKeyMultibase",
+            "revoked",
+            "secretKeyMultibase",
+            "type",
+        ],
+    ),
+];
+
+/// Term ids for the contexts of a document
+struct Dictionary {
+    ids: HashMap<&'static str, u64>,
+    terms: HashMap<u64, &'static str>,
+}
+
+impl Dictionary {
+    fn new(contexts: &[Value]) -> Self {
+        let mut terms: BTreeSet<_> = BTreeSet::new();
+        for context in contexts.iter().filter_map(Value::as_str) {
+            if let Some((_, _, context_terms)) = CONTEXTS.iter().find(|(url, ..)| *url == context) {
+                terms.extend(context_terms.iter().copied());
+            }
+        }
+        let ids: HashMap<_, _> = KEYWORDS
+            .into_iter()
+            .chain(terms.into_iter().zip((FIRST_TERM_ID..).step_by(2)))
+            .collect();
+        let terms = ids.iter().map(|(term, id)| (*id, *term)).collect();
+        Self { ids, terms }
+    }
+}
+
+fn contexts(document: &Value) -> Vec<Value> {
+    match document.get("@context") {
+        Some(Value::Array(contexts)) => contexts.clone(),
+        Some(context) => vec![context.clone()],
+        None => vec![],
+    }
+}
+
+fn compress(value: &Value, key: &str, dictionary: &Dictionary) -> Result<Cbor, String> {
+    Ok(match value {
+        Value::Null => Cbor::Null,
+        Value::Bool(bool) => Cbor::Bool(*bool),
+        Value::Number(_) if ["@context", "@type", "type"].contains(&key) => {
+            return Err(format!("Numeric {} values can't be compressed", key))
+        }
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(integer), _) => Cbor::Integer(integer.into()),
+            (None, Some(integer)) => Cbor::Integer(integer.into()),
+            (None, None) => Cbor::Float(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(string) => compress_string(string, key, dictionary),
+        Value::Array(array) => Cbor::Array(
+            array
+                .iter()
+                .map(|value| compress(value, key, dictionary))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(object) => Cbor::Map(
+            object
+                .iter()
+                .map(|(key, value)| {
+                    let compressed_key = match dictionary.ids.get(key.as_str()) {
+                        Some(id) => Cbor::Integer((id + value.is_array() as u64).into()),
+                        None => Cbor::Text(key.clone()),
+                    };
+                    Ok((compressed_key, compress(value, key, dictionary)?))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+/// Compresses a string to a shorter CBOR value where it decompresses to the same string
+fn compress_string(string: &str, key: &str, dictionary: &Dictionary) -> Cbor {
+    let registered = match key {
+        "@context" => CONTEXTS
+            .iter()
+            .find(|(url, ..)| *url == string)
+            .map(|(_, code, _)| *code),
+        "type" | "@type" => dictionary.ids.get(string).copied(),
+        _ => None,
+    };
+    if let Some(id) = registered {
+        return Cbor::Integer(id.into());
+    }
+    if key == "proofValue" {
+        if let Ok(bytes) = BASE64_STANDARD.decode(string) {
+            if BASE64_STANDARD.encode(&bytes) == string {
+                return Cbor::Bytes(bytes);
+            }
+        }
+    }
+    if let Some(uuid) = string
+        .strip_prefix("urn:uuid:")
+        .and_then(|uuid| Uuid::try_parse(uuid).ok())
+    {
+        if format!("urn:uuid:{}", uuid) == string {
+            return Cbor::Tag(UUID_TAG, Box::new(Cbor::Bytes(uuid.as_bytes().to_vec())));
+        }
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(string) {
+        if date.to_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true) == string {
+            let seconds = Cbor::Integer(date.timestamp().into());
+            return match date.timestamp_subsec_nanos() {
+                0 => Cbor::Tag(EPOCH_TAG, Box::new(seconds)),
+                nanos => Cbor::Tag(
+                    EXTENDED_TIME_TAG,
+                    Box::new(Cbor::Map(vec![
+                        (Cbor::Integer(1.into()), seconds),
+                        (Cbor::Integer((-9).into()), Cbor::Integer(nanos.into())),
+                    ])),
+                ),
+            };
+        }
+    }
+    Cbor::Text(string.to_string())
+}
+
+fn decompress(value: Cbor, key: &str, dictionary: &Dictionary) -> Result<Value, String> {
+    Ok(match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(bool) => Value::Bool(bool),
+        Cbor::Integer(integer) if key == "@context" => {
+            let code = u64::try_from(integer).map_err(|e| e.to_string())?;
+            let (url, ..) = CONTEXTS
+                .iter()
+                .find(|(_, context_code, _)| *context_code == code)
+                .ok_or_else(|| format!("Unknown context code {}", code))?;
+            Value::String(url.to_string())
+        }
+        Cbor::Integer(integer) if key == "type" || key == "@type" => {
+            let id = u64::try_from(integer).map_err(|e| e.to_string())?;
+            Value::String(term(dictionary, id)?.to_string())
+        }
+        Cbor::Integer(integer) => match i64::try_from(integer) {
+            Ok(integer) => Value::from(integer),
+            Err(_) => Value::from(u64::try_from(integer).map_err(|e| e.to_string())?),
+        },
+        Cbor::Float(float) => Value::Number(Number::from_f64(float).ok_or("Invalid number")?),
+        Cbor::Text(string) => Value::String(string),
+        Cbor::Bytes(bytes) if key == "proofValue" => Value::String(BASE64_STANDARD.encode(bytes)),
+        Cbor::Tag(UUID_TAG, uuid) => match *uuid {
+            Cbor::Bytes(bytes) => Value::String(format!(
+                "urn:uuid:{}",
+                Uuid::from_slice(&bytes).map_err(|e| e.to_string())?
+            )),
+            _ => return Err("Invalid uuid".into()),
+        },
+        Cbor::Tag(EPOCH_TAG, seconds) => date(*seconds, 0)?,
+        Cbor::Tag(EXTENDED_TIME_TAG, time) => {
+            let Cbor::Map(time) = *time else {
+                return Err("Invalid extended time".into());
+            };
+            let field = |field: i64| {
+                time.iter()
+                    .find(|(key, _)| key.as_integer() == Some(field.into()))
+                    .map(|(_, value)| value.clone())
+                    .ok_or("Invalid extended time")
+            };
+            let nanos = field(-9)?.as_integer().ok_or("Invalid extended time")?;
+            date(field(1)?, u32::try_from(nanos).map_err(|e| e.to_string())?)?
+        }
+        Cbor::Array(array) => Value::Array(
+            array
+                .into_iter()
+                .map(|value| decompress(value, key, dictionary))
+                .collect::<Result<_, _>>()?,
+        ),
+        Cbor::Map(map) => {
+            let mut object = Map::new();
+            for (key, value) in map {
+                let key = match key {
+                    Cbor::Text(key) => key,
+                    Cbor::Integer(id) => {
+                        let id = u64::try_from(id).map_err(|e| e.to_string())?;
+                        term(dictionary, id & !1)?.to_string()
+                    }
+                    _ => return Err("Invalid map key".into()),
+                };
+                let value = decompress(value, &key, dictionary)?;
+                object.insert(key, value);
+            }
+            Value::Object(object)
+        }
+        _ => return Err(format!("Unexpected CBOR value under {}", key)),
+    })
+}
+
+fn term(dictionary: &Dictionary, id: u64) -> Result<&'static str, String> {
+    dictionary
+        .terms
+        .get(&id)
+        .copied()
+        .ok_or_else(|| format!("Unknown term id {}", id))
+}
+
+fn date(seconds: Cbor, nanos: u32) -> Result<Value, String> {
+    let seconds = seconds.as_integer().ok_or("Invalid date")?;
+    let date = DateTime::from_timestamp(i64::try_from(seconds).map_err(|e| e.to_string())?, nanos)
+        .ok_or("Invalid date")?;
+    Ok(Value::String(
+        date.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+fn to_cbor_ld(document: &impl Serialize, kind: EnvelopeKind) -> Result<Vec<u8>, String> {
+    let document = serde_json::to_value(document).map_err(|e| e.to_string())?;
+    let dictionary = Dictionary::new(&contexts(&document));
+    let payload = compress(&document, "", &dictionary)?;
+    let cbor_ld = write_deterministic(Cbor::Tag(
+        CBOR_LD_TAG,
+        Box::new(Cbor::Array(vec![Cbor::Integer(COMPRESSED.into()), payload])),
+    ))
+    .map_err(|e| e.to_string())?;
+    Ok(write_envelope(kind, CBOR_LD_VERSION, cbor_ld))
+}
+
+fn from_cbor_ld<T: DeserializeOwned>(reader: &[u8], kind: EnvelopeKind) -> Result<T, String> {
+    let reader = read_envelope(reader, kind, CBOR_LD_VERSION)?;
+    let Cbor::Tag(CBOR_LD_TAG, document) = ciborium::from_reader(reader.as_ref())
+        .map_err(|e: ciborium::de::Error<_>| e.to_string())?
+    else {
+        return Err("Not a CBOR-LD document".into());
+    };
+    let Cbor::Array(document) = *document else {
+        return Err("Invalid CBOR-LD document".into());
+    };
+    let [Cbor::Integer(entry), payload] =
+        <[Cbor; 2]>::try_from(document).map_err(|_| "Invalid CBOR-LD document")?
+    else {
+        return Err("Invalid CBOR-LD document".into());
+    };
+    let document = match u64::try_from(entry) {
+        Ok(UNCOMPRESSED) => payload.deserialized::<Value>().map_err(|e| e.to_string())?,
+        Ok(COMPRESSED) => {
+            let context = payload
+                .as_map()
+                .into_iter()
+                .flatten()
+                .find(|(key, _)| {
+                    key.as_integer()
+                        .is_some_and(|id| id == 0.into() || id == 1.into())
+                        || key.as_text() == Some("@context")
+                })
+                .map(|(_, context)| context.clone());
+            let contexts = match context {
+                Some(context) => contexts(&serde_json::json!({
+                    "@context": decompress(context, "@context", &Dictionary::new(&[]))?
+                })),
+                None => vec![],
+            };
+            decompress(payload, "", &Dictionary::new(&contexts))?
+        }
+        _ => return Err("Unsupported CBOR-LD entry".into()),
+    };
+    serde_json::from_value(document).map_err(|e| e.to_string())
+}
+
+impl VerifiablePresentation {
+    /// Serializes a VerifiablePresentation structure into CBOR-LD in a versioned envelope, compressing terms of registered contexts
+    pub fn serialize_cbor_ld(&self) -> Result<Vec<u8>, String> {
+        to_cbor_ld(self, EnvelopeKind::PresentationCborLd)
+    }
+    /// Deserializes CBOR-LD, with or without a versioned envelope, into a VerifiablePresentation structure
+    pub fn deserialize_cbor_ld(reader: Vec<u8>) -> Result<Self, String> {
+        from_cbor_ld(&reader, EnvelopeKind::PresentationCborLd)
+    }
+    /// Verifies a CBOR-LD VerifiablePresentation was signed by the owner of the given public key, returning it decoded
+    pub fn verify_cbor_ld(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vp = Self::deserialize_cbor_ld(reader)?;
+        vp.verify(public_key)?;
+        Ok(vp)
+    }
+}
+
+impl VerifiableCredential {
+    /// Serializes a VerifiableCredential structure into CBOR-LD in a versioned envelope, compressing terms of registered contexts
+    ///
+    /// Registered context urls and terms become integers, and uuids, dates and proof values become binary
+    pub fn serialize_cbor_ld(&self) -> Result<Vec<u8>, String> {
+        to_cbor_ld(self, EnvelopeKind::CredentialCborLd)
+    }
+    /// Deserializes CBOR-LD, with or without a versioned envelope, into a VerifiableCredential structure
+    pub fn deserialize_cbor_ld(reader: Vec<u8>) -> Result<Self, String> {
+        from_cbor_ld(&reader, EnvelopeKind::CredentialCborLd)
+    }
+    /// Verifies a CBOR-LD VerifiableCredential was signed by the owner of the given public key, returning it decoded
+    pub fn verify_cbor_ld(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vc = Self::deserialize_cbor_ld(reader)?;
+        vc.verify(public_key)?;
+        Ok(vc)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::schema::{compile, schema_id, validate, SchemaValidationError, ValidationOptions};
+use crate::verifiable_credential::SignedSchema;
+use crate::{CredentialSchema, VerifiableCredential};
+use jsonschema::Validator;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Signed schema credential that has been verified and compiled once, for validating many credentials
+///
+/// Cloning is cheap and the compiled validator can be shared across threads
+#[derive(Clone)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+pub struct CompiledSchema {
+    schema: VerifiableCredential,
+    validator: Arc<Validator>,
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl CompiledSchema {
+    #[cfg_attr(target_family = "wasm", wasm_bindgen(constructor))]
+    /// Verifies and compiles a signed schema, resolving `$ref`s against the given signed schemas
+    pub fn new(schema: SignedSchema, references: Vec<SignedSchema>) -> Result<Self, String> {
+        Self::new_with_options(schema, references, &ValidationOptions::default())
+    }
+    /// Verifies and compiles a signed schema with the given validation options
+    pub fn new_with_options(
+        schema: SignedSchema,
+        references: Vec<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<Self, String> {
+        schema
+            .vc
+            .verify(schema.public_key())
+            .map_err(|_| "Failed to verify schema signature")?;
+        let references: Vec<_> = references
+            .iter()
+            .filter(|reference| reference.vc.verify(reference.public_key()).is_ok())
+            .map(|reference| &reference.vc)
+            .chain([&schema.vc])
+            .collect();
+        Ok(Self {
+            validator: Arc::new(compile(schema.vc.json_schema()?, &references, options)?),
+            schema: schema.vc,
+        })
+    }
+}
+
+impl CompiledSchema {
+    /// Validates the credentialSubject of a credential against this schema, whatever its credentialSchema lists
+    ///
+    /// Useful for checking credentials issued under an older version of the schema still match
+    pub fn subject_errors(
+        &self,
+        verifiable_credential: &VerifiableCredential,
+    ) -> Vec<SchemaValidationError> {
+        let schema_id = self
+            .schema
+            .json_schema()
+            .ok()
+            .and_then(schema_id)
+            .unwrap_or_default()
+            .to_string();
+        validate(&self.validator, &verifiable_credential.credential_subject)
+            .into_iter()
+            .map(|error| SchemaValidationError {
+                schema_id: schema_id.clone(),
+                ..error
+            })
+            .collect()
+    }
+    pub(crate) fn schema(&self) -> &VerifiableCredential {
+        &self.schema
+    }
+    /// Validates a credential subject against the schema a credentialSchema entry refers to
+    pub(crate) fn errors(
+        &self,
+        credential_schema: &CredentialSchema,
+        subject: &Value,
+    ) -> Result<Vec<SchemaValidationError>, String> {
+        self.schema.check_digest_sri(credential_schema)?;
+        Ok(validate(&self.validator, subject))
+    }
+}
+
+/// Identifies a signed schema by its `$id`, signature and public key
+type CacheKey = (String, String, Vec<u8>);
+
+/// Returns the cache key of a signed schema, which must be signed unless it is only a reference
+fn cache_key(schema: &SignedSchema, signed: bool) -> Result<CacheKey, String> {
+    let json_schema = schema.vc.json_schema()?;
+    let proof_value = match (&schema.vc.proof, signed) {
+        (Some(proof), _) => proof.proof_value.clone(),
+        (None, false) => String::new(),
+        (None, true) => return Err("Schema is unsigned".into()),
+    };
+    Ok((
+        schema_id(json_schema)
+            .ok_or("No $id field in schema")?
+            .to_string(),
+        proof_value,
+        schema.public_key().to_vec(),
+    ))
+}
+
+/// Thread-safe cache of compiled schemas keyed by the `$id`, signature and public key of the schema and each of its references
+#[derive(Default)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+pub struct SchemaCache {
+    options: ValidationOptions,
+    compiled: RwLock<HashMap<Vec<CacheKey>, CompiledSchema>>,
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl SchemaCache {
+    #[cfg_attr(target_family = "wasm", wasm_bindgen(constructor))]
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates an empty cache that compiles schemas with the given validation options
+    pub fn with_options(options: ValidationOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+    /// Returns the compiled form of a signed schema, only verifying and compiling it the first time it is seen
+    pub fn get_or_compile(
+        &self,
+        schema: SignedSchema,
+        references: Vec<SignedSchema>,
+    ) -> Result<CompiledSchema, String> {
+        let mut reference_keys = references
+            .iter()
+            .map(|reference| cache_key(reference, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        reference_keys.sort();
+        let key = [vec![cache_key(&schema, true)?], reference_keys].concat();
+        if let Some(compiled) = self.compiled.read().map_err(|e| e.to_string())?.get(&key) {
+            return Ok(compiled.clone());
+        }
+        let compiled = CompiledSchema::new_with_options(schema, references, &self.options)?;
+        self.compiled
+            .write()
+            .map_err(|e| e.to_string())?
+            .insert(key, compiled.clone());
+        Ok(compiled)
+    }
+    /// Returns the number of compiled schemas in the cache
+    pub fn len(&self) -> usize {
+        self.compiled.read().map_or(0, |compiled| compiled.len())
+    }
+    /// Returns whether the cache holds no compiled schemas
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
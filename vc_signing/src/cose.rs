@@ -0,0 +1,167 @@
+use crate::cbor::write_deterministic;
+use crate::jose::kid;
+use crate::{VerifiableCredential, VerifiablePresentation};
+use ciborium::value::Value as Cbor;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Media type of a COSE secured credential
+pub const VC_COSE_MEDIA_TYPE: &str = "application/vc+cose";
+/// Media type of a COSE secured presentation
+pub const VP_COSE_MEDIA_TYPE: &str = "application/vp+cose";
+
+/// CBOR tag of a COSE_Sign1 message
+const COSE_SIGN1_TAG: u64 = 18;
+const ALG: i64 = 1;
+const CONTENT_TYPE: i64 = 3;
+const KID: i64 = 4;
+const TYP: i64 = 16;
+/// EdDSA, the algorithm of the existing Ed25519 keys
+const EDDSA: i64 = -8;
+
+/// Parts of a COSE_Sign1 message
+struct Sign1 {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// Returns the bytes a COSE_Sign1 signature covers
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>, String> {
+    write_deterministic(Cbor::Array(vec![
+        Cbor::Text("Signature1".into()),
+        Cbor::Bytes(protected.to_vec()),
+        Cbor::Bytes(Vec::new()),
+        Cbor::Bytes(payload.to_vec()),
+    ]))
+    .map_err(|e| e.to_string())
+}
+
+fn sign1(
+    document: &impl Serialize,
+    media_type: &str,
+    content_type: &str,
+    private_key: &[u8],
+) -> Result<Vec<u8>, String> {
+    let key_pair = Ed25519KeyPair::from_pkcs8(private_key).map_err(|e| e.to_string())?;
+    let kid = kid(key_pair.public_key().as_ref())?;
+    let protected = write_deterministic(Cbor::Map(vec![
+        (ALG.into(), EDDSA.into()),
+        (CONTENT_TYPE.into(), content_type.into()),
+        (KID.into(), Cbor::Bytes(kid.into_bytes())),
+        (TYP.into(), media_type.into()),
+    ]))
+    .map_err(|e| e.to_string())?;
+    let payload = serde_json::to_vec(document).map_err(|e| e.to_string())?;
+    let signature = key_pair.sign(&sig_structure(&protected, &payload)?);
+    write_deterministic(Cbor::Tag(
+        COSE_SIGN1_TAG,
+        Box::new(Cbor::Array(vec![
+            Cbor::Bytes(protected),
+            Cbor::Map(Vec::new()),
+            Cbor::Bytes(payload),
+            Cbor::Bytes(signature.as_ref().to_vec()),
+        ])),
+    ))
+    .map_err(|e| e.to_string())
+}
+
+/// Parses a COSE_Sign1 message, checking its protected header names the expected media type
+fn open1(reader: &[u8], media_type: &str) -> Result<Sign1, String> {
+    let message =
+        match ciborium::from_reader(reader).map_err(|e: ciborium::de::Error<_>| e.to_string())? {
+            Cbor::Tag(COSE_SIGN1_TAG, message) => *message,
+            message => message,
+        };
+    let Ok([Cbor::Bytes(protected), Cbor::Map(_), Cbor::Bytes(payload), Cbor::Bytes(signature)]) =
+        <[Cbor; 4]>::try_from(
+            message
+                .into_array()
+                .map_err(|_| "Not a COSE_Sign1 message")?,
+        )
+    else {
+        return Err("Not a COSE_Sign1 message".into());
+    };
+    let header: Cbor = ciborium::from_reader(protected.as_slice())
+        .map_err(|e: ciborium::de::Error<_>| e.to_string())?;
+    let typ = header
+        .as_map()
+        .into_iter()
+        .flatten()
+        .find(|(label, _)| *label == TYP.into())
+        .and_then(|(_, typ)| typ.as_text());
+    if typ.is_some_and(|typ| typ != media_type) {
+        return Err(format!("COSE message is not {}", media_type));
+    }
+    Ok(Sign1 {
+        protected,
+        payload,
+        signature,
+    })
+}
+
+impl Sign1 {
+    fn algorithm(&self) -> Result<i64, String> {
+        let header: Cbor = ciborium::from_reader(self.protected.as_slice())
+            .map_err(|e: ciborium::de::Error<_>| e.to_string())?;
+        header
+            .as_map()
+            .into_iter()
+            .flatten()
+            .find(|(label, _)| *label == ALG.into())
+            .and_then(|(_, alg)| alg.as_integer())
+            .and_then(|alg| i64::try_from(alg).ok())
+            .ok_or_else(|| "COSE message has no alg".to_string())
+    }
+    fn verify(&self, public_key: &[u8]) -> Result<(), String> {
+        let algorithm = match self.algorithm()? {
+            EDDSA => &ED25519,
+            alg => return Err(format!("Unsupported COSE algorithm {}", alg)),
+        };
+        UnparsedPublicKey::new(algorithm, public_key)
+            .verify(
+                &sig_structure(&self.protected, &self.payload)?,
+                &self.signature,
+            )
+            .map_err(|_| "Failed to verify".into())
+    }
+    fn document<T: DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.payload).map_err(|e| e.to_string())
+    }
+}
+
+impl VerifiablePresentation {
+    /// Secures a VerifiablePresentation as an `application/vp+cose` COSE_Sign1 message, signed with the given private key
+    pub fn sign_cose(&self, private_key: &[u8]) -> Result<Vec<u8>, String> {
+        sign1(self, VP_COSE_MEDIA_TYPE, "application/vp", private_key)
+    }
+    /// Verifies an `application/vp+cose` message was signed by the owner of the given public key, returning the presentation it secures
+    pub fn verify_cose(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let message = open1(&reader, VP_COSE_MEDIA_TYPE)?;
+        message.verify(public_key)?;
+        message.document()
+    }
+    /// Returns the presentation an `application/vp+cose` message secures, without verifying it
+    pub fn decode_cose(reader: Vec<u8>) -> Result<Self, String> {
+        open1(&reader, VP_COSE_MEDIA_TYPE)?.document()
+    }
+}
+
+impl VerifiableCredential {
+    /// Secures a VerifiableCredential as an `application/vc+cose` COSE_Sign1 message, signed with the given private key
+    ///
+    /// The protected header names the signing key by its did:key
+    pub fn sign_cose(&self, private_key: &[u8]) -> Result<Vec<u8>, String> {
+        sign1(self, VC_COSE_MEDIA_TYPE, "application/vc", private_key)
+    }
+    /// Verifies an `application/vc+cose` message was signed by the owner of the given public key, returning the credential it secures
+    pub fn verify_cose(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let message = open1(&reader, VC_COSE_MEDIA_TYPE)?;
+        message.verify(public_key)?;
+        message.document()
+    }
+    /// Returns the credential an `application/vc+cose` message secures, without verifying it
+    pub fn decode_cose(reader: Vec<u8>) -> Result<Self, String> {
+        open1(&reader, VC_COSE_MEDIA_TYPE)?.document()
+    }
+}
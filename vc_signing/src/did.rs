@@ -0,0 +1,50 @@
+use url::Url;
+
+/// Multicodec prefix for an ED25519 public key (varint encoded 0xed)
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// Multicodec prefix for a BLS12-381 G2 public key (varint encoded 0xeb)
+const BLS12_381_G2_MULTICODEC: [u8; 2] = [0xeb, 0x01];
+
+/// Multicodec prefix for a compressed P-256 public key (varint encoded 0x1200)
+pub(crate) const P256_MULTICODEC: [u8; 2] = [0x80, 0x24];
+
+/// Creates a did:key identifier from an ED25519, compressed BLS12-381 G2 or compressed or uncompressed P-256 public key
+pub fn did_key_from_public_key(public_key: &[u8]) -> Result<Url, String> {
+    let multicodec = match public_key {
+        ed25519 if ed25519.len() == 32 => [ED25519_MULTICODEC.as_slice(), ed25519].concat(),
+        bls12_381 if bls12_381.len() == 96 => {
+            [BLS12_381_G2_MULTICODEC.as_slice(), bls12_381].concat()
+        }
+        [0x02 | 0x03, x @ ..] if x.len() == 32 => [P256_MULTICODEC.as_slice(), public_key].concat(),
+        // did:key encodes P-256 keys compressed, as the x coordinate prefixed by the parity of y
+        [0x04, point @ ..] if point.len() == 64 => [
+            P256_MULTICODEC.as_slice(),
+            &[0x02 | (point[63] & 1)],
+            &point[..32],
+        ]
+        .concat(),
+        _ => return Err("Unsupported public key".into()),
+    };
+    Url::parse(&format!(
+        "did:key:z{}",
+        bs58::encode(multicodec).into_string()
+    ))
+    .map_err(|e| e.to_string())
+}
+
+/// Resolves a did:key identifier to the ED25519 public key it encodes
+pub fn public_key_from_did_key(did: &Url) -> Result<Vec<u8>, String> {
+    let encoded = did
+        .as_str()
+        .strip_prefix("did:key:z")
+        .ok_or("Holder is not a base58btc did:key")?;
+    let encoded = encoded.split('#').next().unwrap_or(encoded);
+    let decoded = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|e| e.to_string())?;
+    match decoded.strip_prefix(&ED25519_MULTICODEC) {
+        Some(public_key) if public_key.len() == 32 => Ok(public_key.to_vec()),
+        _ => Err("did:key is not an ED25519 public key".into()),
+    }
+}
@@ -0,0 +1,338 @@
+use crate::did::P256_MULTICODEC;
+use crate::selective_disclosure::{
+    absolute_mandatory_pointers, canonicalize_and_group, decode_proof_value, derive_document,
+    encode_proof_value, generate_hmac_key, hash_nquads, hmac_labels, label_index, new_proof,
+    partition, proof_hash, replace_labels, selects_subject, subject_pointers, unsecured_document,
+    verification_method, verifier_label_map, Cbor, LabelMap,
+};
+use crate::{Proof, VerifiableCredential};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::PublicKey;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+    ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde_json::Value;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Name of the ECDSA selective disclosure Data Integrity cryptosuite
+pub const ECDSA_SD_CRYPTOSUITE: &str = "ecdsa-sd-2023";
+
+/// Prefix of a base proof value, the CBOR tag 0xd95d00
+const BASE_PROOF_HEADER: [u8; 3] = [0xd9, 0x5d, 0x00];
+/// Prefix of a derived proof value, the CBOR tag 0xd95d01
+const DERIVED_PROOF_HEADER: [u8; 3] = [0xd9, 0x5d, 0x01];
+
+/// Generates a P-256 private key as PKCS#8, and its uncompressed public key
+pub(crate) fn generate_key_pair() -> Result<(Vec<u8>, Vec<u8>), String> {
+    let rng = SystemRandom::new();
+    let private_key = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+        .map_err(|_| "Error generating key pair")?;
+    let public_key =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, private_key.as_ref(), &rng)
+            .map_err(|_| "Error generating key pair")?
+            .public_key()
+            .as_ref()
+            .to_vec();
+    Ok((private_key.as_ref().to_vec(), public_key))
+}
+
+/// Encodes a compressed or uncompressed P-256 public key as a SEC1 point
+fn encode_point(public_key: &[u8], compress: bool) -> Result<Vec<u8>, String> {
+    Ok(PublicKey::from_sec1_bytes(public_key)
+        .map_err(|_| "Not a P-256 public key")?
+        .to_encoded_point(compress)
+        .as_bytes()
+        .to_vec())
+}
+
+/// Returns the bytes the base signature signs, binding the proof options, ephemeral public key and mandatory
+/// N-Quads
+fn base_signing_input(
+    proof: &Proof,
+    document: &Value,
+    ephemeral_public_key: &[u8],
+    mandatory: &[impl AsRef<str>],
+) -> Result<Vec<u8>, String> {
+    Ok([
+        proof_hash(proof, document)?.as_slice(),
+        ephemeral_public_key,
+        &hash_nquads(mandatory),
+    ]
+    .concat())
+}
+
+/// Compresses the label map of a derived proof to the indexes of canonical labels and the HMACs they map to
+fn compress_label_map(label_map: &LabelMap) -> Result<Cbor, String> {
+    let mut entries = label_map
+        .iter()
+        .map(|(canonical, label)| {
+            let hmac = label
+                .strip_prefix('u')
+                .and_then(|hmac| BASE64_URL_SAFE_NO_PAD.decode(hmac).ok())
+                .ok_or_else(|| format!("Invalid blank node label {}", label))?;
+            Ok((label_index(canonical, "c14n")?, hmac))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    entries.sort();
+    Ok(Cbor::Map(
+        entries
+            .into_iter()
+            .map(|(index, hmac)| (Cbor::Unsigned(index), Cbor::Bytes(hmac)))
+            .collect(),
+    ))
+}
+
+fn decompress_label_map(label_map: Cbor) -> Result<LabelMap, String> {
+    label_map
+        .into_map()?
+        .into_iter()
+        .map(|(index, hmac)| {
+            Ok((
+                format!("c14n{}", index.into_unsigned()?),
+                format!("u{}", BASE64_URL_SAFE_NO_PAD.encode(hmac.into_bytes()?)),
+            ))
+        })
+        .collect()
+}
+
+/// Verifies the base signature with the issuer's public key, and the signature of each non-mandatory N-Quad with
+/// the ephemeral public key
+#[allow(clippy::too_many_arguments)]
+fn verify_signatures(
+    public_key: &[u8],
+    proof: &Proof,
+    document: &Value,
+    base_signature: Cbor,
+    ephemeral_public_key: Cbor,
+    signatures: Cbor,
+    mandatory: &[String],
+    non_mandatory: &[String],
+) -> Result<(), String> {
+    let ephemeral_public_key = ephemeral_public_key.into_bytes()?;
+    UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, encode_point(public_key, false)?)
+        .verify(
+            &base_signing_input(proof, document, &ephemeral_public_key, mandatory)?,
+            &base_signature.into_bytes()?,
+        )
+        .map_err(|_| "Failed to verify")?;
+    let signatures = signatures.into_array()?;
+    if signatures.len() != non_mandatory.len() {
+        return Err("Proof does not match the credential".into());
+    }
+    let ephemeral_public_key = ephemeral_public_key
+        .strip_prefix(&P256_MULTICODEC)
+        .ok_or("Ephemeral public key is not a P-256 multikey")?;
+    let ephemeral_public_key = UnparsedPublicKey::new(
+        &ECDSA_P256_SHA256_FIXED,
+        encode_point(ephemeral_public_key, false)?,
+    );
+    for (signature, nquad) in signatures.into_iter().zip(non_mandatory) {
+        ephemeral_public_key
+            .verify(nquad.as_bytes(), &signature.into_bytes()?)
+            .map_err(|_| "Failed to verify")?;
+    }
+    Ok(())
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiableCredential {
+    /// Signs a VerifiableCredential with an ecdsa-sd-2023 base proof, using the given P-256 private key
+    ///
+    /// Claims outside the credentialSubject, and subject claims named by a JSON pointer in `mandatory_pointers`,
+    /// are covered by the base signature and disclosed in every derived proof. Each other N-Quad of the credential
+    /// is signed with a key made for this proof, so holders can choose to disclose it
+    pub fn sign_ecdsa_sd(
+        mut self,
+        private_key: &[u8],
+        mandatory_pointers: Vec<String>,
+    ) -> Result<Self, String> {
+        self.proof = None;
+        let document = unsecured_document(&self)?;
+        let mandatory_pointers = absolute_mandatory_pointers(&document, &mandatory_pointers);
+        let hmac_key = generate_hmac_key()?;
+        let canonicalized = canonicalize_and_group(
+            &document,
+            |canonical| Ok(hmac_labels(&hmac_key, canonical)),
+            &[&mandatory_pointers],
+        )?;
+        let group = &canonicalized.groups[0];
+        let mandatory: Vec<&String> = group
+            .matching
+            .iter()
+            .map(|index| &canonicalized.nquads[*index])
+            .collect();
+
+        let rng = SystemRandom::new();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, private_key, &rng)
+                .map_err(|e| e.to_string())?;
+        let ephemeral_key_pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            generate_key_pair()?.0.as_slice(),
+            &rng,
+        )
+        .map_err(|e| e.to_string())?;
+        let ephemeral_public_key = [
+            P256_MULTICODEC.as_slice(),
+            &encode_point(ephemeral_key_pair.public_key().as_ref(), true)?,
+        ]
+        .concat();
+
+        let mut proof = new_proof(
+            ECDSA_SD_CRYPTOSUITE,
+            verification_method(key_pair.public_key().as_ref())?,
+        );
+        let base_signature = key_pair
+            .sign(
+                &rng,
+                &base_signing_input(&proof, &document, &ephemeral_public_key, &mandatory)?,
+            )
+            .map_err(|_| "Error signing")?;
+        let signatures = group
+            .non_matching
+            .iter()
+            .map(|index| {
+                Ok(Cbor::Bytes(
+                    ephemeral_key_pair
+                        .sign(&rng, canonicalized.nquads[*index].as_bytes())
+                        .map_err(|_| "Error signing")?
+                        .as_ref()
+                        .to_vec(),
+                ))
+            })
+            .collect::<Result<_, String>>()?;
+        proof.proof_value = encode_proof_value(
+            ECDSA_SD_CRYPTOSUITE,
+            BASE_PROOF_HEADER,
+            vec![
+                Cbor::Bytes(base_signature.as_ref().to_vec()),
+                Cbor::Bytes(ephemeral_public_key),
+                Cbor::Bytes(hmac_key),
+                Cbor::Array(signatures),
+                Cbor::Array(mandatory_pointers.into_iter().map(Cbor::Text).collect()),
+            ],
+        );
+        self.proof = Some(proof);
+        Ok(self)
+    }
+    /// Derives a credential from one with an ecdsa-sd-2023 base proof, disclosing only its mandatory claims and
+    /// the subject claims named by a JSON pointer in `selective_pointers`
+    pub fn derive_ecdsa_sd(&self, selective_pointers: Vec<String>) -> Result<Self, String> {
+        let base_proof = self.proof.as_ref().ok_or("VC is unsigned")?;
+        let (BASE_PROOF_HEADER, components) = decode_proof_value(base_proof)? else {
+            return Err("Not an ecdsa-sd-2023 base proof".into());
+        };
+        let Ok([base_signature, ephemeral_public_key, hmac_key, signatures, mandatory_pointers]) =
+            <[Cbor; 5]>::try_from(components)
+        else {
+            return Err("Invalid ecdsa-sd-2023 base proof".into());
+        };
+        let hmac_key = hmac_key.into_bytes()?;
+        let mandatory_pointers = mandatory_pointers.into_texts()?;
+        let selective_pointers = subject_pointers(&selective_pointers);
+        let combined_pointers = [mandatory_pointers.as_slice(), &selective_pointers].concat();
+        if !selects_subject(&combined_pointers) {
+            return Err("A derived credential must disclose a subject claim".into());
+        }
+
+        let document = unsecured_document(self)?;
+        let canonicalized = canonicalize_and_group(
+            &document,
+            |canonical| Ok(hmac_labels(&hmac_key, canonical)),
+            &[&mandatory_pointers, &selective_pointers, &combined_pointers],
+        )?;
+        let [mandatory, selective, combined] = &canonicalized.groups[..] else {
+            return Err("Error grouping N-Quads".into());
+        };
+        let signatures = signatures.into_array()?;
+        if signatures.len() != mandatory.non_matching.len() {
+            return Err("Base proof does not match the credential".into());
+        }
+        let signatures = mandatory
+            .non_matching
+            .iter()
+            .zip(signatures)
+            .filter(|(index, _)| selective.matching.contains(index))
+            .map(|(_, signature)| signature)
+            .collect();
+        let mandatory_indexes = combined
+            .matching
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| mandatory.matching.contains(index))
+            .map(|(position, _)| Cbor::Unsigned(position as u64))
+            .collect();
+        let label_map = verifier_label_map(&combined.quads, &canonicalized.labels)?;
+
+        let proof = Proof {
+            proof_value: encode_proof_value(
+                ECDSA_SD_CRYPTOSUITE,
+                DERIVED_PROOF_HEADER,
+                vec![
+                    base_signature,
+                    ephemeral_public_key,
+                    Cbor::Array(signatures),
+                    compress_label_map(&label_map)?,
+                    Cbor::Array(mandatory_indexes),
+                ],
+            ),
+            ..base_proof.clone()
+        };
+        derive_document(&document, &combined_pointers, proof)
+    }
+}
+
+impl VerifiableCredential {
+    /// Verifies an ecdsa-sd-2023 base or derived proof was made with the given P-256 public key
+    pub(crate) fn verify_ecdsa_sd(&self, public_key: &[u8]) -> Result<(), String> {
+        let proof = self.proof.as_ref().ok_or("VC is unsigned")?;
+        let (header, components) = decode_proof_value(proof)?;
+        let document = unsecured_document(self)?;
+        let invalid = || "Invalid ecdsa-sd-2023 proof".to_string();
+        let (base_signature, ephemeral_public_key, signatures, (mandatory, non_mandatory)) =
+            match header {
+                BASE_PROOF_HEADER => {
+                    let [base_signature, ephemeral_public_key, hmac_key, signatures, mandatory_pointers] =
+                        <[Cbor; 5]>::try_from(components).map_err(|_| invalid())?;
+                    let hmac_key = hmac_key.into_bytes()?;
+                    let canonicalized = canonicalize_and_group(
+                        &document,
+                        |canonical| Ok(hmac_labels(&hmac_key, canonical)),
+                        &[&mandatory_pointers.into_texts()?],
+                    )?;
+                    let mandatory_indexes = &canonicalized.groups[0].matching;
+                    let nquads = partition(canonicalized.nquads, mandatory_indexes)?;
+                    (base_signature, ephemeral_public_key, signatures, nquads)
+                }
+                DERIVED_PROOF_HEADER => {
+                    let [base_signature, ephemeral_public_key, signatures, label_map, mandatory_indexes] =
+                        <[Cbor; 5]>::try_from(components).map_err(|_| invalid())?;
+                    let label_map = decompress_label_map(label_map)?;
+                    let canonicalized = canonicalize_and_group(
+                        &document,
+                        |canonical| replace_labels(&label_map, canonical),
+                        &[],
+                    )?;
+                    let nquads =
+                        partition(canonicalized.nquads, &mandatory_indexes.into_indexes()?)?;
+                    (base_signature, ephemeral_public_key, signatures, nquads)
+                }
+                _ => return Err(invalid()),
+            };
+        verify_signatures(
+            public_key,
+            proof,
+            &document,
+            base_signature,
+            ephemeral_public_key,
+            signatures,
+            &mandatory,
+            &non_mandatory,
+        )
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{VerifiableCredential, VerifiablePresentation};
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+use std::borrow::Cow;
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+use std::io::{Read, Write};
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Prefix of a QR payload, naming version 1 of the encoding: zlib compressed CBOR-LD in base45
+pub const QR_PREFIX: &str = "VC1:";
+
+/// Magic number starting an envelope, which no JSON, CBOR or protobuf document starts with
+const ENVELOPE_MAGIC: [u8; 3] = [0x00, b'V', b'C'];
+/// Length of an envelope header: magic number, kind, schema version and flags
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+const ENVELOPE_LENGTH: usize = 6;
+/// Envelope flag set when the document is zlib compressed
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+const COMPRESSED: u8 = 0x01;
+/// Largest decompressed document accepted
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+const MAX_DOCUMENT_SIZE: u64 = 1 << 20;
+
+/// Self-described CBOR tag 55799, as encoded at the start of a document
+const SELF_DESCRIBED_CBOR: [u8; 3] = [0xd9, 0xd9, 0xf7];
+/// Private CBOR-LD tag 0x56434c44, as encoded at the start of a document
+const CBOR_LD: [u8; 5] = [0xda, 0x56, 0x43, 0x4c, 0x44];
+/// COSE_Sign1 tag 18, as encoded at the start of a message
+const COSE_SIGN1: u8 = 0xd2;
+/// Array of 4 items, the start of an untagged COSE_Sign1 message
+const UNTAGGED_COSE_SIGN1: u8 = 0x84;
+
+/// Encodings a credential or presentation can be loaded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Jwt,
+    Qr,
+    Cbor,
+    CborLd,
+    Cose,
+    Protobuf,
+    CompactProtobuf,
+}
+
+/// Whether a serialized document is a credential or a presentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentType {
+    Credential,
+    Presentation,
+}
+
+/// Kind of document in an envelope, naming its encoding and whether it is a credential or presentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum EnvelopeKind {
+    CredentialProtobuf = 1,
+    PresentationProtobuf = 2,
+    CredentialCbor = 3,
+    PresentationCbor = 4,
+    CredentialCborLd = 5,
+    PresentationCborLd = 6,
+    CredentialCompactProtobuf = 7,
+}
+
+impl EnvelopeKind {
+    fn from_byte(kind: u8) -> Result<Self, String> {
+        [
+            Self::CredentialProtobuf,
+            Self::PresentationProtobuf,
+            Self::CredentialCbor,
+            Self::PresentationCbor,
+            Self::CredentialCborLd,
+            Self::PresentationCborLd,
+            Self::CredentialCompactProtobuf,
+        ]
+        .into_iter()
+        .find(|known| *known as u8 == kind)
+        .ok_or_else(|| format!("Unsupported envelope kind {}", kind))
+    }
+    fn encoding(self) -> Encoding {
+        match self {
+            Self::CredentialProtobuf | Self::PresentationProtobuf => Encoding::Protobuf,
+            Self::CredentialCbor | Self::PresentationCbor => Encoding::Cbor,
+            Self::CredentialCborLd | Self::PresentationCborLd => Encoding::CborLd,
+            Self::CredentialCompactProtobuf => Encoding::CompactProtobuf,
+        }
+    }
+    fn document_type(self) -> DocumentType {
+        match self {
+            Self::CredentialProtobuf
+            | Self::CredentialCbor
+            | Self::CredentialCborLd
+            | Self::CredentialCompactProtobuf => DocumentType::Credential,
+            Self::PresentationProtobuf | Self::PresentationCbor | Self::PresentationCborLd => {
+                DocumentType::Presentation
+            }
+        }
+    }
+}
+
+/// Compresses bytes with zlib
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+pub(crate) fn deflate(reader: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(reader).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+/// Decompresses zlib compressed bytes, refusing documents larger than a megabyte
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+pub(crate) fn inflate(reader: &[u8]) -> Result<Vec<u8>, String> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(reader)
+        .take(MAX_DOCUMENT_SIZE + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| e.to_string())?;
+    if inflated.len() as u64 > MAX_DOCUMENT_SIZE {
+        return Err("Decompressed document is too large".into());
+    }
+    Ok(inflated)
+}
+
+/// Wraps a binary document in an envelope naming its kind and schema version
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+pub(crate) fn write_envelope(kind: EnvelopeKind, version: u8, document: Vec<u8>) -> Vec<u8> {
+    let mut envelope = ENVELOPE_MAGIC.to_vec();
+    envelope.extend_from_slice(&[kind as u8, version, 0]);
+    envelope.extend(document);
+    envelope
+}
+
+/// Returns the document in an envelope, decompressed, checking it is of the expected kind and at most the given schema version
+///
+/// Legacy bare documents without an envelope are returned whole
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+pub(crate) fn read_envelope(
+    reader: &[u8],
+    kind: EnvelopeKind,
+    version: u8,
+) -> Result<Cow<'_, [u8]>, String> {
+    if !reader.starts_with(&ENVELOPE_MAGIC) {
+        return Ok(Cow::Borrowed(reader));
+    }
+    let Some(&[envelope_kind, envelope_version, flags]) =
+        reader.get(ENVELOPE_MAGIC.len()..ENVELOPE_LENGTH)
+    else {
+        return Err("Truncated envelope".into());
+    };
+    let envelope_kind = EnvelopeKind::from_byte(envelope_kind)?;
+    if envelope_kind != kind {
+        return Err(format!(
+            "Envelope holds a {:?}, not a {:?}",
+            envelope_kind, kind
+        ));
+    }
+    if envelope_version > version {
+        return Err(format!(
+            "Unsupported {:?} version {}",
+            kind, envelope_version
+        ));
+    }
+    match flags {
+        0 => Ok(Cow::Borrowed(&reader[ENVELOPE_LENGTH..])),
+        COMPRESSED => Ok(Cow::Owned(inflate(&reader[ENVELOPE_LENGTH..])?)),
+        flags => Err(format!("Unsupported envelope flags {:#04x}", flags)),
+    }
+}
+
+/// Compresses the document in an envelope, as written by the protobuf, CBOR and CBOR-LD serializers
+#[cfg(any(feature = "cbor", feature = "protobuf"))]
+pub fn compress(reader: Vec<u8>) -> Result<Vec<u8>, String> {
+    let Some(&[kind, version, flags]) = reader
+        .starts_with(&ENVELOPE_MAGIC)
+        .then(|| reader.get(ENVELOPE_MAGIC.len()..ENVELOPE_LENGTH))
+        .flatten()
+    else {
+        return Err("Document has no envelope".into());
+    };
+    if flags != 0 {
+        return Err("Document is already compressed".into());
+    }
+    let mut envelope = ENVELOPE_MAGIC.to_vec();
+    envelope.extend_from_slice(&[kind, version, COMPRESSED]);
+    envelope.extend(deflate(&reader[ENVELOPE_LENGTH..])?);
+    Ok(envelope)
+}
+
+/// Returns the kind of document in an envelope, or None for a document without one
+pub(crate) fn envelope_kind(reader: &[u8]) -> Result<Option<EnvelopeKind>, String> {
+    if !reader.starts_with(&ENVELOPE_MAGIC) {
+        return Ok(None);
+    }
+    let kind = reader
+        .get(ENVELOPE_MAGIC.len())
+        .ok_or("Truncated envelope")?;
+    Ok(Some(EnvelopeKind::from_byte(*kind)?))
+}
+
+/// Identifies whether a serialized document in any supported encoding is a credential or a presentation
+///
+/// Enveloped documents name their type, and other encodings are presentations when they load as one.
+/// Legacy protobuf without an envelope can't be told apart, and is taken to be a credential
+pub fn document_type(reader: &[u8]) -> Result<DocumentType, String> {
+    if let Some(kind) = envelope_kind(reader)? {
+        return Ok(kind.document_type());
+    }
+    Ok(match sniff(reader)? {
+        Encoding::Protobuf => DocumentType::Credential,
+        _ if VerifiablePresentation::deserialize_any(reader.to_vec()).is_ok() => {
+            DocumentType::Presentation
+        }
+        _ => DocumentType::Credential,
+    })
+}
+
+/// Identifies the encoding of a serialized credential or presentation from its leading bytes
+///
+/// Binary encodings are told apart by their envelope or tag, and text encodings by their shape.
+/// Legacy CBOR and protobuf without an envelope are recognised by their first byte
+pub fn sniff(reader: &[u8]) -> Result<Encoding, String> {
+    if let Some(kind) = envelope_kind(reader)? {
+        return Ok(kind.encoding());
+    }
+    if reader.starts_with(&SELF_DESCRIBED_CBOR) {
+        return Ok(Encoding::Cbor);
+    }
+    if reader.starts_with(&CBOR_LD) {
+        return Ok(Encoding::CborLd);
+    }
+    let text = reader.trim_ascii();
+    if text.starts_with(b"{") {
+        return Ok(Encoding::Json);
+    }
+    if text.starts_with(QR_PREFIX.as_bytes()) {
+        return Ok(Encoding::Qr);
+    }
+    // A JWS compact serialization is three base64url parts, the first a JSON header
+    if text.starts_with(b"eyJ")
+        && text.iter().filter(|&&c| c == b'.').count() == 2
+        && text
+            .iter()
+            .all(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.')
+    {
+        return Ok(Encoding::Jwt);
+    }
+    match reader.first() {
+        Some(&COSE_SIGN1 | &UNTAGGED_COSE_SIGN1) => Ok(Encoding::Cose),
+        // A map, the untagged document
+        Some(0xa0..=0xbf) => Ok(Encoding::Cbor),
+        // Field 1 or 2, length delimited, as the legacy documents start
+        Some(0x0a | 0x12) => Ok(Encoding::Protobuf),
+        _ => Err("Unrecognised encoding".into()),
+    }
+}
+
+/// Returns a text encoding as a string
+fn text(reader: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(reader).map_err(|e| e.to_string())
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiablePresentation {
+    /// Deserializes a VerifiablePresentation structure from any supported encoding, identified with `sniff`
+    pub fn deserialize_any(reader: Vec<u8>) -> Result<Self, String> {
+        match sniff(&reader)? {
+            Encoding::Json => serde_json::from_slice(&reader).map_err(|e| e.to_string()),
+            Encoding::Jwt => Self::decode_jwt(text(&reader)?),
+            #[cfg(feature = "qr")]
+            Encoding::Qr => Self::deserialize_qr(text(&reader)?),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => Self::deserialize_cbor(reader),
+            #[cfg(feature = "cbor")]
+            Encoding::CborLd => Self::deserialize_cbor_ld(reader),
+            #[cfg(feature = "cbor")]
+            Encoding::Cose => Self::decode_cose(reader),
+            #[cfg(feature = "protobuf")]
+            Encoding::Protobuf => Self::deserialize_protobuf(reader),
+            Encoding::CompactProtobuf => Err("Compact protobuf only holds credentials".into()),
+            #[allow(unreachable_patterns)]
+            encoding => Err(format!("{:?} support is not enabled", encoding)),
+        }
+    }
+    /// Verifies a VerifiablePresentation in any supported encoding was signed by the owner of the given public key, returning it decoded
+    ///
+    /// COSE and JWT envelopes are verified instead of the presentation's own proof
+    pub fn verify_any(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        match sniff(&reader)? {
+            Encoding::Jwt => Self::verify_jwt(text(&reader)?, public_key),
+            #[cfg(feature = "cbor")]
+            Encoding::Cose => Self::verify_cose(reader, public_key),
+            _ => {
+                let vp = Self::deserialize_any(reader)?;
+                vp.verify(public_key)?;
+                Ok(vp)
+            }
+        }
+    }
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiableCredential {
+    /// Deserializes a VerifiableCredential structure from any supported encoding, identified with `sniff`
+    ///
+    /// Compact protobuf credentials need their schema, so are refused and decoded with `deserialize_protobuf_compact` instead
+    pub fn deserialize_any(reader: Vec<u8>) -> Result<Self, String> {
+        match sniff(&reader)? {
+            Encoding::Json => serde_json::from_slice(&reader).map_err(|e| e.to_string()),
+            Encoding::Jwt => Self::decode_jwt(text(&reader)?),
+            #[cfg(feature = "qr")]
+            Encoding::Qr => Self::deserialize_qr(text(&reader)?),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => Self::deserialize_cbor(reader),
+            #[cfg(feature = "cbor")]
+            Encoding::CborLd => Self::deserialize_cbor_ld(reader),
+            #[cfg(feature = "cbor")]
+            Encoding::Cose => Self::decode_cose(reader),
+            #[cfg(feature = "protobuf")]
+            Encoding::Protobuf => Self::deserialize_protobuf(reader),
+            Encoding::CompactProtobuf => {
+                Err("Compact protobuf credentials need their schema to be decoded".into())
+            }
+            #[allow(unreachable_patterns)]
+            encoding => Err(format!("{:?} support is not enabled", encoding)),
+        }
+    }
+    /// Verifies a VerifiableCredential in any supported encoding was signed by the owner of the given public key, returning it decoded
+    ///
+    /// COSE and JWT envelopes are verified instead of the credential's own proof
+    pub fn verify_any(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        match sniff(&reader)? {
+            Encoding::Jwt => Self::verify_jwt(text(&reader)?, public_key),
+            #[cfg(feature = "cbor")]
+            Encoding::Cose => Self::verify_cose(reader, public_key),
+            _ => {
+                let vc = Self::deserialize_any(reader)?;
+                vc.verify(public_key)?;
+                Ok(vc)
+            }
+        }
+    }
+}
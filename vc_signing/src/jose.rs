@@ -0,0 +1,160 @@
+use crate::did::did_key_from_public_key;
+use crate::{VerifiableCredential, VerifiablePresentation};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Media type of a JOSE secured credential
+pub const VC_JWT_MEDIA_TYPE: &str = "application/vc+jwt";
+/// Media type of a JOSE secured presentation
+pub const VP_JWT_MEDIA_TYPE: &str = "application/vp+jwt";
+
+/// EdDSA, the algorithm of the existing Ed25519 keys
+const EDDSA: &str = "EdDSA";
+
+/// Protected header of a JWS
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cty: Option<String>,
+}
+
+/// Parts of a JWS compact serialization
+pub(crate) struct Jws<'a> {
+    header: Header,
+    signing_input: &'a str,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// Returns the did:key verification method naming an Ed25519 public key
+pub(crate) fn kid(public_key: &[u8]) -> Result<String, String> {
+    let did = did_key_from_public_key(public_key)?;
+    let fragment = did.as_str().trim_start_matches("did:key:").to_string();
+    Ok(format!("{}#{}", did, fragment))
+}
+
+/// Signs a document as a JWS compact serialization with the given `typ` and `cty` headers
+pub(crate) fn sign_compact(
+    document: &impl Serialize,
+    typ: &str,
+    cty: Option<&str>,
+    private_key: &[u8],
+) -> Result<String, String> {
+    let key_pair = Ed25519KeyPair::from_pkcs8(private_key).map_err(|e| e.to_string())?;
+    let header = Header {
+        alg: EDDSA.into(),
+        kid: Some(kid(key_pair.public_key().as_ref())?),
+        typ: Some(typ.into()),
+        cty: cty.map(Into::into),
+    };
+    let signing_input = format!(
+        "{}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).map_err(|e| e.to_string())?),
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(document).map_err(|e| e.to_string())?)
+    );
+    let signature = key_pair.sign(signing_input.as_bytes());
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        BASE64_URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+/// Parses a JWS compact serialization, checking its header names the expected media type
+pub(crate) fn open_compact<'a>(jwt: &'a str, media_type: &str) -> Result<Jws<'a>, String> {
+    let jwt = jwt.trim();
+    let (signing_input, signature) = jwt.rsplit_once('.').ok_or("Not a JWS")?;
+    let Some((header, payload)) = signing_input.split_once('.') else {
+        return Err("Not a JWS".into());
+    };
+    let decode = |part: &str| {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|e| e.to_string())
+    };
+    let header: Header = serde_json::from_slice(&decode(header)?).map_err(|e| e.to_string())?;
+    // typ may be given with or without the application/ prefix
+    if header.typ.as_deref().is_some_and(|typ| {
+        typ != media_type && Some(typ) != media_type.strip_prefix("application/")
+    }) {
+        return Err(format!("JWS is not {}", media_type));
+    }
+    Ok(Jws {
+        header,
+        signing_input,
+        payload: decode(payload)?,
+        signature: decode(signature)?,
+    })
+}
+
+impl Jws<'_> {
+    pub(crate) fn verify(&self, public_key: &[u8]) -> Result<(), String> {
+        let algorithm = match self.header.alg.as_str() {
+            EDDSA => &ED25519,
+            alg => return Err(format!("Unsupported JWS algorithm {}", alg)),
+        };
+        UnparsedPublicKey::new(algorithm, public_key)
+            .verify(self.signing_input.as_bytes(), &self.signature)
+            .map_err(|_| "Failed to verify".into())
+    }
+    pub(crate) fn document<T: DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.payload).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiablePresentation {
+    /// Secures a VerifiablePresentation as an `application/vp+jwt` JWS, signed with the given private key
+    pub fn sign_jwt(&self, private_key: &[u8]) -> Result<String, String> {
+        sign_compact(
+            self,
+            VP_JWT_MEDIA_TYPE.trim_start_matches("application/"),
+            Some("vp"),
+            private_key,
+        )
+    }
+    /// Verifies an `application/vp+jwt` JWS was signed by the owner of the given public key, returning the presentation it secures
+    pub fn verify_jwt(jwt: &str, public_key: &[u8]) -> Result<Self, String> {
+        let jws = open_compact(jwt, VP_JWT_MEDIA_TYPE)?;
+        jws.verify(public_key)?;
+        jws.document()
+    }
+    /// Returns the presentation an `application/vp+jwt` JWS secures, without verifying it
+    pub fn decode_jwt(jwt: &str) -> Result<Self, String> {
+        open_compact(jwt, VP_JWT_MEDIA_TYPE)?.document()
+    }
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiableCredential {
+    /// Secures a VerifiableCredential as an `application/vc+jwt` JWS, signed with the given private key
+    ///
+    /// The header names the signing key by its did:key verification method
+    pub fn sign_jwt(&self, private_key: &[u8]) -> Result<String, String> {
+        sign_compact(
+            self,
+            VC_JWT_MEDIA_TYPE.trim_start_matches("application/"),
+            Some("vc"),
+            private_key,
+        )
+    }
+    /// Verifies an `application/vc+jwt` JWS was signed by the owner of the given public key, returning the credential it secures
+    pub fn verify_jwt(jwt: &str, public_key: &[u8]) -> Result<Self, String> {
+        let jws = open_compact(jwt, VC_JWT_MEDIA_TYPE)?;
+        jws.verify(public_key)?;
+        jws.document()
+    }
+    /// Returns the credential an `application/vc+jwt` JWS secures, without verifying it
+    pub fn decode_jwt(jwt: &str) -> Result<Self, String> {
+        open_compact(jwt, VC_JWT_MEDIA_TYPE)?.document()
+    }
+}
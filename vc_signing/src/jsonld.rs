@@ -0,0 +1,649 @@
+//! Conversion of JSON-LD credentials to RDF, for the Data Integrity cryptosuites that sign canonical N-Quads
+//!
+//! Contexts can't be fetched while signing, so only the credentials v2 context and inline contexts are supported.
+//! Like the safe mode the cryptosuites require, anything expansion would drop, such as a relative IRI, is an error
+//! rather than silently left unsigned.
+
+use crate::verifiable_credential::sorted;
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+const CREDENTIALS_V2: &str = "https://www.w3.org/ns/credentials/v2";
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_JSON: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#JSON";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+/// Prefix of the IRIs blank nodes are named by while skolemized
+const SKOLEM_PREFIX: &str = "urn:bnid:";
+const KEYWORDS: &[&str] = &[
+    "@base",
+    "@container",
+    "@context",
+    "@direction",
+    "@graph",
+    "@id",
+    "@import",
+    "@included",
+    "@index",
+    "@json",
+    "@language",
+    "@list",
+    "@nest",
+    "@none",
+    "@prefix",
+    "@propagate",
+    "@protected",
+    "@reverse",
+    "@set",
+    "@type",
+    "@value",
+    "@version",
+    "@vocab",
+];
+
+fn context_document(url: &str) -> Result<&'static Map<String, Value>, String> {
+    static CREDENTIALS_V2_CONTEXT: OnceLock<Value> = OnceLock::new();
+    match url {
+        CREDENTIALS_V2 => CREDENTIALS_V2_CONTEXT
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("../credentials_v2_context.json"))
+                    .unwrap_or_default()
+            })
+            .get("@context")
+            .and_then(Value::as_object)
+            .ok_or_else(|| "Invalid JSON-LD context".to_string()),
+        _ => Err(format!("Unsupported JSON-LD context {}", url)),
+    }
+}
+
+/// Subject or object of an RDF statement
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum Term {
+    Iri(String),
+    Blank(String),
+    /// Lexical value and datatype IRI
+    Literal(String, String),
+}
+
+/// RDF statement in the default graph
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct Quad {
+    pub(crate) subject: Term,
+    pub(crate) predicate: String,
+    pub(crate) object: Term,
+}
+
+impl Quad {
+    /// Returns the distinct labels of the blank nodes in the statement
+    pub(crate) fn blank_nodes(&self) -> Vec<&str> {
+        let mut labels = Vec::new();
+        for term in [&self.subject, &self.object] {
+            if let Term::Blank(label) = term {
+                if !labels.contains(&label.as_str()) {
+                    labels.push(label.as_str());
+                }
+            }
+        }
+        labels
+    }
+    /// Serializes the statement as a canonical N-Quad, naming its blank nodes with `label`
+    pub(crate) fn to_nquad(&self, label: impl Fn(&str) -> String) -> String {
+        let term = |term: &Term| match term {
+            Term::Iri(iri) => format!("<{}>", iri),
+            Term::Blank(blank) => format!("_:{}", label(blank)),
+            Term::Literal(value, datatype) if datatype == XSD_STRING => {
+                format!("\"{}\"", escape(value))
+            }
+            Term::Literal(value, datatype) => format!("\"{}\"^^<{}>", escape(value), datatype),
+        };
+        format!(
+            "{} <{}> {} .\n",
+            term(&self.subject),
+            self.predicate,
+            term(&self.object)
+        )
+    }
+}
+
+/// Escapes a literal for canonical N-Quads
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '\0'..='\u{1f}' | '\u{7f}' => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serializes statements as sorted canonical N-Quads, naming blank nodes by their labels
+pub(crate) fn to_nquads(
+    quads: &[Quad],
+    labels: &HashMap<String, String>,
+) -> Result<Vec<String>, String> {
+    let mut nquads = quads
+        .iter()
+        .map(|quad| {
+            match quad
+                .blank_nodes()
+                .iter()
+                .all(|blank| labels.contains_key(*blank))
+            {
+                true => Ok(quad.to_nquad(|blank| labels[blank].clone())),
+                false => Err("Blank node has no label".to_string()),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    nquads.sort();
+    Ok(nquads)
+}
+
+/// Definition of a term in a JSON-LD context
+#[derive(Clone, PartialEq)]
+struct Definition {
+    /// Expanded IRI or keyword, None when the term is mapped to null
+    id: Option<String>,
+    type_mapping: Option<String>,
+    container: Vec<String>,
+    context: Option<Value>,
+    protected: bool,
+}
+
+/// Active JSON-LD context
+#[derive(Clone, Default)]
+struct Context {
+    terms: HashMap<String, Definition>,
+    vocab: Option<String>,
+    /// Context nested nodes revert to, when a type-scoped context doesn't propagate
+    previous: Option<Box<Context>>,
+}
+
+impl Context {
+    /// Applies a local context, as the JSON-LD context processing algorithm
+    fn process(
+        &self,
+        local: &Value,
+        propagate: bool,
+        override_protected: bool,
+    ) -> Result<Self, String> {
+        let mut result = self.clone();
+        if !propagate && result.previous.is_none() {
+            result.previous = Some(Box::new(self.clone()));
+        }
+        let locals = match local {
+            Value::Array(locals) => locals.iter().collect(),
+            local => vec![local],
+        };
+        for local in locals {
+            match local {
+                Value::Null => {
+                    result = Context {
+                        previous: result.previous.filter(|_| !propagate),
+                        ..Context::default()
+                    }
+                }
+                Value::String(url) => {
+                    result.define_all(context_document(url)?, override_protected)?
+                }
+                Value::Object(local) => result.define_all(local, override_protected)?,
+                _ => return Err("Invalid JSON-LD context".into()),
+            }
+        }
+        Ok(result)
+    }
+    fn define_all(
+        &mut self,
+        local: &Map<String, Value>,
+        override_protected: bool,
+    ) -> Result<(), String> {
+        match local.get("@vocab") {
+            Some(Value::Null) => self.vocab = None,
+            Some(Value::String(vocab)) => {
+                self.vocab = Some(
+                    self.expand(vocab, true)
+                        .filter(|vocab| vocab.contains(':'))
+                        .ok_or_else(|| format!("Invalid JSON-LD @vocab {}", vocab))?,
+                )
+            }
+            Some(_) => return Err("Invalid JSON-LD @vocab".into()),
+            None => {}
+        }
+        let protected = local.get("@protected") == Some(&Value::Bool(true));
+        for (term, value) in local {
+            match term.as_str() {
+                "@protected" | "@version" | "@vocab" => {}
+                keyword if keyword.starts_with('@') => {
+                    return Err(format!("Unsupported JSON-LD keyword {}", keyword))
+                }
+                term => self.define(term, value, protected, override_protected)?,
+            }
+        }
+        Ok(())
+    }
+    fn define(
+        &mut self,
+        term: &str,
+        value: &Value,
+        protected: bool,
+        override_protected: bool,
+    ) -> Result<(), String> {
+        let expand = |iri: &str| {
+            self.expand(iri, true)
+                .ok_or_else(|| format!("Invalid JSON-LD term definition {}", term))
+        };
+        let definition = match value {
+            Value::Null => Definition {
+                id: None,
+                type_mapping: None,
+                container: Vec::new(),
+                context: None,
+                protected,
+            },
+            Value::String(id) => Definition {
+                id: Some(expand(id)?),
+                type_mapping: None,
+                container: Vec::new(),
+                context: None,
+                protected,
+            },
+            Value::Object(definition) => {
+                if let Some(key) = definition.keys().find(|key| {
+                    !["@id", "@type", "@container", "@context", "@protected"]
+                        .contains(&key.as_str())
+                }) {
+                    return Err(format!(
+                        "Unsupported JSON-LD term definition {} of {}",
+                        key, term
+                    ));
+                }
+                Definition {
+                    id: match definition.get("@id") {
+                        Some(Value::Null) => None,
+                        Some(Value::String(id)) => Some(expand(id)?),
+                        None => Some(expand(term)?),
+                        Some(_) => return Err(format!("Invalid JSON-LD term definition {}", term)),
+                    },
+                    type_mapping: match definition.get("@type") {
+                        Some(Value::String(keyword)) if keyword.starts_with('@') => {
+                            Some(keyword.clone())
+                        }
+                        Some(Value::String(datatype)) => Some(expand(datatype)?),
+                        None => None,
+                        Some(_) => return Err(format!("Invalid JSON-LD term definition {}", term)),
+                    },
+                    container: match definition.get("@container") {
+                        Some(Value::String(container)) => vec![container.clone()],
+                        Some(Value::Array(containers)) => containers
+                            .iter()
+                            .map(|container| container.as_str().map(str::to_string))
+                            .collect::<Option<_>>()
+                            .ok_or_else(|| format!("Invalid JSON-LD term definition {}", term))?,
+                        None => Vec::new(),
+                        Some(_) => return Err(format!("Invalid JSON-LD term definition {}", term)),
+                    },
+                    context: definition.get("@context").cloned(),
+                    protected: definition
+                        .get("@protected")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(protected),
+                }
+            }
+            _ => return Err(format!("Invalid JSON-LD term definition {}", term)),
+        };
+        if let Some(existing) = self.terms.get(term) {
+            let redefined = Definition {
+                protected: existing.protected,
+                ..definition.clone()
+            } != *existing;
+            if existing.protected && redefined && !override_protected {
+                return Err(format!(
+                    "Protected JSON-LD term {} can't be redefined",
+                    term
+                ));
+            }
+        }
+        self.terms.insert(term.to_string(), definition);
+        Ok(())
+    }
+    /// Expands a term, compact IRI or IRI, returning None when expansion would drop it
+    ///
+    /// Terms and the vocabulary mapping only apply to `vocab` values, such as properties and types
+    fn expand(&self, value: &str, vocab: bool) -> Option<String> {
+        if value.starts_with('@') {
+            return KEYWORDS.contains(&value).then(|| value.to_string());
+        }
+        if let Some(definition) = self.terms.get(value).filter(|_| vocab) {
+            return definition.id.clone();
+        }
+        if let Some((prefix, suffix)) = value.split_once(':') {
+            if prefix == "_" || suffix.starts_with("//") {
+                return Some(value.to_string());
+            }
+            return match self
+                .terms
+                .get(prefix)
+                .and_then(|definition| definition.id.as_ref())
+            {
+                Some(prefix) => Some(format!("{}{}", prefix, suffix)),
+                None => Some(value.to_string()),
+            };
+        }
+        match vocab {
+            true => self
+                .vocab
+                .as_ref()
+                .map(|vocab| format!("{}{}", vocab, value)),
+            false => None,
+        }
+    }
+}
+
+/// Returns the node an expanded IRI names
+fn reference(expanded: Option<String>, value: &str) -> Result<Term, String> {
+    match expanded {
+        Some(iri) if !iri.starts_with('@') => Ok(
+            match iri
+                .strip_prefix("_:")
+                .or_else(|| iri.strip_prefix(SKOLEM_PREFIX))
+            {
+                Some(label) => Term::Blank(label.to_string()),
+                None => Term::Iri(iri),
+            },
+        ),
+        _ => Err(format!("{} is not an absolute IRI", value)),
+    }
+}
+
+fn integer(number: &Number) -> Option<String> {
+    match (number.as_i64(), number.as_u64(), number.as_f64()) {
+        (Some(integer), _, _) => Some(integer.to_string()),
+        (_, Some(integer), _) => Some(integer.to_string()),
+        (_, _, Some(float)) if float.fract() == 0.0 && float.abs() < 1e21 => {
+            Some(format!("{:.0}", float))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the canonical lexical form of an xsd:double, such as 1.5E0
+fn double(number: &Number) -> String {
+    static EXPONENT: OnceLock<Regex> = OnceLock::new();
+    EXPONENT
+        .get_or_init(|| Regex::new(r"(\d)0*E\+?").unwrap())
+        .replace(
+            &format!("{:.15E}", number.as_f64().unwrap_or_default()),
+            "${1}E",
+        )
+        .into_owned()
+}
+
+fn literal(value: &Value, datatype: Option<&str>) -> Result<Term, String> {
+    let (value, default_datatype) = match value {
+        Value::String(value) => (value.clone(), XSD_STRING),
+        Value::Bool(value) => (value.to_string(), XSD_BOOLEAN),
+        Value::Number(number) => match integer(number).filter(|_| datatype != Some(XSD_DOUBLE)) {
+            Some(integer) => (integer, XSD_INTEGER),
+            None => (double(number), XSD_DOUBLE),
+        },
+        _ => return Err("Invalid JSON-LD value".into()),
+    };
+    Ok(Term::Literal(
+        value,
+        datatype.unwrap_or(default_datatype).to_string(),
+    ))
+}
+
+fn json_literal(value: &Value) -> Term {
+    Term::Literal(sorted(value).to_string(), RDF_JSON.to_string())
+}
+
+/// Converts a value object, such as {"@value": "1", "@type": "xsd:integer"}
+fn value_object(object: &Map<String, Value>, context: &Context) -> Result<Option<Term>, String> {
+    let mut value = &Value::Null;
+    let mut datatype = None;
+    for (key, entry) in object {
+        match context.expand(key, true).as_deref() {
+            Some("@value") => value = entry,
+            Some("@type") => {
+                datatype = Some(
+                    entry
+                        .as_str()
+                        .and_then(|datatype| context.expand(datatype, true))
+                        .ok_or("Invalid JSON-LD value type")?,
+                )
+            }
+            _ => return Err(format!("Unsupported JSON-LD value object entry {}", key)),
+        }
+    }
+    match (value, datatype.as_deref()) {
+        (Value::Null, _) => Ok(None),
+        (value, Some("@json")) => Ok(Some(json_literal(value))),
+        (value, datatype) => literal(value, datatype).map(Some),
+    }
+}
+
+#[derive(Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Default)]
+struct Walker {
+    quads: Vec<Quad>,
+    seen: HashSet<Quad>,
+    blank_nodes: usize,
+    /// Paths to the nodes without an id
+    unnamed: Vec<Vec<Segment>>,
+}
+
+impl Walker {
+    fn walk(document: &Value) -> Result<Self, String> {
+        let mut walker = Walker::default();
+        let node = document
+            .as_object()
+            .ok_or("JSON-LD document is not an object")?;
+        walker.node(node, &Context::default(), None, &mut Vec::new())?;
+        Ok(walker)
+    }
+    fn push(&mut self, subject: &Term, predicate: &str, object: Term) {
+        let quad = Quad {
+            subject: subject.clone(),
+            predicate: predicate.to_string(),
+            object,
+        };
+        if self.seen.insert(quad.clone()) {
+            self.quads.push(quad);
+        }
+    }
+    /// Converts a node object with its property-scoped context, returning the node
+    fn node(
+        &mut self,
+        node: &Map<String, Value>,
+        active: &Context,
+        scoped: Option<&Value>,
+        path: &mut Vec<Segment>,
+    ) -> Result<Term, String> {
+        let only_id = node.len() == 1
+            && node
+                .keys()
+                .all(|key| active.expand(key, true).as_deref() == Some("@id"));
+        let mut context = match &active.previous {
+            Some(previous) if !only_id => (**previous).clone(),
+            _ => active.clone(),
+        };
+        if let Some(scoped) = scoped {
+            context = context.process(scoped, true, true)?;
+        }
+        if let Some(local) = node.get("@context") {
+            context = context.process(local, true, false)?;
+        }
+
+        // Type-scoped contexts apply in order of the types, which are looked up before any of them apply
+        let type_scoped = context.clone();
+        let mut type_keys: Vec<&String> = node
+            .keys()
+            .filter(|key| type_scoped.expand(key, true).as_deref() == Some("@type"))
+            .collect();
+        type_keys.sort();
+        let mut types = Vec::new();
+        for key in type_keys {
+            let mut values = match &node[key] {
+                Value::String(value) => vec![value.as_str()],
+                Value::Array(values) => values
+                    .iter()
+                    .map(Value::as_str)
+                    .collect::<Option<_>>()
+                    .ok_or("Invalid JSON-LD type")?,
+                _ => return Err("Invalid JSON-LD type".into()),
+            };
+            values.sort();
+            for value in values {
+                if let Some(local) = type_scoped
+                    .terms
+                    .get(value)
+                    .and_then(|definition| definition.context.as_ref())
+                {
+                    context = context.process(local, false, false)?;
+                }
+                types.push(reference(type_scoped.expand(value, true), value)?);
+            }
+        }
+
+        let subject = match node
+            .iter()
+            .find(|(key, _)| context.expand(key, true).as_deref() == Some("@id"))
+        {
+            Some((_, Value::String(id))) => reference(context.expand(id, false), id)?,
+            Some((_, Value::Null)) | None => {
+                self.unnamed.push(path.clone());
+                self.blank_nodes += 1;
+                Term::Blank(format!("_b{}", self.blank_nodes - 1))
+            }
+            Some(_) => return Err("Invalid JSON-LD id".into()),
+        };
+        for node_type in types {
+            self.push(&subject, RDF_TYPE, node_type);
+        }
+        for (key, value) in node {
+            let property = match context.expand(key, true) {
+                Some(keyword) if ["@context", "@id", "@type"].contains(&keyword.as_str()) => {
+                    continue
+                }
+                Some(keyword) if keyword.starts_with('@') => {
+                    return Err(format!("Unsupported JSON-LD keyword {}", keyword))
+                }
+                Some(property) if property.contains(':') && !property.starts_with("_:") => property,
+                _ => return Err(format!("{} is not defined by the JSON-LD context", key)),
+            };
+            let definition = context.terms.get(key);
+            if let Some(container) = definition
+                .iter()
+                .flat_map(|definition| &definition.container)
+                .find(|container| *container != "@set")
+            {
+                return Err(format!(
+                    "Unsupported JSON-LD container {} of {}",
+                    container, key
+                ));
+            }
+            path.push(Segment::Key(key.clone()));
+            self.values(&subject, &property, value, definition, &context, path)?;
+            path.pop();
+        }
+        Ok(subject)
+    }
+    fn values(
+        &mut self,
+        subject: &Term,
+        property: &str,
+        value: &Value,
+        definition: Option<&Definition>,
+        context: &Context,
+        path: &mut Vec<Segment>,
+    ) -> Result<(), String> {
+        let type_mapping = definition.and_then(|definition| definition.type_mapping.as_deref());
+        let scoped = definition.and_then(|definition| definition.context.as_ref());
+        if type_mapping == Some("@json") {
+            self.push(subject, property, json_literal(value));
+            return Ok(());
+        }
+        match value {
+            Value::Null => {}
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    path.push(Segment::Index(index));
+                    self.values(subject, property, item, definition, context, path)?;
+                    path.pop();
+                }
+            }
+            Value::Object(object)
+                if object
+                    .keys()
+                    .any(|key| context.expand(key, true).as_deref() == Some("@value")) =>
+            {
+                if let Some(object) = value_object(object, context)? {
+                    self.push(subject, property, object);
+                }
+            }
+            Value::Object(object) => {
+                let object = self.node(object, context, scoped, path)?;
+                self.push(subject, property, object);
+            }
+            value => {
+                let context = match scoped {
+                    Some(scoped) => context.process(scoped, true, true)?,
+                    None => context.clone(),
+                };
+                let object = match (value, type_mapping) {
+                    (Value::String(id), Some("@id")) => reference(context.expand(id, false), id)?,
+                    (Value::String(id), Some("@vocab")) => reference(context.expand(id, true), id)?,
+                    (value, Some(datatype)) if !datatype.starts_with('@') => {
+                        literal(value, Some(datatype))?
+                    }
+                    (value, _) => literal(value, None)?,
+                };
+                self.push(subject, property, object);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts a JSON-LD document to RDF, as the JSON-LD to RDF algorithm
+pub(crate) fn to_rdf(document: &Value) -> Result<Vec<Quad>, String> {
+    Ok(Walker::walk(document)?.quads)
+}
+
+/// Names every node of a JSON-LD document without an id with a skolem IRI, so a selection of the document names
+/// the same blank nodes as the whole of it
+pub(crate) fn skolemize(document: &Value) -> Result<Value, String> {
+    let walker = Walker::walk(document)?;
+    let mut skolemized = document.clone();
+    for (index, path) in walker.unnamed.iter().enumerate() {
+        path.iter()
+            .try_fold(&mut skolemized, |value, segment| match segment {
+                Segment::Key(key) => value.get_mut(key),
+                Segment::Index(index) => value.get_mut(index),
+            })
+            .and_then(Value::as_object_mut)
+            .ok_or("Invalid JSON-LD document")?
+            .insert(
+                "id".to_string(),
+                Value::String(format!("{}_{}", SKOLEM_PREFIX, index)),
+            );
+    }
+    Ok(skolemized)
+}
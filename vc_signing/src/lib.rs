@@ -1,3 +1,5 @@
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use base64::Engine;
 use chrono::{DateTime, Utc};
 use ring::signature::{Ed25519KeyPair, KeyPair};
 use serde::{Deserialize, Serialize};
@@ -5,10 +7,35 @@ use serde_json::Value;
 use url::Url;
 #[cfg(target_family = "wasm")]
 use wasm_bindgen::prelude::wasm_bindgen;
+#[cfg(feature = "bbs")]
+pub mod bbs;
 #[cfg(feature = "cbor")]
 pub mod cbor;
+#[cfg(feature = "cbor")]
+pub mod cbor_ld;
+pub mod compiled_schema;
+#[cfg(feature = "cbor")]
+pub mod cose;
+pub mod did;
+pub mod ecdsa_sd;
+pub mod format;
+pub mod jose;
+mod jsonld;
 #[cfg(feature = "protobuf")]
 pub mod protobuf;
+#[cfg(feature = "protobuf")]
+pub mod protobuf_schema;
+#[cfg(feature = "qr")]
+pub mod qr;
+mod rdfc;
+mod sample;
+pub mod schema;
+pub mod schema_diff;
+pub mod schema_registry;
+pub mod sd_jwt;
+mod selective_disclosure;
+#[cfg(not(target_family = "wasm"))]
+pub mod typed;
 pub mod verifiable_credential;
 pub mod verifiable_presentation;
 
@@ -28,7 +55,7 @@ pub struct VerifiablePresentation {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(untagged)]
 enum VerifiableCredentialEnum {
-    Single(VerifiableCredential),
+    Single(Box<VerifiableCredential>),
     Multiple(Vec<VerifiableCredential>),
 }
 
@@ -92,6 +119,8 @@ struct CredentialSchema {
     id: Url,
     #[serde(rename = "type")]
     credential_type: String,
+    #[serde(rename = "digestSRI", skip_serializing_if = "Option::is_none")]
+    digest_sri: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -104,6 +133,36 @@ pub struct Proof {
     proof_purpose: String,
     #[serde(rename = "proofValue")]
     proof_value: String,
+    #[serde(rename = "verificationMethod", skip_serializing_if = "Option::is_none")]
+    verification_method: Option<String>,
+}
+
+impl Proof {
+    /// Decodes the proof value, multibase base64url for selective disclosure cryptosuites and base64 otherwise
+    pub(crate) fn proof_value_bytes(&self) -> Result<Vec<u8>, String> {
+        match self.proof_value.strip_prefix('u') {
+            Some(proof_value) if Self::is_multibase(&self.cryptosuite) => {
+                BASE64_URL_SAFE_NO_PAD.decode(proof_value)
+            }
+            _ => BASE64_STANDARD.decode(&self.proof_value),
+        }
+        .map_err(|e| e.to_string())
+    }
+    /// Encodes a proof value the way the cryptosuite expects
+    pub(crate) fn encode_proof_value(cryptosuite: &str, proof_value: &[u8]) -> String {
+        match Self::is_multibase(cryptosuite) {
+            true => format!("u{}", BASE64_URL_SAFE_NO_PAD.encode(proof_value)),
+            false => BASE64_STANDARD.encode(proof_value),
+        }
+    }
+    /// Whether the cryptosuite's proof values are multibase encoded
+    fn is_multibase(cryptosuite: &str) -> bool {
+        #[cfg(feature = "bbs")]
+        if cryptosuite == bbs::BBS_CRYPTOSUITE {
+            return true;
+        }
+        cryptosuite == ecdsa_sd::ECDSA_SD_CRYPTOSUITE
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -137,6 +196,31 @@ impl SignatureKeyPair {
             public_key,
         })
     }
+    #[cfg(feature = "bbs")]
+    /// Generates a new random BLS12-381 key pair, for BBS selective disclosure proofs
+    ///
+    /// The private key is a big-endian scalar and the public key a compressed G2 point
+    pub fn new_bls12_381() -> Result<Self, String> {
+        let (private_key, public_key) = bbs::generate_key_pair()?;
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+    /// Generates a new random P-256 key pair, for ECDSA selective disclosure proofs
+    ///
+    /// The private key is a PKCS#8 document and the public key an uncompressed point
+    pub fn new_p256() -> Result<Self, String> {
+        let (private_key, public_key) = ecdsa_sd::generate_key_pair()?;
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+    /// Returns the did:key identifier of the public key
+    pub fn did_key(&self) -> Result<String, String> {
+        Ok(did::did_key_from_public_key(&self.public_key)?.to_string())
+    }
     #[cfg(target_family = "wasm")]
     /// Returns a copy of the public key
     pub fn public_key(&self) -> Vec<u8> {
@@ -1,8 +1,10 @@
+use crate::format::{envelope_kind, read_envelope, write_envelope, EnvelopeKind};
 use crate::protobuf::verifiable_credentials::verifiable_presentation::RepeatedCredential;
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
-use chrono::DateTime;
+use crate::protobuf_schema::SubjectDescriptor;
+use chrono::{DateTime, Utc};
 use prost::Message;
+use prost_types::{value::Kind, ListValue, Struct};
+use serde_json::Value;
 use std::str::FromStr;
 use url::Url;
 use verifiable_credentials::type_struct::{OneofType, RepeatedType};
@@ -13,81 +15,286 @@ use verifiable_credentials::verifiable_credential::{
 use verifiable_credentials::verifiable_presentation;
 use verifiable_credentials::{TypeStruct, VerifiableCredential, VerifiablePresentation};
 
+#[allow(clippy::large_enum_variant)]
 pub mod verifiable_credentials {
     include!(concat!(env!("OUT_DIR"), "/verifiable_credentials.rs"));
 }
 
+/// Legacy credentialSubject encoding, a json string in a `google.protobuf.Any`
+const LEGACY_SUBJECT_ENCODING: u32 = 0;
+/// credentialSubject encoding as a `google.protobuf.Value`
+const VALUE_SUBJECT_ENCODING: u32 = 1;
+/// credentialSubject encoding with a message derived from its json schema
+const COMPACT_SUBJECT_ENCODING: u32 = 2;
+/// credentialSubject encoding as a `google.protobuf.Value`, with some strings tagged by `to_protobuf_value`
+const TAGGED_SUBJECT_ENCODING: u32 = 3;
+/// Character starting a tagged string in a `google.protobuf.Value`
+const TAG: char = '\0';
+/// Version of verifiable_credentials.proto written in the envelope of serialized documents
+const PROTOBUF_VERSION: u8 = 1;
+/// Largest integer a `google.protobuf.Value` number holds exactly
+pub(crate) const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+/// Converts json to a `google.protobuf.Value`
+///
+/// Numbers that wouldn't decode back to the same json, such as `1.0` or integers beyond 2^53, are written as their
+/// json text in a string starting with [`TAG`], since signatures cover the json text. Strings that already start
+/// with it are escaped with a second one. `tagged` is set when any string is tagged
+fn to_protobuf_value(value: &Value, tagged: &mut bool) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(bool) => Kind::BoolValue(*bool),
+        Value::Number(number) => match (number.as_i64(), number.as_u64(), number.as_f64()) {
+            (Some(integer), _, _) if (integer as f64).abs() <= MAX_SAFE_INTEGER => {
+                Kind::NumberValue(integer as f64)
+            }
+            (None, Some(integer), _) if integer as f64 <= MAX_SAFE_INTEGER => {
+                Kind::NumberValue(integer as f64)
+            }
+            (None, None, Some(float)) if float.fract() != 0.0 => Kind::NumberValue(float),
+            _ => {
+                *tagged = true;
+                Kind::StringValue(format!("{}{}", TAG, number))
+            }
+        },
+        Value::String(string) if string.starts_with(TAG) => {
+            *tagged = true;
+            Kind::StringValue(format!("{}{}", TAG, string))
+        }
+        Value::String(string) => Kind::StringValue(string.clone()),
+        Value::Array(array) => Kind::ListValue(ListValue {
+            values: array
+                .iter()
+                .map(|value| to_protobuf_value(value, tagged))
+                .collect(),
+        }),
+        Value::Object(object) => Kind::StructValue(Struct {
+            fields: object
+                .iter()
+                .map(|(key, value)| (key.clone(), to_protobuf_value(value, tagged)))
+                .collect(),
+        }),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// Converts a `google.protobuf.Value` to json, with integral numbers as integers
+///
+/// With `tagged`, strings starting with [`TAG`] are decoded as written by `to_protobuf_value`
+fn from_protobuf_value(value: prost_types::Value, tagged: bool) -> Result<Value, String> {
+    Ok(match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(bool)) => Value::Bool(bool),
+        Some(Kind::NumberValue(number))
+            if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER =>
+        {
+            Value::from(number as i64)
+        }
+        Some(Kind::NumberValue(number)) => Value::from(number),
+        Some(Kind::StringValue(string)) => match string.strip_prefix(TAG) {
+            Some(escaped) if tagged && escaped.starts_with(TAG) => Value::String(escaped.into()),
+            Some(number) if tagged => Value::Number(
+                serde_json::from_str(number)
+                    .map_err(|e| format!("Invalid tagged number: {}", e))?,
+            ),
+            _ => Value::String(string),
+        },
+        Some(Kind::ListValue(list)) => Value::Array(
+            list.values
+                .into_iter()
+                .map(|value| from_protobuf_value(value, tagged))
+                .collect::<Result<_, _>>()?,
+        ),
+        Some(Kind::StructValue(object)) => Value::Object(
+            object
+                .fields
+                .into_iter()
+                .map(|(key, value)| Ok((key, from_protobuf_value(value, tagged)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+/// Parses a url decoded from protobuf
+fn url(url: &str) -> Result<Url, String> {
+    Url::from_str(url).map_err(|e| format!("{}: {}", url, e))
+}
+
+/// Converts a protobuf timestamp to a date
+fn timestamp(timestamp: prost_types::Timestamp) -> Result<DateTime<Utc>, String> {
+    DateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32)
+        .ok_or_else(|| "Invalid timestamp".into())
+}
+
+/// Converts a protobuf type, either a single type or a list of types
+fn type_enum(type_struct: Option<TypeStruct>, field: &str) -> Result<crate::TypeEnum, String> {
+    match type_struct {
+        Some(TypeStruct {
+            oneof_type: Some(OneofType::SingleType(single_type)),
+        }) => Ok(crate::TypeEnum::Single(single_type)),
+        Some(TypeStruct {
+            oneof_type: Some(OneofType::MultipleType(RepeatedType { repeated_type })),
+        }) => Ok(crate::TypeEnum::Multiple(repeated_type)),
+        _ => Err(format!("Error in {}", field)),
+    }
+}
+
+/// Converts a protobuf proof, with its proof value encoded as the cryptosuite expects
+fn proof(proof: verifiable_credentials::Proof) -> Result<crate::Proof, String> {
+    Ok(crate::Proof {
+        proof_type: proof.proof_type,
+        created: timestamp(proof.created.ok_or("Missing proof created")?)?,
+        proof_value: crate::Proof::encode_proof_value(&proof.cryptosuite, &proof.proof_value),
+        cryptosuite: proof.cryptosuite,
+        proof_purpose: proof.proof_purpose,
+        verification_method: Some(proof.verification_method).filter(|method| !method.is_empty()),
+    })
+}
+
+/// Converts a protobuf credentialSchema entry
+fn credential_schema(schema: CredentialSchemaStruct) -> Result<crate::CredentialSchema, String> {
+    Ok(crate::CredentialSchema {
+        id: url(&schema.schema_id)?,
+        credential_type: schema.schema_type,
+        digest_sri: schema.digest_sri,
+    })
+}
+
+/// Converts a protobuf credentialStatus entry
+fn credential_status(status: CredentialStatusStruct) -> Result<crate::CredentialStatus, String> {
+    Ok(crate::CredentialStatus {
+        id: status.status_id.as_deref().map(url).transpose()?,
+        status_type: type_enum(status.status_type, "credential status")?,
+    })
+}
+
 impl crate::VerifiablePresentation {
-    /// Serializes a VerifiablePresentation structure into protobuf
+    /// Serializes a VerifiablePresentation structure into protobuf, in a versioned envelope
     pub fn serialize_protobuf(self) -> Vec<u8> {
-        Into::<VerifiablePresentation>::into(self).encode_to_vec()
+        write_envelope(
+            EnvelopeKind::PresentationProtobuf,
+            PROTOBUF_VERSION,
+            Into::<VerifiablePresentation>::into(self).encode_to_vec(),
+        )
+    }
+    /// Deserializes protobuf, with or without a versioned envelope, into a VerifiablePresentation structure
+    pub fn deserialize_protobuf(reader: Vec<u8>) -> Result<Self, String> {
+        let reader = read_envelope(
+            &reader,
+            EnvelopeKind::PresentationProtobuf,
+            PROTOBUF_VERSION,
+        )?;
+        VerifiablePresentation::decode(reader.as_ref())
+            .map_err(|e| e.to_string())?
+            .try_into()
     }
-    /// Deserializes protobuf into a VerifiablePresentation structure
-    pub fn deserialize_protobuf(reader: Vec<u8>) -> Result<Self, prost::DecodeError> {
-        Ok(Into::<Self>::into(VerifiablePresentation::decode(
-            reader.as_slice(),
-        )?))
+    /// Verifies a protobuf VerifiablePresentation was signed by the owner of the given public key, returning it decoded
+    pub fn verify_protobuf(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vp = Self::deserialize_protobuf(reader)?;
+        vp.verify(public_key)?;
+        Ok(vp)
     }
 }
 
 impl crate::VerifiableCredential {
-    /// Serializes a VerifiableCredential structure into protobuf
+    /// Serializes a VerifiableCredential structure into protobuf, in a versioned envelope
     pub fn serialize_protobuf(self) -> Vec<u8> {
-        Into::<VerifiableCredential>::into(self).encode_to_vec()
+        write_envelope(
+            EnvelopeKind::CredentialProtobuf,
+            PROTOBUF_VERSION,
+            Into::<VerifiableCredential>::into(self).encode_to_vec(),
+        )
     }
-    /// Deserializes protobuf into a VerifiableCredential structure
-    pub fn deserialize_protobuf(reader: Vec<u8>) -> Result<Self, prost::DecodeError> {
-        Ok(Into::<Self>::into(VerifiableCredential::decode(
-            reader.as_slice(),
-        )?))
+    /// Deserializes protobuf, with or without a versioned envelope, into a VerifiableCredential structure
+    pub fn deserialize_protobuf(reader: Vec<u8>) -> Result<Self, String> {
+        let reader = read_envelope(&reader, EnvelopeKind::CredentialProtobuf, PROTOBUF_VERSION)?;
+        VerifiableCredential::decode(reader.as_ref())
+            .map_err(|e| e.to_string())?
+            .try_into()
+    }
+    /// Verifies a protobuf VerifiableCredential was signed by the owner of the given public key, returning it decoded
+    pub fn verify_protobuf(reader: Vec<u8>, public_key: &[u8]) -> Result<Self, String> {
+        let vc = Self::deserialize_protobuf(reader)?;
+        vc.verify(public_key)?;
+        Ok(vc)
+    }
+    /// Serializes a VerifiableCredential structure into protobuf, encoding credentialSubject with a message
+    /// derived from its schema
+    ///
+    /// Fails if the derived message can't reproduce the subject exactly
+    pub fn serialize_protobuf_compact(
+        self,
+        descriptor: &SubjectDescriptor,
+    ) -> Result<Vec<u8>, String> {
+        let subject_compact = descriptor.encode(&self.credential_subject)?;
+        let mut vc = Into::<VerifiableCredential>::into(self);
+        vc.credential_subject = None;
+        vc.subject_value = None;
+        vc.subject_compact = subject_compact;
+        vc.subject_encoding = COMPACT_SUBJECT_ENCODING;
+        Ok(write_envelope(
+            EnvelopeKind::CredentialCompactProtobuf,
+            PROTOBUF_VERSION,
+            vc.encode_to_vec(),
+        ))
+    }
+    /// Deserializes protobuf into a VerifiableCredential structure, decoding a compact credentialSubject with
+    /// the message derived from its schema
+    pub fn deserialize_protobuf_compact(
+        reader: Vec<u8>,
+        descriptor: &SubjectDescriptor,
+    ) -> Result<Self, String> {
+        let kind = match envelope_kind(&reader)? {
+            Some(EnvelopeKind::CredentialCompactProtobuf) => {
+                EnvelopeKind::CredentialCompactProtobuf
+            }
+            _ => EnvelopeKind::CredentialProtobuf,
+        };
+        let reader = read_envelope(&reader, kind, PROTOBUF_VERSION)?;
+        let vc = VerifiableCredential::decode(reader.as_ref()).map_err(|e| e.to_string())?;
+        if vc.subject_encoding != COMPACT_SUBJECT_ENCODING {
+            return vc.try_into();
+        }
+        let credential_subject = descriptor.decode(&vc.subject_compact)?;
+        credential(vc, credential_subject)
     }
 }
 
-impl From<VerifiablePresentation> for crate::VerifiablePresentation {
-    fn from(vp: VerifiablePresentation) -> Self {
-        let id = vp.vp_id.map(|id| Url::from_str(&id).unwrap());
+impl TryFrom<VerifiablePresentation> for crate::VerifiablePresentation {
+    type Error = String;
 
-        let vp_type = match vp.vp_type.unwrap() {
-            TypeStruct {
-                oneof_type: Some(OneofType::SingleType(string)),
-            } => crate::TypeEnum::Single(string),
-            TypeStruct {
-                oneof_type: Some(OneofType::MultipleType(RepeatedType { repeated_type })),
-            } => crate::TypeEnum::Multiple(repeated_type),
-            _ => panic!("Error in presentation type"),
-        };
+    fn try_from(vp: VerifiablePresentation) -> Result<Self, Self::Error> {
+        let id = vp.vp_id.as_deref().map(url).transpose()?;
 
-        let verifiable_credential = match vp.verifiable_credential.unwrap() {
+        let vp_type = type_enum(vp.vp_type, "presentation type")?;
+
+        let verifiable_credential = match vp
+            .verifiable_credential
+            .ok_or("Missing verifiableCredential")?
+        {
             verifiable_presentation::VerifiableCredential::SingleVc(vc) => {
-                crate::VerifiableCredentialEnum::Single(vc.into())
+                crate::VerifiableCredentialEnum::Single(Box::new(vc.try_into()?))
             }
             verifiable_presentation::VerifiableCredential::MultipleVc(RepeatedCredential {
                 repeated_vc,
             }) => crate::VerifiableCredentialEnum::Multiple(
-                repeated_vc.into_iter().map(|vc| vc.into()).collect(),
+                repeated_vc
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
             ),
         };
 
-        let holder = vp.holder.map(|holder| Url::from_str(&holder).unwrap());
-        let proof = vp.proof.map(|proof| crate::Proof {
-            proof_type: proof.proof_type,
-            created: DateTime::from_timestamp(
-                proof.created.unwrap().seconds,
-                proof.created.unwrap().nanos as u32,
-            )
-            .unwrap(),
-            cryptosuite: proof.cryptosuite,
-            proof_purpose: proof.proof_purpose,
-            proof_value: BASE64_STANDARD.encode(proof.proof_value),
-        });
+        let holder = vp.holder.as_deref().map(url).transpose()?;
+        let proof = vp.proof.map(proof).transpose()?;
 
-        Self {
+        Ok(Self {
             id,
             vp_type,
             verifiable_credential,
             holder,
             proof,
-        }
+        })
     }
 }
 
@@ -106,7 +313,7 @@ impl From<crate::VerifiablePresentation> for VerifiablePresentation {
 
         let verifiable_credential = match vp.verifiable_credential {
             crate::VerifiableCredentialEnum::Single(vc) => Some(
-                verifiable_presentation::VerifiableCredential::SingleVc(vc.into()),
+                verifiable_presentation::VerifiableCredential::SingleVc((*vc).into()),
             ),
             crate::VerifiableCredentialEnum::Multiple(vec) => Some(
                 verifiable_presentation::VerifiableCredential::MultipleVc(RepeatedCredential {
@@ -118,11 +325,12 @@ impl From<crate::VerifiablePresentation> for VerifiablePresentation {
         let holder = vp.holder.map(|holder| holder.to_string());
 
         let proof = vp.proof.map(|proof| verifiable_credentials::Proof {
+            proof_value: proof.proof_value_bytes().unwrap(),
             proof_type: proof.proof_type,
             created: Some(prost_types::Timestamp::from_str(&proof.created.to_rfc3339()).unwrap()),
             cryptosuite: proof.cryptosuite,
             proof_purpose: proof.proof_purpose,
-            proof_value: BASE64_STANDARD.decode(proof.proof_value).unwrap(),
+            verification_method: proof.verification_method.unwrap_or_default(),
         });
 
         Self {
@@ -135,129 +343,92 @@ impl From<crate::VerifiablePresentation> for VerifiablePresentation {
     }
 }
 
-impl From<VerifiableCredential> for crate::VerifiableCredential {
-    fn from(vc: VerifiableCredential) -> Self {
-        let context = vc
-            .context
-            .iter()
-            .map(|context| Url::from_str(context).unwrap())
-            .collect();
+impl TryFrom<VerifiableCredential> for crate::VerifiableCredential {
+    type Error = String;
 
-        let credential_schema = match vc.credential_schema.unwrap() {
-            CredentialSchema::MultipleSchema(RepeatedCredentialSchema { repeated_schema }) => {
-                crate::SchemaEnum::Multiple(
-                    repeated_schema
-                        .iter()
-                        .map(|schema| crate::CredentialSchema {
-                            id: Url::from_str(&schema.schema_id).unwrap(),
-                            credential_type: schema.schema_type.clone(),
-                        })
-                        .collect(),
-                )
+    fn try_from(mut vc: VerifiableCredential) -> Result<Self, Self::Error> {
+        let credential_subject = match (vc.subject_encoding, vc.subject_value.take()) {
+            (COMPACT_SUBJECT_ENCODING, _) => {
+                return Err("Compact credentialSubject needs its schema to be decoded".into())
+            }
+            (LEGACY_SUBJECT_ENCODING, _) | (_, None) => {
+                let prost_types::Any { type_url: _, value } = vc
+                    .credential_subject
+                    .take()
+                    .ok_or("Missing credentialSubject")?;
+                let credential_subject: String =
+                    Message::decode(value.as_slice()).map_err(|e| e.to_string())?;
+                serde_json::from_str(&credential_subject).map_err(|e| e.to_string())?
+            }
+            (encoding, Some(subject_value)) => {
+                from_protobuf_value(subject_value, encoding == TAGGED_SUBJECT_ENCODING)?
             }
-            CredentialSchema::SingleSchema(CredentialSchemaStruct {
-                schema_id,
-                schema_type,
-            }) => crate::SchemaEnum::Single(crate::CredentialSchema {
-                id: Url::from_str(&schema_id).unwrap(),
-                credential_type: schema_type,
-            }),
         };
 
-        let credential_status =
-            vc.credential_status
-                .map(|credential_status| match credential_status {
-                    CredentialStatus::MultipleStatus(RepeatedCredentialStatus {
-                        repeated_status,
-                    }) => crate::StatusEnum::Multiple(
-                        repeated_status
-                            .iter()
-                            .map(|status| crate::CredentialStatus {
-                                id: status
-                                    .status_id
-                                    .clone()
-                                    .map(|id| Url::from_str(&id).unwrap()),
-                                status_type: match status.status_type.clone().unwrap() {
-                                    TypeStruct {
-                                        oneof_type: Some(OneofType::SingleType(single_type)),
-                                    } => crate::TypeEnum::Single(single_type),
-                                    TypeStruct {
-                                        oneof_type:
-                                            Some(OneofType::MultipleType(RepeatedType {
-                                                repeated_type,
-                                            })),
-                                    } => crate::TypeEnum::Multiple(repeated_type),
-                                    _ => panic!("Error in credential status"),
-                                },
-                            })
-                            .collect(),
-                    ),
-                    CredentialStatus::SingleStatus(CredentialStatusStruct {
-                        status_type,
-                        status_id,
-                    }) => crate::StatusEnum::Single(crate::CredentialStatus {
-                        id: status_id.map(|id| Url::from_str(&id).unwrap()),
-                        status_type: match status_type.unwrap() {
-                            TypeStruct {
-                                oneof_type: Some(OneofType::SingleType(single_type)),
-                            } => crate::TypeEnum::Single(single_type),
-                            TypeStruct {
-                                oneof_type:
-                                    Some(OneofType::MultipleType(RepeatedType { repeated_type })),
-                            } => crate::TypeEnum::Multiple(repeated_type),
-                            _ => panic!("Error in credential status"),
-                        },
-                    }),
-                });
-
-        let prost_types::Any { type_url: _, value } = vc.credential_subject.unwrap();
-        let credential_subject: String = Message::decode(value.as_slice()).unwrap();
-        let credential_subject = serde_json::from_str(&credential_subject).unwrap();
+        credential(vc, credential_subject)
+    }
+}
 
-        let valid_from = vc
-            .valid_from
-            .map(|x| DateTime::from_timestamp(x.seconds, x.nanos as u32).unwrap());
-        let valid_until = vc
-            .valid_until
-            .map(|x| DateTime::from_timestamp(x.seconds, x.nanos as u32).unwrap());
+/// Converts a protobuf credential, with its credentialSubject already decoded
+fn credential(
+    vc: VerifiableCredential,
+    credential_subject: Value,
+) -> Result<crate::VerifiableCredential, String> {
+    let context = vc
+        .context
+        .iter()
+        .map(|context| url(context))
+        .collect::<Result<_, _>>()?;
 
-        let proof = vc.proof.map(|proof| crate::Proof {
-            proof_type: proof.proof_type,
-            created: DateTime::from_timestamp(
-                proof.created.unwrap().seconds,
-                proof.created.unwrap().nanos as u32,
+    let credential_schema = match vc.credential_schema.ok_or("Missing credentialSchema")? {
+        CredentialSchema::MultipleSchema(RepeatedCredentialSchema { repeated_schema }) => {
+            crate::SchemaEnum::Multiple(
+                repeated_schema
+                    .into_iter()
+                    .map(credential_schema)
+                    .collect::<Result<_, _>>()?,
             )
-            .unwrap(),
-            cryptosuite: proof.cryptosuite,
-            proof_purpose: proof.proof_purpose,
-            proof_value: BASE64_STANDARD.encode(proof.proof_value),
-        });
+        }
+        CredentialSchema::SingleSchema(schema) => {
+            crate::SchemaEnum::Single(credential_schema(schema)?)
+        }
+    };
 
-        let vc_type = match vc.vc_type.unwrap() {
-            TypeStruct {
-                oneof_type: Some(OneofType::SingleType(vc_type)),
-            } => crate::TypeEnum::Single(vc_type),
-            TypeStruct {
-                oneof_type: Some(OneofType::MultipleType(RepeatedType { repeated_type })),
-            } => crate::TypeEnum::Multiple(repeated_type),
-            _ => panic!("Error in vc type"),
-        };
+    let credential_status = vc
+        .credential_status
+        .map(|status| match status {
+            CredentialStatus::MultipleStatus(RepeatedCredentialStatus { repeated_status }) => {
+                repeated_status
+                    .into_iter()
+                    .map(credential_status)
+                    .collect::<Result<_, _>>()
+                    .map(crate::StatusEnum::Multiple)
+            }
+            CredentialStatus::SingleStatus(status) => {
+                credential_status(status).map(crate::StatusEnum::Single)
+            }
+        })
+        .transpose()?;
 
-        Self {
-            context,
-            credential_schema,
-            credential_status,
-            credential_subject,
-            description: vc.description,
-            id: vc.vc_id.map(|id| Url::from_str(&id).unwrap()),
-            issuer: Url::from_str(&vc.issuer).unwrap(),
-            name: vc.name,
-            proof,
-            valid_from,
-            valid_until,
-            vc_type,
-        }
-    }
+    let valid_from = vc.valid_from.map(timestamp).transpose()?;
+    let valid_until = vc.valid_until.map(timestamp).transpose()?;
+    let proof = vc.proof.map(proof).transpose()?;
+    let vc_type = type_enum(vc.vc_type, "vc type")?;
+
+    Ok(crate::VerifiableCredential {
+        context,
+        credential_schema,
+        credential_status,
+        credential_subject,
+        description: vc.description,
+        id: vc.vc_id.as_deref().map(url).transpose()?,
+        issuer: url(&vc.issuer)?,
+        name: vc.name,
+        proof,
+        valid_from,
+        valid_until,
+        vc_type,
+    })
 }
 
 impl From<crate::VerifiableCredential> for VerifiableCredential {
@@ -292,6 +463,7 @@ impl From<crate::VerifiableCredential> for VerifiableCredential {
                 Some(CredentialSchema::SingleSchema(CredentialSchemaStruct {
                     schema_id: credential_schema.id.to_string(),
                     schema_type: credential_schema.credential_type,
+                    digest_sri: credential_schema.digest_sri,
                 }))
             }
             crate::SchemaEnum::Multiple(credential_schema) => {
@@ -301,16 +473,19 @@ impl From<crate::VerifiableCredential> for VerifiableCredential {
                         .map(|schema| CredentialSchemaStruct {
                             schema_id: schema.id.to_string(),
                             schema_type: schema.credential_type.clone(),
+                            digest_sri: schema.digest_sri.clone(),
                         })
                         .collect(),
                 }))
             }
         };
 
-        let credential_subject = Some(prost_types::Any {
-            type_url: "Value.to_string()".to_string(),
-            value: vc.credential_subject.to_string().encode_to_vec(),
-        });
+        let mut tagged = false;
+        let subject_value = to_protobuf_value(&vc.credential_subject, &mut tagged);
+        let subject_encoding = match tagged {
+            true => TAGGED_SUBJECT_ENCODING,
+            false => VALUE_SUBJECT_ENCODING,
+        };
 
         let credential_status =
             vc.credential_status
@@ -355,11 +530,12 @@ impl From<crate::VerifiableCredential> for VerifiableCredential {
                 });
 
         let proof = vc.proof.map(|proof| verifiable_credentials::Proof {
+            proof_value: proof.proof_value_bytes().unwrap(),
             proof_type: proof.proof_type,
             created: Some(prost_types::Timestamp::from_str(&proof.created.to_rfc3339()).unwrap()),
             cryptosuite: proof.cryptosuite,
             proof_purpose: proof.proof_purpose,
-            proof_value: BASE64_STANDARD.decode(proof.proof_value).unwrap(),
+            verification_method: proof.verification_method.unwrap_or_default(),
         });
 
         Self {
@@ -370,8 +546,11 @@ impl From<crate::VerifiableCredential> for VerifiableCredential {
             valid_from,
             valid_until,
             credential_schema,
-            credential_subject,
+            credential_subject: None,
             proof,
+            subject_value: Some(subject_value),
+            subject_encoding,
+            subject_compact: Vec::new(),
             vc_id,
             issuer: vc.issuer.to_string(),
             credential_status,
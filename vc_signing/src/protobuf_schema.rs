@@ -0,0 +1,663 @@
+use crate::protobuf::MAX_SAFE_INTEGER;
+use crate::VerifiableCredential;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, Kind, Value as ReflectValue};
+use prost_types::descriptor_proto::ReservedRange;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    OneofDescriptorProto,
+};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Json schema keyword recording the protobuf field number of a property
+pub const FIELD_KEYWORD: &str = "x-protobuf-field";
+/// Json schema keyword listing the field numbers of removed properties, so they are never reused
+pub const RESERVED_KEYWORD: &str = "x-protobuf-reserved";
+/// Field holding the properties a schema doesn't list, as a json object
+const EXTRA_FIELD: u32 = 536_870_911;
+/// Largest field number left for properties
+const MAX_FIELD: u32 = EXTRA_FIELD - 1;
+/// Field numbers reserved by the protobuf implementation
+const IMPLEMENTATION_RESERVED: std::ops::RangeInclusive<u32> = 19000..=19999;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// Any value a schema doesn't pin to a single protobuf type, as json text
+    Json,
+}
+
+#[derive(Clone, Debug)]
+enum FieldType {
+    Scalar(Scalar),
+    Message(MessageSchema),
+}
+
+#[derive(Clone, Debug)]
+struct FieldSchema {
+    /// Json property name
+    name: String,
+    /// Protobuf field name, unique within its message
+    proto_name: String,
+    number: u32,
+    repeated: bool,
+    field_type: FieldType,
+}
+
+#[derive(Clone, Debug)]
+struct MessageSchema {
+    /// Protobuf message name, unique within its parent message
+    name: String,
+    fields: Vec<FieldSchema>,
+    reserved: BTreeSet<u32>,
+    /// Name of the field holding the properties the schema doesn't list
+    extra_name: String,
+}
+
+/// Protobuf message derived from the json schema of a schema credential, for compactly encoding credential subjects
+///
+/// Properties are numbered by their `x-protobuf-field` keyword, see [`annotate_field_numbers`] to keep numbers
+/// stable as a schema evolves. Unannotated properties are numbered in name order after the annotated ones.
+#[derive(Clone, Debug)]
+pub struct SubjectDescriptor {
+    root: MessageSchema,
+    message: prost_reflect::MessageDescriptor,
+}
+
+impl SubjectDescriptor {
+    /// Derives the credentialSubject message of a schema credential
+    pub fn new(schema: &VerifiableCredential) -> Result<Self, String> {
+        let json_schema = schema.json_schema()?;
+        let mut builder = Builder {
+            root: json_schema,
+            in_progress: Vec::new(),
+        };
+        let FieldType::Message(root) = builder.single(json_schema, "Subject")? else {
+            return Err("Subject schema must be an object with properties".into());
+        };
+        let file = FileDescriptorProto {
+            name: Some("subject.proto".into()),
+            syntax: Some("proto3".into()),
+            message_type: vec![root.descriptor_proto("")],
+            ..Default::default()
+        };
+        let message =
+            DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: vec![file] })
+                .map_err(|e| e.to_string())?
+                .get_message_by_name(&root.name)
+                .ok_or("Subject message is missing from its descriptor")?;
+        Ok(Self { root, message })
+    }
+    /// Returns the `.proto` definition of the derived message
+    pub fn to_proto(&self, message_name: &str) -> String {
+        let mut proto = "syntax = \"proto3\";\n\n".to_string();
+        let root = MessageSchema {
+            name: message_name.to_string(),
+            ..self.root.clone()
+        };
+        root.render(&mut proto, 0);
+        proto
+    }
+    /// Encodes a credential subject with the derived message
+    ///
+    /// Fails if the subject can't be decoded back to the same json, such as for empty arrays, `null`s or
+    /// numbers of the wrong type
+    pub fn encode(&self, subject: &Value) -> Result<Vec<u8>, String> {
+        let subject = subject
+            .as_object()
+            .ok_or("credentialSubject is not an object")?;
+        Ok(self.root.encode(subject, &self.message)?.encode_to_vec())
+    }
+    /// Decodes a credential subject encoded with the derived message
+    pub fn decode(&self, bytes: &[u8]) -> Result<Value, String> {
+        let message =
+            DynamicMessage::decode(self.message.clone(), bytes).map_err(|e| e.to_string())?;
+        self.root.decode(&message).map(Value::Object)
+    }
+}
+
+/// Annotates every property of a json schema with a stable `x-protobuf-field` number
+///
+/// Properties keep the number they had in the previous version of the schema, and numbers of removed properties
+/// are listed under `x-protobuf-reserved` so they are never reused
+pub fn annotate_field_numbers(json_schema: &mut Value, previous: Option<&Value>) {
+    let Some(object) = json_schema.as_object_mut() else {
+        return;
+    };
+    for keyword in ["$defs", "definitions"] {
+        if let Some(Value::Object(definitions)) = object.get_mut(keyword) {
+            for (name, definition) in definitions {
+                let previous = previous.and_then(|previous| previous.get(keyword)?.get(name));
+                annotate_field_numbers(definition, previous);
+            }
+        }
+    }
+    if let Some(items) = object.get_mut("items") {
+        annotate_field_numbers(items, previous.and_then(|previous| previous.get("items")));
+    }
+    let Some(Value::Object(properties)) = object.get_mut("properties") else {
+        return;
+    };
+    let previous_properties = previous
+        .and_then(|previous| previous.get("properties"))
+        .and_then(Value::as_object);
+    let previous_number = |name: &str| {
+        previous_properties?
+            .get(name)?
+            .get(FIELD_KEYWORD)?
+            .as_u64()
+            .map(|number| number as u32)
+    };
+    let mut reserved = numbers(previous.and_then(|previous| previous.get(RESERVED_KEYWORD)));
+    reserved.extend(
+        previous_properties
+            .into_iter()
+            .flatten()
+            .filter(|(name, _)| !properties.contains_key(*name))
+            .filter_map(|(name, _)| previous_number(name)),
+    );
+    let mut used: BTreeSet<_> = properties
+        .iter()
+        .filter_map(|(name, property)| {
+            property
+                .get(FIELD_KEYWORD)
+                .and_then(Value::as_u64)
+                .map(|number| number as u32)
+                .or_else(|| previous_number(name))
+        })
+        .collect();
+    let mut next = used.iter().chain(&reserved).max().copied().unwrap_or(0);
+    for (name, property) in properties.iter_mut() {
+        let previous_property = previous_properties.and_then(|previous| previous.get(name));
+        annotate_field_numbers(property, previous_property);
+        if let Some(property) = property.as_object_mut() {
+            if !property.contains_key(FIELD_KEYWORD) {
+                let number = match previous_number(name) {
+                    Some(number) => number,
+                    None => {
+                        next = next_number(next);
+                        next
+                    }
+                };
+                used.insert(number);
+                property.insert(FIELD_KEYWORD.into(), number.into());
+            }
+        }
+    }
+    reserved.retain(|number| !used.contains(number));
+    if !reserved.is_empty() {
+        object.insert(RESERVED_KEYWORD.into(), reserved.into_iter().collect());
+    }
+}
+
+fn next_number(number: u32) -> u32 {
+    match number + 1 {
+        number if IMPLEMENTATION_RESERVED.contains(&number) => IMPLEMENTATION_RESERVED.end() + 1,
+        number => number,
+    }
+}
+
+fn numbers(value: Option<&Value>) -> BTreeSet<u32> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_u64)
+        .map(|number| number as u32)
+        .collect()
+}
+
+/// Returns the types a subschema allows, inferring `object` from `properties`
+fn types(schema: &Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(single)) => vec![single.as_str()],
+        Some(Value::Array(multiple)) => multiple.iter().filter_map(Value::as_str).collect(),
+        _ if schema.get("properties").is_some() => vec!["object"],
+        _ => vec![],
+    }
+}
+
+struct Builder<'a> {
+    root: &'a Value,
+    in_progress: Vec<&'a str>,
+}
+
+impl<'a> Builder<'a> {
+    /// Returns whether a property is repeated, and the type of each of its values
+    fn field(&mut self, schema: &'a Value, name: &str) -> Result<(bool, FieldType), String> {
+        match (types(schema).as_slice(), schema.get("items")) {
+            (["array"], Some(items)) if items.is_object() => {
+                Ok((true, self.single(items, &format!("{}Item", name))?))
+            }
+            _ => Ok((false, self.single(schema, name)?)),
+        }
+    }
+    fn single(&mut self, schema: &'a Value, name: &str) -> Result<FieldType, String> {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer));
+            return match target {
+                Some(target) if !self.in_progress.contains(&reference) => {
+                    self.in_progress.push(reference);
+                    let definition = reference.rsplit('/').next().unwrap_or(name);
+                    let field_type = self.single(target, &pascal_case(definition));
+                    self.in_progress.pop();
+                    field_type
+                }
+                _ => Ok(FieldType::Scalar(Scalar::Json)),
+            };
+        }
+        if ["allOf", "anyOf", "oneOf"]
+            .iter()
+            .any(|keyword| schema.get(keyword).is_some())
+        {
+            return Ok(FieldType::Scalar(Scalar::Json));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return Ok(FieldType::Scalar(
+                match values.iter().all(Value::is_string) {
+                    true => Scalar::String,
+                    false => Scalar::Json,
+                },
+            ));
+        }
+        Ok(match types(schema).as_slice() {
+            ["string"] => FieldType::Scalar(Scalar::String),
+            ["integer"] => FieldType::Scalar(Scalar::Integer),
+            ["number"] => FieldType::Scalar(Scalar::Number),
+            ["boolean"] => FieldType::Scalar(Scalar::Boolean),
+            ["object"] if schema.get("properties").is_some_and(Value::is_object) => {
+                FieldType::Message(self.message(schema, name)?)
+            }
+            _ => FieldType::Scalar(Scalar::Json),
+        })
+    }
+    fn message(&mut self, schema: &'a Value, name: &str) -> Result<MessageSchema, String> {
+        let properties: Vec<_> = schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .collect();
+        let reserved = numbers(schema.get(RESERVED_KEYWORD));
+        let annotated = |property: &Value| property.get(FIELD_KEYWORD).map(Value::as_u64);
+        let mut next = properties
+            .iter()
+            .filter_map(|(_, property)| annotated(property).flatten())
+            .map(|number| number as u32)
+            .chain(reserved.iter().copied())
+            .max()
+            .unwrap_or(0);
+        let mut fields = Vec::new();
+        let mut used = BTreeSet::new();
+        for (property_name, property) in properties {
+            let number = match annotated(property) {
+                Some(Some(number))
+                    if (1..=MAX_FIELD as u64).contains(&number)
+                        && !IMPLEMENTATION_RESERVED.contains(&(number as u32)) =>
+                {
+                    number as u32
+                }
+                Some(_) => return Err(format!("Invalid {} of {}", FIELD_KEYWORD, property_name)),
+                None => {
+                    next = next_number(next);
+                    next
+                }
+            };
+            if reserved.contains(&number) || !used.insert(number) {
+                return Err(format!(
+                    "Field number {} of {} is already used",
+                    number, property_name
+                ));
+            }
+            let (repeated, field_type) = self.field(property, &pascal_case(property_name))?;
+            fields.push(FieldSchema {
+                name: property_name.clone(),
+                proto_name: proto_name(property_name),
+                number,
+                repeated,
+                field_type,
+            });
+        }
+        let mut names = BTreeSet::new();
+        for field in &mut fields {
+            while !names.insert(field.proto_name.clone()) {
+                field.proto_name.push('_');
+            }
+        }
+        for field in &mut fields {
+            if let FieldType::Message(message) = &mut field.field_type {
+                while !names.insert(message.name.clone()) {
+                    message.name.push('_');
+                }
+            }
+        }
+        let mut extra_name = "extra_properties".to_string();
+        while names.contains(&extra_name) {
+            extra_name.push('_');
+        }
+        Ok(MessageSchema {
+            name: name.to_string(),
+            fields,
+            reserved,
+            extra_name,
+        })
+    }
+}
+
+impl MessageSchema {
+    fn render(&self, proto: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let _ = writeln!(proto, "{}message {} {{", indent, self.name);
+        for field in &self.fields {
+            let field_type = match &field.field_type {
+                FieldType::Scalar(scalar) => scalar.proto_type(),
+                FieldType::Message(message) => &message.name,
+            };
+            let label = match (field.repeated, &field.field_type) {
+                (true, _) => "repeated ",
+                (false, FieldType::Scalar(_)) => "optional ",
+                (false, FieldType::Message(_)) => "",
+            };
+            let json_name = match field.proto_name == field.name {
+                true => String::new(),
+                false => format!(" [json_name = {:?}]", field.name),
+            };
+            let comment = match field.field_type {
+                FieldType::Scalar(Scalar::Json) => " // json",
+                _ => "",
+            };
+            let _ = writeln!(
+                proto,
+                "{}    {}{} {} = {}{};{}",
+                indent, label, field_type, field.proto_name, field.number, json_name, comment
+            );
+        }
+        if !self.reserved.is_empty() {
+            let reserved: Vec<_> = self.reserved.iter().map(u32::to_string).collect();
+            let _ = writeln!(proto, "{}    reserved {};", indent, reserved.join(", "));
+        }
+        let _ = writeln!(
+            proto,
+            "{}    // Properties the schema doesn't list, as a json object\n{}    optional string {} = {};",
+            indent, indent, self.extra_name, EXTRA_FIELD
+        );
+        for field in &self.fields {
+            if let FieldType::Message(message) = &field.field_type {
+                message.render(proto, depth + 1);
+            }
+        }
+        let _ = writeln!(proto, "{}}}", indent);
+    }
+    /// Returns the protobuf descriptor of the message, nested in the message named `scope`
+    fn descriptor_proto(&self, scope: &str) -> DescriptorProto {
+        let full_name = format!("{}.{}", scope, self.name);
+        let mut message = DescriptorProto {
+            name: Some(self.name.clone()),
+            reserved_range: self
+                .reserved
+                .iter()
+                .map(|number| ReservedRange {
+                    start: Some(*number as i32),
+                    end: Some(*number as i32 + 1),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        for field in &self.fields {
+            let (field_type, type_name) = match &field.field_type {
+                FieldType::Scalar(scalar) => (scalar.field_type(), None),
+                FieldType::Message(nested) => {
+                    message
+                        .nested_type
+                        .push(nested.descriptor_proto(&full_name));
+                    (
+                        Type::Message,
+                        Some(format!("{}.{}", full_name, nested.name)),
+                    )
+                }
+            };
+            let optional = !field.repeated && matches!(field.field_type, FieldType::Scalar(_));
+            message.field.push(FieldDescriptorProto {
+                type_name,
+                label: Some(match field.repeated {
+                    true => Label::Repeated,
+                    false => Label::Optional,
+                } as i32),
+                ..field_proto(&field.proto_name, field.number, &field.name, field_type)
+            });
+            if optional {
+                proto3_optional(&mut message);
+            }
+        }
+        message.field.push(field_proto(
+            &self.extra_name,
+            EXTRA_FIELD,
+            &self.extra_name,
+            Type::String,
+        ));
+        proto3_optional(&mut message);
+        message
+    }
+    fn encode(
+        &self,
+        object: &Map<String, Value>,
+        descriptor: &prost_reflect::MessageDescriptor,
+    ) -> Result<DynamicMessage, String> {
+        let mut message = DynamicMessage::new(descriptor.clone());
+        for field in &self.fields {
+            if let Some(value) = object.get(&field.name) {
+                let field_descriptor = descriptor
+                    .get_field(field.number)
+                    .ok_or_else(|| field.mismatch())?;
+                let value = field.encode(value, &field_descriptor.kind())?;
+                message
+                    .try_set_field(&field_descriptor, value)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        let extra: Map<_, _> = object
+            .iter()
+            .filter(|(name, _)| !self.fields.iter().any(|field| field.name == **name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if !extra.is_empty() {
+            message
+                .try_set_field_by_number(
+                    EXTRA_FIELD,
+                    ReflectValue::String(Value::Object(extra).to_string()),
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(message)
+    }
+    fn decode(&self, message: &DynamicMessage) -> Result<Map<String, Value>, String> {
+        let mut object = Map::new();
+        for field in &self.fields {
+            if message.has_field_by_number(field.number) {
+                if let Some(value) = message.get_field_by_number(field.number) {
+                    object.insert(field.name.clone(), field.decode(&value)?);
+                }
+            }
+        }
+        if message.has_field_by_number(EXTRA_FIELD) {
+            let extra = match message.get_field_by_number(EXTRA_FIELD).as_deref() {
+                Some(ReflectValue::String(extra)) => {
+                    serde_json::from_str(extra).map_err(|e| e.to_string())?
+                }
+                _ => Value::Null,
+            };
+            match extra {
+                Value::Object(extra) => object.extend(extra),
+                _ => return Err("Extra properties are not an object".into()),
+            }
+        }
+        Ok(object)
+    }
+}
+
+fn field_proto(name: &str, number: u32, json_name: &str, field_type: Type) -> FieldDescriptorProto {
+    FieldDescriptorProto {
+        name: Some(name.to_string()),
+        number: Some(number as i32),
+        label: Some(Label::Optional as i32),
+        r#type: Some(field_type as i32),
+        json_name: Some(json_name.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Gives the last field of a message explicit presence, as proto3 `optional` does with a synthetic oneof
+fn proto3_optional(message: &mut DescriptorProto) {
+    if let Some(field) = message.field.last_mut() {
+        field.proto3_optional = Some(true);
+        field.oneof_index = Some(message.oneof_decl.len() as i32);
+        message.oneof_decl.push(OneofDescriptorProto {
+            name: Some(format!("_{}", field.name())),
+            ..Default::default()
+        });
+    }
+}
+
+impl Scalar {
+    fn proto_type(self) -> &'static str {
+        match self {
+            Scalar::String | Scalar::Json => "string",
+            Scalar::Integer => "sint64",
+            Scalar::Number => "double",
+            Scalar::Boolean => "bool",
+        }
+    }
+    fn field_type(self) -> Type {
+        match self {
+            Scalar::String | Scalar::Json => Type::String,
+            Scalar::Integer => Type::Sint64,
+            Scalar::Number => Type::Double,
+            Scalar::Boolean => Type::Bool,
+        }
+    }
+}
+
+impl FieldSchema {
+    fn encode(&self, value: &Value, kind: &Kind) -> Result<ReflectValue, String> {
+        if !self.repeated {
+            return self.encode_one(value, kind);
+        }
+        let values = value
+            .as_array()
+            .filter(|values| !values.is_empty())
+            .ok_or_else(|| self.mismatch())?;
+        values
+            .iter()
+            .map(|value| self.encode_one(value, kind))
+            .collect::<Result<_, _>>()
+            .map(ReflectValue::List)
+    }
+    fn encode_one(&self, value: &Value, kind: &Kind) -> Result<ReflectValue, String> {
+        Ok(match (&self.field_type, value, kind) {
+            (FieldType::Scalar(Scalar::String), Value::String(string), _) => {
+                ReflectValue::String(string.clone())
+            }
+            (FieldType::Scalar(Scalar::Json), value, _) => ReflectValue::String(value.to_string()),
+            (FieldType::Scalar(Scalar::Integer), value, _) => {
+                ReflectValue::I64(value.as_i64().ok_or_else(|| self.mismatch())?)
+            }
+            (FieldType::Scalar(Scalar::Number), value, _) => {
+                ReflectValue::F64(number(value).ok_or_else(|| self.mismatch())?)
+            }
+            (FieldType::Scalar(Scalar::Boolean), Value::Bool(bool), _) => ReflectValue::Bool(*bool),
+            (FieldType::Message(message), Value::Object(object), Kind::Message(descriptor)) => {
+                ReflectValue::Message(message.encode(object, descriptor)?)
+            }
+            _ => return Err(self.mismatch()),
+        })
+    }
+    fn decode(&self, value: &ReflectValue) -> Result<Value, String> {
+        match (self.repeated, value) {
+            (true, ReflectValue::List(values)) => values
+                .iter()
+                .map(|value| self.decode_one(value))
+                .collect::<Result<_, _>>()
+                .map(Value::Array),
+            _ => self.decode_one(value),
+        }
+    }
+    fn decode_one(&self, value: &ReflectValue) -> Result<Value, String> {
+        Ok(match (&self.field_type, value) {
+            (FieldType::Scalar(Scalar::String), ReflectValue::String(string)) => {
+                Value::String(string.clone())
+            }
+            (FieldType::Scalar(Scalar::Json), ReflectValue::String(json)) => {
+                serde_json::from_str(json).map_err(|e| e.to_string())?
+            }
+            (FieldType::Scalar(Scalar::Integer), ReflectValue::I64(integer)) => {
+                Value::from(*integer)
+            }
+            (FieldType::Scalar(Scalar::Number), ReflectValue::F64(number)) => from_double(*number),
+            (FieldType::Scalar(Scalar::Boolean), ReflectValue::Bool(bool)) => Value::Bool(*bool),
+            (FieldType::Message(message), ReflectValue::Message(inner)) => {
+                Value::Object(message.decode(inner)?)
+            }
+            _ => return Err(format!("Unexpected value for field {}", self.name)),
+        })
+    }
+    fn mismatch(&self) -> String {
+        format!("Property {} doesn't match its protobuf field", self.name)
+    }
+}
+
+/// Returns a number as a double, if it decodes back to the same json
+fn number(value: &Value) -> Option<f64> {
+    let number = value.as_number()?;
+    match (number.as_i64(), number.as_u64()) {
+        (Some(integer), _) => Some(integer as f64).filter(|float| float.abs() <= MAX_SAFE_INTEGER),
+        (None, Some(integer)) => Some(integer as f64).filter(|float| *float <= MAX_SAFE_INTEGER),
+        (None, None) => number.as_f64().filter(|float| float.fract() != 0.0),
+    }
+}
+
+fn from_double(number: f64) -> Value {
+    match number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER {
+        true => Value::from(number as i64),
+        false => Value::from(number),
+    }
+}
+
+/// Returns a valid protobuf identifier for a json property name
+fn proto_name(name: &str) -> String {
+    let mut proto_name: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    if !proto_name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        proto_name.insert(0, 'f');
+    }
+    proto_name
+}
+
+fn pascal_case(name: &str) -> String {
+    let mut pascal = String::new();
+    let mut upper = true;
+    for c in name.chars() {
+        match c.is_ascii_alphanumeric() {
+            true if upper => pascal.push(c.to_ascii_uppercase()),
+            true => pascal.push(c),
+            false => {}
+        }
+        upper = !c.is_ascii_alphanumeric();
+    }
+    match pascal.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => pascal,
+        false => format!("M{}", pascal),
+    }
+}
@@ -0,0 +1,96 @@
+use crate::format::{deflate, inflate, QR_PREFIX};
+use crate::{VerifiableCredential, VerifiablePresentation};
+use image::{ImageFormat, Luma};
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+use std::io::Cursor;
+
+/// Encodes CBOR-LD as a QR payload
+fn to_qr(cbor_ld: &[u8]) -> Result<String, String> {
+    Ok(format!(
+        "{}{}",
+        QR_PREFIX,
+        base45::encode(deflate(cbor_ld)?)
+    ))
+}
+
+/// Decodes a scanned QR payload into CBOR-LD
+fn from_qr(qr: &str) -> Result<Vec<u8>, String> {
+    let payload = qr
+        .trim()
+        .strip_prefix(QR_PREFIX)
+        .ok_or("Not a VC1 QR payload")?;
+    inflate(&base45::decode(payload).map_err(|e| e.to_string())?)
+}
+
+/// Encodes a QR payload as a QR code, in alphanumeric mode as base45 only uses its characters
+fn qr_code(qr: &str) -> Result<QrCode, String> {
+    QrCode::with_error_correction_level(qr, EcLevel::L).map_err(|e| e.to_string())
+}
+
+/// Renders a QR payload as a PNG image
+fn render_png(qr: &str) -> Result<Vec<u8>, String> {
+    let image = qr_code(qr)?.render::<Luma<u8>>().build();
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png.into_inner())
+}
+
+/// Renders a QR payload as an SVG image
+fn render_svg(qr: &str) -> Result<String, String> {
+    Ok(qr_code(qr)?.render::<svg::Color>().build())
+}
+
+impl VerifiablePresentation {
+    /// Serializes a VerifiablePresentation structure into a QR payload, as CBOR-LD compressed and encoded in base45
+    pub fn serialize_qr(&self) -> Result<String, String> {
+        to_qr(&self.serialize_cbor_ld()?)
+    }
+    /// Deserializes a scanned QR payload into a VerifiablePresentation structure
+    pub fn deserialize_qr(qr: &str) -> Result<Self, String> {
+        Self::deserialize_cbor_ld(from_qr(qr)?)
+    }
+    /// Verifies a QR payload VerifiablePresentation was signed by the owner of the given public key, returning it decoded
+    pub fn verify_qr(qr: &str, public_key: &[u8]) -> Result<Self, String> {
+        let vp = Self::deserialize_qr(qr)?;
+        vp.verify(public_key)?;
+        Ok(vp)
+    }
+    /// Renders a VerifiablePresentation as a QR code PNG image
+    pub fn qr_png(&self) -> Result<Vec<u8>, String> {
+        render_png(&self.serialize_qr()?)
+    }
+    /// Renders a VerifiablePresentation as a QR code SVG image
+    pub fn qr_svg(&self) -> Result<String, String> {
+        render_svg(&self.serialize_qr()?)
+    }
+}
+
+impl VerifiableCredential {
+    /// Serializes a VerifiableCredential structure into a QR payload, as CBOR-LD compressed and encoded in base45
+    ///
+    /// The payload starts with the `VC1:` version prefix, and only uses characters of the QR alphanumeric mode
+    pub fn serialize_qr(&self) -> Result<String, String> {
+        to_qr(&self.serialize_cbor_ld()?)
+    }
+    /// Deserializes a scanned QR payload into a VerifiableCredential structure
+    pub fn deserialize_qr(qr: &str) -> Result<Self, String> {
+        Self::deserialize_cbor_ld(from_qr(qr)?)
+    }
+    /// Verifies a QR payload VerifiableCredential was signed by the owner of the given public key, returning it decoded
+    pub fn verify_qr(qr: &str, public_key: &[u8]) -> Result<Self, String> {
+        let vc = Self::deserialize_qr(qr)?;
+        vc.verify(public_key)?;
+        Ok(vc)
+    }
+    /// Renders a VerifiableCredential as a QR code PNG image
+    pub fn qr_png(&self) -> Result<Vec<u8>, String> {
+        render_png(&self.serialize_qr()?)
+    }
+    /// Renders a VerifiableCredential as a QR code SVG image
+    pub fn qr_svg(&self) -> Result<String, String> {
+        render_svg(&self.serialize_qr()?)
+    }
+}
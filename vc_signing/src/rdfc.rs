@@ -0,0 +1,239 @@
+//! RDF Dataset Canonicalization (RDFC-1.0), which labels the blank nodes of a dataset independently of how it was
+//! serialized
+
+use crate::jsonld::{Quad, Term};
+use ring::digest::{digest, SHA256};
+use std::collections::{BTreeMap, HashMap};
+
+/// Most N-degree hashes and permutations canonicalizing a dataset may take, since crafted datasets can otherwise
+/// take exponential time
+const MAX_WORK: usize = 100_000;
+
+fn hash(input: &str) -> String {
+    digest(&SHA256, input.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Rearranges items into their next permutation in lexicographic order, returning false after the last one
+fn next_permutation<T: Ord>(items: &mut [T]) -> bool {
+    let Some(i) = items.windows(2).rposition(|pair| pair[0] < pair[1]) else {
+        return false;
+    };
+    let j = items.iter().rposition(|item| items[i] < *item).unwrap_or(i);
+    items.swap(i, j);
+    items[i + 1..].reverse();
+    true
+}
+
+#[derive(Clone)]
+struct IdentifierIssuer {
+    prefix: &'static str,
+    /// Existing identifiers, in the order they were issued new ones
+    issued: Vec<String>,
+    identifiers: HashMap<String, String>,
+}
+
+impl IdentifierIssuer {
+    fn new(prefix: &'static str) -> Self {
+        IdentifierIssuer {
+            prefix,
+            issued: Vec::new(),
+            identifiers: HashMap::new(),
+        }
+    }
+    fn issue(&mut self, existing: &str) -> String {
+        if let Some(identifier) = self.identifiers.get(existing) {
+            return identifier.clone();
+        }
+        let identifier = format!("{}{}", self.prefix, self.issued.len());
+        self.issued.push(existing.to_string());
+        self.identifiers
+            .insert(existing.to_string(), identifier.clone());
+        identifier
+    }
+    fn get(&self, existing: &str) -> Option<&String> {
+        self.identifiers.get(existing)
+    }
+}
+
+struct Canonicalizer<'a> {
+    quads: HashMap<&'a str, Vec<&'a Quad>>,
+    first_degree_hashes: HashMap<&'a str, String>,
+    canonical: IdentifierIssuer,
+    work: usize,
+}
+
+impl<'a> Canonicalizer<'a> {
+    fn count_work(&mut self) -> Result<(), String> {
+        self.work += 1;
+        match self.work > MAX_WORK {
+            true => Err("Dataset is too complex to canonicalize".into()),
+            false => Ok(()),
+        }
+    }
+    fn hash_first_degree(&self, blank: &str) -> String {
+        let mut nquads: Vec<String> = self.quads[blank]
+            .iter()
+            .map(|quad| {
+                quad.to_nquad(|label| match label == blank {
+                    true => "a".to_string(),
+                    false => "z".to_string(),
+                })
+            })
+            .collect();
+        nquads.sort();
+        hash(&nquads.concat())
+    }
+    fn hash_related(
+        &self,
+        related: &str,
+        quad: &Quad,
+        issuer: &IdentifierIssuer,
+        position: &str,
+    ) -> String {
+        let identifier = self
+            .canonical
+            .get(related)
+            .or_else(|| issuer.get(related))
+            .unwrap_or(&self.first_degree_hashes[related]);
+        hash(&format!("{}<{}>{}", position, quad.predicate, identifier))
+    }
+    fn hash_n_degree(
+        &mut self,
+        blank: &str,
+        mut issuer: IdentifierIssuer,
+    ) -> Result<(String, IdentifierIssuer), String> {
+        self.count_work()?;
+        let mut related_by_hash: BTreeMap<String, Vec<&'a str>> = BTreeMap::new();
+        for quad in self.quads[blank].clone() {
+            for (term, position) in [(&quad.subject, "s"), (&quad.object, "o")] {
+                match term {
+                    Term::Blank(related) if related != blank => related_by_hash
+                        .entry(self.hash_related(related, quad, &issuer, position))
+                        .or_default()
+                        .push(related),
+                    _ => {}
+                }
+            }
+        }
+        let mut data = String::new();
+        for (related_hash, mut related) in related_by_hash {
+            data.push_str(&related_hash);
+            related.sort();
+            let mut chosen: Option<(String, IdentifierIssuer)> = None;
+            loop {
+                self.count_work()?;
+                let chosen_path = chosen.as_ref().map(|(path, _)| path.as_str());
+                if let Some((path, path_issuer)) = self.path(&related, &issuer, chosen_path)? {
+                    if chosen_path.is_none_or(|chosen_path| path.as_str() < chosen_path) {
+                        chosen = Some((path, path_issuer));
+                    }
+                }
+                if !next_permutation(&mut related) {
+                    break;
+                }
+            }
+            let (path, path_issuer) = chosen.ok_or("Error canonicalizing dataset")?;
+            data.push_str(&path);
+            issuer = path_issuer;
+        }
+        Ok((hash(&data), issuer))
+    }
+    /// Returns the path through a permutation of related blank nodes, or None when it can't be shorter than the
+    /// chosen path
+    fn path(
+        &mut self,
+        permutation: &[&'a str],
+        issuer: &IdentifierIssuer,
+        chosen_path: Option<&str>,
+    ) -> Result<Option<(String, IdentifierIssuer)>, String> {
+        let longer = |path: &str| {
+            chosen_path
+                .is_some_and(|chosen_path| path.len() >= chosen_path.len() && path > chosen_path)
+        };
+        let mut issuer = issuer.clone();
+        let mut path = String::new();
+        let mut recursion = Vec::new();
+        for related in permutation {
+            match self.canonical.get(related) {
+                Some(identifier) => path.push_str(identifier),
+                None => {
+                    if issuer.get(related).is_none() {
+                        recursion.push(*related);
+                    }
+                    path.push_str(&issuer.issue(related));
+                }
+            }
+            if longer(&path) {
+                return Ok(None);
+            }
+        }
+        for related in recursion {
+            let (related_hash, related_issuer) = self.hash_n_degree(related, issuer.clone())?;
+            path.push_str(&issuer.issue(related));
+            path.push_str(&format!("<{}>", related_hash));
+            issuer = related_issuer;
+            if longer(&path) {
+                return Ok(None);
+            }
+        }
+        Ok(Some((path, issuer)))
+    }
+}
+
+/// Returns the canonical label, such as c14n0, of every blank node in a dataset, as the RDFC-1.0 algorithm
+pub(crate) fn canonical_labels(quads: &[Quad]) -> Result<HashMap<String, String>, String> {
+    let mut quads_by_blank: HashMap<&str, Vec<&Quad>> = HashMap::new();
+    for quad in quads {
+        for blank in quad.blank_nodes() {
+            quads_by_blank.entry(blank).or_default().push(quad);
+        }
+    }
+    let mut canonicalizer = Canonicalizer {
+        quads: quads_by_blank,
+        first_degree_hashes: HashMap::new(),
+        canonical: IdentifierIssuer::new("_:c14n"),
+        work: 0,
+    };
+    let mut blanks_by_hash: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    let blanks: Vec<&str> = canonicalizer.quads.keys().copied().collect();
+    for blank in blanks {
+        let first_degree_hash = canonicalizer.hash_first_degree(blank);
+        blanks_by_hash
+            .entry(first_degree_hash.clone())
+            .or_default()
+            .push(blank);
+        canonicalizer
+            .first_degree_hashes
+            .insert(blank, first_degree_hash);
+    }
+    for blanks in blanks_by_hash.values().filter(|blanks| blanks.len() == 1) {
+        canonicalizer.canonical.issue(blanks[0]);
+    }
+    for blanks in blanks_by_hash.values().filter(|blanks| blanks.len() > 1) {
+        let mut results = Vec::new();
+        for blank in blanks {
+            if canonicalizer.canonical.get(blank).is_some() {
+                continue;
+            }
+            let mut issuer = IdentifierIssuer::new("_:b");
+            issuer.issue(blank);
+            results.push(canonicalizer.hash_n_degree(blank, issuer)?);
+        }
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, issuer) in results {
+            for existing in &issuer.issued {
+                canonicalizer.canonical.issue(existing);
+            }
+        }
+    }
+    Ok(canonicalizer
+        .canonical
+        .identifiers
+        .into_iter()
+        .map(|(existing, identifier)| (existing, identifier.trim_start_matches("_:").to_string()))
+        .collect())
+}
@@ -0,0 +1,290 @@
+use crate::schema::{compile, ValidationOptions};
+use crate::verifiable_credential::SignedSchema;
+use serde_json::{json, Map, Number, Value};
+#[cfg(target_family = "wasm")]
+use {
+    serde::Serialize,
+    wasm_bindgen::{prelude::wasm_bindgen, JsError, JsValue},
+};
+
+/// Maximum depth of nested subschemas followed, so recursive schemas terminate
+const MAX_DEPTH: usize = 16;
+
+/// Small SplitMix64 generator, so samples are reproducible from a seed on every platform
+struct SampleRng(u64);
+
+impl SampleRng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    /// Returns a number in the inclusive range `low..=high`
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        match high.checked_sub(low).and_then(|span| span.checked_add(1)) {
+            Some(span) if span > 0 => low + (self.next() % span as u64) as i64,
+            _ => low,
+        }
+    }
+    fn pick<'a, T>(&mut self, values: &'a [T]) -> Option<&'a T> {
+        match values.len() {
+            0 => None,
+            len => values.get((self.next() % len as u64) as usize),
+        }
+    }
+    fn alphanumeric(&mut self, len: usize) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+        (0..len)
+            .map(|_| CHARS[(self.next() % CHARS.len() as u64) as usize] as char)
+            .collect()
+    }
+}
+
+struct Generator<'a> {
+    root: &'a Value,
+    rng: SampleRng,
+    full: bool,
+}
+
+impl Generator<'_> {
+    fn generate(&mut self, schema: &Value, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("Schema nests too deeply to sample".into());
+        }
+        let schema = match schema {
+            Value::Bool(true) => return Ok(json!({})),
+            Value::Bool(false) => return Err("Schema allows no values".into()),
+            schema => schema,
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer))
+                .ok_or_else(|| format!("Cannot sample external reference: {}", reference))?;
+            return self.generate(target, depth + 1);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(value.clone());
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return self
+                .rng
+                .pick(values)
+                .cloned()
+                .ok_or_else(|| "Empty enum".to_string());
+        }
+        if let Some(value) = schema.get("default") {
+            return Ok(value.clone());
+        }
+        if let Some(value) = schema
+            .get("examples")
+            .and_then(Value::as_array)
+            .and_then(|examples| self.rng.pick(examples))
+        {
+            return Ok(value.clone());
+        }
+        if let Some(subschemas) = schema.get("allOf").and_then(Value::as_array) {
+            return self.generate(&merge(schema, subschemas), depth + 1);
+        }
+        for keyword in ["oneOf", "anyOf"] {
+            if let Some(subschema) = schema
+                .get(keyword)
+                .and_then(Value::as_array)
+                .and_then(|subschemas| subschemas.first())
+            {
+                return self.generate(&merge(schema, std::slice::from_ref(subschema)), depth + 1);
+            }
+        }
+        match schema_type(schema) {
+            "object" => self.object(schema, depth),
+            "array" => self.array(schema, depth),
+            "integer" | "number" => self.number(schema),
+            "boolean" => Ok(Value::Bool(self.rng.next().is_multiple_of(2))),
+            "null" => Ok(Value::Null),
+            _ => Ok(Value::String(self.string(schema))),
+        }
+    }
+    fn object(&mut self, schema: &Value, depth: usize) -> Result<Value, String> {
+        let empty = Map::new();
+        let properties = schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+        let required: Vec<_> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut object = Map::new();
+        for (name, property) in properties {
+            if self.full || required.contains(&name.as_str()) {
+                object.insert(name.clone(), self.generate(property, depth + 1)?);
+            }
+        }
+        for name in required {
+            if !object.contains_key(name) {
+                object.insert(name.to_string(), Value::String(self.rng.alphanumeric(8)));
+            }
+        }
+        Ok(Value::Object(object))
+    }
+    fn array(&mut self, schema: &Value, depth: usize) -> Result<Value, String> {
+        let min_items = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let max_items = schema.get("maxItems").and_then(Value::as_u64);
+        let len = match self.full {
+            true => max_items.map_or(min_items.max(1), |max| min_items.max(1).min(max)),
+            false => min_items,
+        };
+        let items = schema.get("items").cloned().unwrap_or(json!({}));
+        (0..len)
+            .map(|_| self.generate(&items, depth + 1))
+            .collect::<Result<_, _>>()
+            .map(Value::Array)
+    }
+    fn number(&mut self, schema: &Value) -> Result<Value, String> {
+        let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+        let low = match (bound("minimum"), bound("exclusiveMinimum")) {
+            (_, Some(exclusive)) => exclusive.floor() as i64 + 1,
+            (Some(minimum), _) => minimum.ceil() as i64,
+            _ => 0,
+        };
+        let high = match (bound("maximum"), bound("exclusiveMaximum")) {
+            (_, Some(exclusive)) => exclusive.ceil() as i64 - 1,
+            (Some(maximum), _) => maximum.floor() as i64,
+            _ => low + 100,
+        };
+        let value = self.rng.range(low, high);
+        match schema.get("multipleOf").and_then(Value::as_i64) {
+            Some(multiple) if multiple > 0 => {
+                let value = (value.div_euclid(multiple) + 1) * multiple;
+                let value = match value > high {
+                    true => value - multiple,
+                    false => value,
+                };
+                Ok(Value::Number(value.into()))
+            }
+            _ => Ok(Value::Number(Number::from(value))),
+        }
+    }
+    fn string(&mut self, schema: &Value) -> String {
+        let n = self.rng.next();
+        match schema.get("format").and_then(Value::as_str) {
+            Some("date-time") => format!("2024-{:02}-{:02}T12:00:00Z", n % 12 + 1, n % 28 + 1),
+            Some("date") => format!("2024-{:02}-{:02}", n % 12 + 1, n % 28 + 1),
+            Some("time") => format!("{:02}:{:02}:00Z", n % 24, n % 60),
+            Some("email") => format!("user{}@example.com", n % 10000),
+            Some("uri") | Some("iri") => format!("https://example.com/{}", n % 10000),
+            Some("uuid") => {
+                let hex = format!("{:016x}{:016x}", n, self.rng.next());
+                format!(
+                    "{}-{}-4{}-a{}-{}",
+                    &hex[0..8],
+                    &hex[8..12],
+                    &hex[13..16],
+                    &hex[17..20],
+                    &hex[20..32]
+                )
+            }
+            _ => {
+                let min_length = schema.get("minLength").and_then(Value::as_u64).unwrap_or(0);
+                let max_length = schema.get("maxLength").and_then(Value::as_u64);
+                let len = max_length.map_or(min_length.max(8), |max| min_length.max(8).min(max));
+                self.rng.alphanumeric(len as usize)
+            }
+        }
+    }
+}
+
+/// Returns the type to generate for a subschema, inferring it when `type` is missing
+fn schema_type(schema: &Value) -> &str {
+    match schema.get("type") {
+        Some(Value::String(single)) => single,
+        Some(Value::Array(multiple)) => multiple
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|single| *single != "null")
+            .unwrap_or("null"),
+        _ if schema.get("properties").is_some() => "object",
+        _ if schema.get("items").is_some() => "array",
+        _ => "string",
+    }
+}
+
+/// Merges subschemas into a schema, combining their properties and required lists
+fn merge(schema: &Value, subschemas: &[Value]) -> Value {
+    let mut merged = schema.clone();
+    if let Some(merged) = merged.as_object_mut() {
+        for keyword in ["allOf", "anyOf", "oneOf"] {
+            merged.remove(keyword);
+        }
+    }
+    for subschema in subschemas.iter().filter_map(Value::as_object) {
+        for (keyword, value) in subschema {
+            match (keyword.as_str(), merged.get_mut(keyword)) {
+                ("properties", Some(Value::Object(properties))) => {
+                    if let Value::Object(value) = value {
+                        properties.extend(value.clone());
+                    }
+                }
+                ("required", Some(Value::Array(required))) => {
+                    if let Value::Array(value) = value {
+                        required.extend(value.clone());
+                    }
+                }
+                _ => merged[keyword] = value.clone(),
+            }
+        }
+    }
+    merged
+}
+
+/// Generates an example credentialSubject matching a signed schema, checking the result validates
+fn sample(schema: &SignedSchema, full: bool, seed: u64) -> Result<Value, String> {
+    schema
+        .vc
+        .verify(schema.public_key())
+        .map_err(|_| "Failed to verify schema signature")?;
+    let json_schema = schema.vc.json_schema()?;
+    let subject = Generator {
+        root: json_schema,
+        rng: SampleRng(seed),
+        full,
+    }
+    .generate(json_schema, 0)?;
+    let errors: Vec<_> = compile(json_schema, &[&schema.vc], &ValidationOptions::default())?
+        .iter_errors(&subject)
+        .map(|e| format!("{} At: {}", e, e.instance_path.as_str()))
+        .collect();
+    match errors.is_empty() {
+        true => Ok(subject),
+        false => Err(format!(
+            "Could not generate a valid sample: {}",
+            errors.join("\n")
+        )),
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl SignedSchema<'_> {
+    /// Generates an example credentialSubject matching a signed schema, the same for a given seed
+    ///
+    /// Minimal subjects only include required properties, full subjects include every property
+    pub fn sample_subject(&self, full: bool, seed: u64) -> Result<Value, String> {
+        sample(self, full, seed)
+    }
+}
+
+#[cfg(target_family = "wasm")]
+#[wasm_bindgen]
+impl SignedSchema {
+    /// Generates an example credentialSubject matching a signed schema, the same for a given seed
+    ///
+    /// Minimal subjects only include required properties, full subjects include every property
+    pub fn sample_subject(&self, full: bool, seed: u64) -> Result<JsValue, JsError> {
+        Ok(sample(self, full, seed)
+            .map_err(|e| JsError::new(&e))?
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+}
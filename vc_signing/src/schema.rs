@@ -0,0 +1,374 @@
+use crate::schema_registry::SchemaRegistry;
+use crate::{CredentialSchema, SchemaEnum, TypeEnum, VerifiableCredential};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use jsonschema::{Resource, Retrieve, Uri, Validator};
+use regex::Regex;
+use ring::digest::{digest, Algorithm, SHA256, SHA384, SHA512};
+use serde::Serialize;
+use serde_json::{from_str, json, to_string, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, OnceLock};
+use url::Url;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+pub(crate) const SCHEMA_SCHEMA_ID: &str = "https://json-schema.org/draft/2020-12/schema";
+/// Bundled meta-schemas for every supported JSON Schema draft, keyed by their id without a trailing '#'
+const META_SCHEMAS: [(&str, &str); 5] = [
+    (SCHEMA_SCHEMA_ID, include_str!("../schema_schema.json")),
+    (
+        "https://json-schema.org/draft/2019-09/schema",
+        include_str!("../schema_schema_2019-09.json"),
+    ),
+    (
+        "http://json-schema.org/draft-07/schema",
+        include_str!("../schema_schema_draft-07.json"),
+    ),
+    (
+        "http://json-schema.org/draft-06/schema",
+        include_str!("../schema_schema_draft-06.json"),
+    ),
+    (
+        "http://json-schema.org/draft-04/schema",
+        include_str!("../schema_schema_draft-04.json"),
+    ),
+];
+/// Schema for the credentialSubject of a JsonSchemaCredential, as defined by the VC JSON Schema spec
+pub(crate) const JSON_SCHEMA_CREDENTIAL_SCHEMA_ID: &str =
+    "https://www.w3.org/ns/credentials/json-schema/v2.json";
+
+/// Retriever that refuses every external reference, so `$ref`s only resolve to signed schemas
+struct OfflineRetriever;
+
+impl Retrieve for OfflineRetriever {
+    fn retrieve(&self, uri: &Uri<&str>) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        Err(format!("Reference to unknown or unsigned schema: {}", uri.as_str()).into())
+    }
+}
+
+/// Custom `format` checker, given the string value being validated
+type FormatCheck = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Options for validating credential subjects against their schemas
+#[derive(Clone, Default)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+pub struct ValidationOptions {
+    validate_formats: bool,
+    formats: HashMap<String, FormatCheck>,
+    registry: Option<Arc<SchemaRegistry>>,
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl ValidationOptions {
+    #[cfg_attr(target_family = "wasm", wasm_bindgen(constructor))]
+    /// Creates the default options, where `format` keywords are annotations whatever the schema's draft
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets whether `format` keywords are asserted, rejecting subjects with values like malformed dates
+    pub fn should_validate_formats(mut self, validate_formats: bool) -> Self {
+        self.validate_formats = validate_formats;
+        self
+    }
+    /// Registers a custom `format` whose values must match a regular expression
+    pub fn with_format_pattern(self, name: String, pattern: &str) -> Result<Self, String> {
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(self.with_format(name, move |value| pattern.is_match(value)))
+    }
+    /// Looks up the schemas a credential's credentialSchema entries name in a registry,
+    /// alongside any schemas passed in explicitly
+    pub fn with_registry(mut self, registry: SchemaRegistry) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+}
+
+impl ValidationOptions {
+    /// Registers a custom `format`, checked by the given function
+    pub fn with_format(
+        mut self,
+        name: impl Into<String>,
+        format: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.formats.insert(name.into(), Arc::new(format));
+        self
+    }
+    pub(crate) fn registry(&self) -> Option<&SchemaRegistry> {
+        self.registry.as_deref()
+    }
+}
+
+/// A single failure validating a credential against one of its credentialSchema entries
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SchemaValidationError {
+    /// Id of the credentialSchema entry that failed
+    #[serde(rename = "schemaId")]
+    pub schema_id: String,
+    /// JSON pointer to the offending value in the credentialSubject
+    #[serde(rename = "instancePath")]
+    pub instance_path: String,
+    /// JSON pointer to the failing keyword in the schema
+    #[serde(rename = "schemaPath")]
+    pub schema_path: String,
+    /// Failing schema keyword, or `credentialSchema` when the schema itself couldn't be used
+    pub keyword: String,
+    pub message: String,
+}
+
+impl SchemaValidationError {
+    /// Creates an error for a credentialSchema entry that couldn't be resolved, verified or compiled
+    pub(crate) fn credential_schema(message: impl Into<String>) -> Self {
+        Self {
+            schema_id: String::new(),
+            instance_path: String::new(),
+            schema_path: String::new(),
+            keyword: "credentialSchema".to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<jsonschema::ValidationError<'_>> for SchemaValidationError {
+    fn from(error: jsonschema::ValidationError) -> Self {
+        let schema_path = error.schema_path.as_str().to_string();
+        let keyword = schema_path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .replace("~1", "/")
+            .replace("~0", "~");
+        Self {
+            schema_id: String::new(),
+            instance_path: error.instance_path.as_str().to_string(),
+            schema_path,
+            keyword,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Display for SchemaValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.keyword.as_str() {
+            "credentialSchema" => write!(f, "{}: {}", self.schema_id, self.message),
+            _ => write!(
+                f,
+                "{}: Schema validation error: {} At: {}",
+                self.schema_id, self.message, self.instance_path
+            ),
+        }
+    }
+}
+
+/// Compiles a json schema into a validator
+///
+/// `$ref`s are resolved offline against the json schemas of the given signed schema credentials
+pub(crate) fn compile(
+    schema: &Value,
+    references: &[&VerifiableCredential],
+    options: &ValidationOptions,
+) -> Result<Validator, String> {
+    let mut resources = Vec::new();
+    for reference in references {
+        let Ok(json_schema) = reference.json_schema() else {
+            continue;
+        };
+        let Ok(resource) = Resource::from_contents(json_schema.clone()) else {
+            continue;
+        };
+        if let (true, Some(id)) = (reference.has_type("JsonSchemaCredential"), &reference.id) {
+            resources.push((id.to_string(), resource.clone()));
+        }
+        if let Some(id) = schema_id(json_schema) {
+            resources.push((id.to_string(), resource));
+        }
+    }
+    let mut builder = jsonschema::options();
+    // Set explicitly, as draft-07 and older otherwise assert `format` by default
+    builder
+        .with_retriever(OfflineRetriever)
+        .with_resources(resources.into_iter())
+        .should_validate_formats(options.validate_formats);
+    for (name, format) in &options.formats {
+        let format = format.clone();
+        builder.with_format(name.clone(), move |value: &str| format(value));
+    }
+    builder.build(schema).map_err(|e| e.to_string())
+}
+
+/// Validates a json value against a compiled schema, returning every validation error
+pub(crate) fn validate(validator: &Validator, subject: &Value) -> Vec<SchemaValidationError> {
+    validator.iter_errors(subject).map(Into::into).collect()
+}
+
+/// Returns the position in [`META_SCHEMAS`] of the bundled meta-schema with the given id
+fn bundled_meta_schema(id: &str) -> Option<usize> {
+    META_SCHEMAS
+        .iter()
+        .position(|(meta_schema_id, _)| *meta_schema_id == id.trim_end_matches('#'))
+}
+
+/// Returns the canonical id of the bundled meta-schema with the given id
+pub(crate) fn meta_schema_id(id: &str) -> Option<&'static str> {
+    bundled_meta_schema(id).map(|position| META_SCHEMAS[position].0)
+}
+
+/// Returns the id of a json schema, `id` for draft-04 and `$id` for later drafts
+pub(crate) fn schema_id(json_schema: &Value) -> Option<&str> {
+    let field = match json_schema
+        .get("$schema")
+        .and_then(Value::as_str)
+        .and_then(meta_schema_id)
+    {
+        Some("http://json-schema.org/draft-04/schema") => "id",
+        _ => "$id",
+    };
+    json_schema.get(field).and_then(Value::as_str)
+}
+
+/// Returns the compiled bundled meta-schema for a credentialSchema that refers to one
+///
+/// The meta-schema named by the schema's `$schema` takes precedence over the credentialSchema id,
+/// each meta-schema is only parsed and compiled the first time it is used
+pub(crate) fn meta_validator(
+    credential_schema: &CredentialSchema,
+    verifiable_credential: &VerifiableCredential,
+) -> Option<Result<&'static Validator, String>> {
+    static META_VALIDATORS: [OnceLock<Result<Validator, String>>; META_SCHEMAS.len()] =
+        [const { OnceLock::new() }; META_SCHEMAS.len()];
+    let default = match credential_schema.id.as_str() {
+        JSON_SCHEMA_CREDENTIAL_SCHEMA_ID
+            if verifiable_credential.has_type("JsonSchemaCredential") =>
+        {
+            SCHEMA_SCHEMA_ID
+        }
+        id => meta_schema_id(id)?,
+    };
+    let id = verifiable_credential
+        .json_schema()
+        .ok()
+        .and_then(|json_schema| json_schema.get("$schema"))
+        .and_then(Value::as_str)
+        .unwrap_or(default);
+    let Some(position) = bundled_meta_schema(id) else {
+        return Some(Err(format!("Unsupported $schema: {}", id)));
+    };
+    Some(
+        META_VALIDATORS[position]
+            .get_or_init(|| {
+                from_str(META_SCHEMAS[position].1)
+                    .map_err(|e| e.to_string())
+                    .and_then(|meta_schema| {
+                        compile(&meta_schema, &[], &ValidationOptions::default())
+                    })
+            })
+            .as_ref()
+            .map_err(Clone::clone),
+    )
+}
+
+/// Creates a Subresource Integrity string for the given bytes
+pub(crate) fn digest_sri(algorithm: &str, bytes: &[u8]) -> Result<String, String> {
+    Ok(format!(
+        "{}-{}",
+        algorithm,
+        BASE64_STANDARD.encode(digest(sri_algorithm(algorithm)?, bytes))
+    ))
+}
+
+fn sri_algorithm(algorithm: &str) -> Result<&'static Algorithm, String> {
+    match algorithm {
+        "sha256" => Ok(&SHA256),
+        "sha384" => Ok(&SHA384),
+        "sha512" => Ok(&SHA512),
+        _ => Err(format!("Unsupported digestSRI algorithm: {}", algorithm)),
+    }
+}
+
+impl VerifiableCredential {
+    pub(crate) fn has_type(&self, vc_type: &str) -> bool {
+        match &self.vc_type {
+            TypeEnum::Single(single) => single == vc_type,
+            TypeEnum::Multiple(multiple) => multiple.iter().any(|single| single == vc_type),
+        }
+    }
+    /// Returns the json schema held by a schema credential, either a JsonSchemaCredential or a raw schema subject
+    pub fn json_schema(&self) -> Result<&Value, String> {
+        match self.has_type("JsonSchemaCredential") {
+            true => match self.credential_subject.get("type").and_then(Value::as_str) {
+                Some("JsonSchema") => self
+                    .credential_subject
+                    .get("jsonSchema")
+                    .ok_or_else(|| "JsonSchemaCredential has no jsonSchema".to_string()),
+                _ => Err("JsonSchemaCredential subject is not of type JsonSchema".into()),
+            },
+            false => Ok(&self.credential_subject),
+        }
+    }
+    /// Returns whether this schema credential is the one referred to by a credentialSchema
+    pub(crate) fn is_schema_for(&self, credential_schema: &CredentialSchema) -> bool {
+        match credential_schema.credential_type.as_str() {
+            "JsonSchemaCredential" => {
+                self.has_type("JsonSchemaCredential")
+                    && self.id.as_ref() == Some(&credential_schema.id)
+            }
+            _ => self.json_schema().is_ok_and(|json_schema| {
+                schema_id(json_schema) == Some(credential_schema.id.as_str())
+            }),
+        }
+    }
+    /// Checks the digestSRI of a credentialSchema against this schema credential
+    ///
+    /// JsonSchemaCredential digests cover the compact json of the whole signed credential,
+    /// JsonSchema digests cover the compact json of the schema itself
+    pub(crate) fn check_digest_sri(
+        &self,
+        credential_schema: &CredentialSchema,
+    ) -> Result<(), String> {
+        let Some(expected) = &credential_schema.digest_sri else {
+            return Ok(());
+        };
+        let resource = match credential_schema.credential_type.as_str() {
+            "JsonSchemaCredential" => to_string(self),
+            _ => to_string(self.json_schema()?),
+        }
+        .map_err(|e| e.to_string())?;
+        let (algorithm, _) = expected.split_once('-').ok_or("Malformed digestSRI")?;
+        match digest_sri(algorithm, resource.as_bytes())? == *expected {
+            true => Ok(()),
+            false => Err("Schema does not match digestSRI".into()),
+        }
+    }
+    /// Creates an unsigned JsonSchemaCredential wrapping a json schema
+    pub(crate) fn generate_schema(json_schema: Value) -> Result<Self, String> {
+        let id = schema_id(&json_schema).map(str::to_string);
+        let mut subject = json!({"type": "JsonSchema", "jsonSchema": json_schema});
+        if let Some(id) = id {
+            subject["id"] = id.into();
+        }
+        let mut vc = Self::generate(
+            subject,
+            CredentialSchema {
+                id: Url::parse(JSON_SCHEMA_CREDENTIAL_SCHEMA_ID).map_err(|e| e.to_string())?,
+                credential_type: "JsonSchema".to_string(),
+                digest_sri: None,
+            },
+        )?;
+        vc.vc_type = TypeEnum::Multiple(vec![
+            "VerifiableCredential".to_string(),
+            "JsonSchemaCredential".to_string(),
+        ]);
+        Ok(vc)
+    }
+}
+
+impl SchemaEnum {
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &CredentialSchema> {
+        match self {
+            SchemaEnum::Single(credential_schema) => std::slice::from_ref(credential_schema).iter(),
+            SchemaEnum::Multiple(credential_schemas) => credential_schemas.iter(),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::VerifiableCredential;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::{prelude::wasm_bindgen, JsError, JsValue};
+
+/// Kind of difference between two versions of a json schema
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SchemaChangeKind {
+    RequiredAdded,
+    RequiredRemoved,
+    PropertyAdded,
+    PropertyRemoved,
+    TypeNarrowed,
+    TypeWidened,
+    EnumAdded,
+    EnumRemoved,
+    EnumValueAdded,
+    EnumValueRemoved,
+    ConstraintTightened,
+    ConstraintLoosened,
+}
+
+/// A single difference between two versions of a json schema
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SchemaChange {
+    pub kind: SchemaChangeKind,
+    /// JSON pointer to the changed subschema
+    pub path: String,
+    /// Whether credentials valid under the old schema may be rejected by the new one
+    pub breaking: bool,
+    pub message: String,
+}
+
+/// Compares the json schemas of two schema credentials, listing how the new version differs from the old one
+///
+/// Properties, required properties, types, enums and value constraints like `minimum`, `maxLength`, `pattern`,
+/// `format` and `const` are compared recursively through `properties` and `items`. Combinators such as `allOf`
+/// and `$ref`s aren't followed, so re-validate a corpus of existing credentials for a definitive answer
+#[cfg(not(target_family = "wasm"))]
+pub fn diff(
+    old: &VerifiableCredential,
+    new: &VerifiableCredential,
+) -> Result<Vec<SchemaChange>, String> {
+    let mut changes = Vec::new();
+    diff_node(old.json_schema()?, new.json_schema()?, "", &mut changes);
+    Ok(changes)
+}
+
+#[cfg(target_family = "wasm")]
+#[wasm_bindgen]
+/// Compares the json schemas of two schema credentials, as an array of change objects
+pub fn diff(old: &VerifiableCredential, new: &VerifiableCredential) -> Result<JsValue, JsError> {
+    let mut changes = Vec::new();
+    diff_node(
+        old.json_schema().map_err(|e| JsError::new(&e))?,
+        new.json_schema().map_err(|e| JsError::new(&e))?,
+        "",
+        &mut changes,
+    );
+    Ok(changes.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+fn change(
+    changes: &mut Vec<SchemaChange>,
+    kind: SchemaChangeKind,
+    path: &str,
+    breaking: bool,
+    message: String,
+) {
+    changes.push(SchemaChange {
+        kind,
+        path: path.to_string(),
+        breaking,
+        message,
+    });
+}
+
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+/// Returns the set of types a subschema allows, or None if it allows any type
+fn types(schema: &Value) -> Option<BTreeSet<&str>> {
+    match schema.get("type")? {
+        Value::String(single) => Some(BTreeSet::from([single.as_str()])),
+        Value::Array(multiple) => Some(multiple.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn diff_node(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    diff_types(old, new, path, changes);
+    diff_enum(old, new, path, changes);
+    diff_required(old, new, path, changes);
+    diff_properties(old, new, path, changes);
+    diff_constraints(old, new, path, changes);
+    if let (Some(old_items), Some(new_items)) = (old.get("items"), new.get("items")) {
+        if old_items.is_object() && new_items.is_object() {
+            diff_node(old_items, new_items, &format!("{}/items", path), changes);
+        }
+    }
+}
+
+fn diff_types(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    let (old_types, new_types) = (types(old), types(new));
+    let allows = |types: &Option<BTreeSet<&str>>, single: &str| match types {
+        None => true,
+        Some(types) => types.contains(single) || (single == "integer" && types.contains("number")),
+    };
+    let removed: Vec<_> = match &old_types {
+        None if new_types.is_some() => vec!["any"],
+        None => vec![],
+        Some(old_types) => old_types
+            .iter()
+            .copied()
+            .filter(|single| !allows(&new_types, single))
+            .collect(),
+    };
+    let added: Vec<_> = match &new_types {
+        None if old_types.is_some() => vec!["any"],
+        None => vec![],
+        Some(new_types) => new_types
+            .iter()
+            .copied()
+            .filter(|single| !allows(&old_types, single))
+            .collect(),
+    };
+    if !removed.is_empty() {
+        change(
+            changes,
+            SchemaChangeKind::TypeNarrowed,
+            path,
+            true,
+            format!("Type no longer allows {}", removed.join(", ")),
+        );
+    }
+    if !added.is_empty() {
+        change(
+            changes,
+            SchemaChangeKind::TypeWidened,
+            path,
+            false,
+            format!("Type now allows {}", added.join(", ")),
+        );
+    }
+}
+
+fn diff_enum(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    match (
+        old.get("enum").and_then(Value::as_array),
+        new.get("enum").and_then(Value::as_array),
+    ) {
+        (None, Some(_)) => change(
+            changes,
+            SchemaChangeKind::EnumAdded,
+            path,
+            true,
+            "Values are now restricted to an enum".to_string(),
+        ),
+        (Some(_), None) => change(
+            changes,
+            SchemaChangeKind::EnumRemoved,
+            path,
+            false,
+            "Values are no longer restricted to an enum".to_string(),
+        ),
+        (Some(old_values), Some(new_values)) => {
+            for value in old_values
+                .iter()
+                .filter(|value| !new_values.contains(value))
+            {
+                change(
+                    changes,
+                    SchemaChangeKind::EnumValueRemoved,
+                    path,
+                    true,
+                    format!("Enum value {} was removed", value),
+                );
+            }
+            for value in new_values
+                .iter()
+                .filter(|value| !old_values.contains(value))
+            {
+                change(
+                    changes,
+                    SchemaChangeKind::EnumValueAdded,
+                    path,
+                    false,
+                    format!("Enum value {} was added", value),
+                );
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+fn diff_required(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    let required = |schema: &Value| -> Vec<String> {
+        schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| {
+                required
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let (old_required, new_required) = (required(old), required(new));
+    for name in new_required
+        .iter()
+        .filter(|name| !old_required.contains(name))
+    {
+        change(
+            changes,
+            SchemaChangeKind::RequiredAdded,
+            &format!("{}/properties/{}", path, escape(name)),
+            true,
+            format!("Property {} is now required", name),
+        );
+    }
+    for name in old_required
+        .iter()
+        .filter(|name| !new_required.contains(name))
+    {
+        change(
+            changes,
+            SchemaChangeKind::RequiredRemoved,
+            &format!("{}/properties/{}", path, escape(name)),
+            false,
+            format!("Property {} is no longer required", name),
+        );
+    }
+}
+
+fn diff_properties(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    let empty = Map::new();
+    let old_properties = old
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let new_properties = new
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let closed = new.get("additionalProperties") == Some(&Value::Bool(false));
+    for (name, old_property) in old_properties {
+        let property_path = format!("{}/properties/{}", path, escape(name));
+        match new_properties.get(name) {
+            Some(new_property) => diff_node(old_property, new_property, &property_path, changes),
+            None => change(
+                changes,
+                SchemaChangeKind::PropertyRemoved,
+                &property_path,
+                closed,
+                format!("Property {} was removed", name),
+            ),
+        }
+    }
+    // Credentials under an open schema may already carry the new property with a value it rejects
+    let open = old.get("additionalProperties") != Some(&Value::Bool(false));
+    for name in new_properties
+        .keys()
+        .filter(|name| !old_properties.contains_key(*name))
+    {
+        change(
+            changes,
+            SchemaChangeKind::PropertyAdded,
+            &format!("{}/properties/{}", path, escape(name)),
+            open,
+            format!("Property {} was added", name),
+        );
+    }
+}
+
+/// Keywords bounding a value from below, so raising them rejects more values
+const LOWER_BOUNDS: [&str; 5] = [
+    "minimum",
+    "exclusiveMinimum",
+    "minLength",
+    "minItems",
+    "minProperties",
+];
+/// Keywords bounding a value from above, so lowering them rejects more values
+const UPPER_BOUNDS: [&str; 5] = [
+    "maximum",
+    "exclusiveMaximum",
+    "maxLength",
+    "maxItems",
+    "maxProperties",
+];
+/// Keywords where any change may reject values the old one accepted
+const RESTRICTIONS: [&str; 5] = ["pattern", "format", "const", "multipleOf", "uniqueItems"];
+
+fn diff_constraints(old: &Value, new: &Value, path: &str, changes: &mut Vec<SchemaChange>) {
+    let keywords = LOWER_BOUNDS
+        .iter()
+        .chain(&UPPER_BOUNDS)
+        .chain(&RESTRICTIONS);
+    for &keyword in keywords {
+        let tightened = match (old.get(keyword), new.get(keyword)) {
+            (old_value, new_value) if old_value == new_value => continue,
+            (_, None | Some(Value::Bool(false))) => false,
+            (None | Some(Value::Bool(false)), Some(_)) => true,
+            (Some(Value::Number(old_bound)), Some(Value::Number(new_bound)))
+                if LOWER_BOUNDS.contains(&keyword) || UPPER_BOUNDS.contains(&keyword) =>
+            {
+                let (old_bound, new_bound) = (
+                    old_bound.as_f64().unwrap_or_default(),
+                    new_bound.as_f64().unwrap_or_default(),
+                );
+                match LOWER_BOUNDS.contains(&keyword) {
+                    true => new_bound > old_bound,
+                    false => new_bound < old_bound,
+                }
+            }
+            _ => true,
+        };
+        let (kind, message) = match tightened {
+            true => (SchemaChangeKind::ConstraintTightened, "tightened"),
+            false => (SchemaChangeKind::ConstraintLoosened, "loosened"),
+        };
+        change(
+            changes,
+            kind,
+            path,
+            tightened,
+            format!("Constraint {} was {}", keyword, message),
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::schema::schema_id;
+use crate::verifiable_credential::SignedSchema;
+use crate::VerifiableCredential;
+use serde_json::Value;
+use std::collections::HashMap;
+#[cfg(not(target_family = "wasm"))]
+use std::{fs::read_to_string, path::Path};
+use url::Url;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Store of signed schema credentials indexed by their schema `$id`
+#[derive(Default)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+pub struct SchemaRegistry {
+    trusted_keys: Vec<Vec<u8>>,
+    schemas: Vec<(VerifiableCredential, Vec<u8>)>,
+    index: HashMap<String, usize>,
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl SchemaRegistry {
+    #[cfg_attr(target_family = "wasm", wasm_bindgen(constructor))]
+    /// Creates an empty registry with no trusted keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a public key that schema credentials may be signed with
+    pub fn add_trusted_key(&mut self, public_key: Vec<u8>) {
+        self.trusted_keys.push(public_key);
+    }
+    /// Adds a schema credential, checking it was signed by one of the trusted keys
+    ///
+    /// JsonSchemaCredentials are indexed by both their credential id and their schema `$id`
+    pub fn insert(&mut self, schema: VerifiableCredential) -> Result<(), String> {
+        let mut ids = vec![schema_id(schema.json_schema()?)
+            .ok_or("No $id field in schema")?
+            .to_string()];
+        if let (true, Some(id)) = (schema.has_type("JsonSchemaCredential"), &schema.id) {
+            ids.push(id.to_string());
+        }
+        let public_key = self
+            .trusted_keys
+            .iter()
+            .find(|public_key| schema.verify(public_key).is_ok())
+            .ok_or("Schema is not signed by a trusted key")?
+            .clone();
+        let position = match ids.iter().find_map(|id| self.index.get(id)) {
+            Some(&position) => {
+                self.schemas[position] = (schema, public_key);
+                position
+            }
+            None => {
+                self.schemas.push((schema, public_key));
+                self.schemas.len() - 1
+            }
+        };
+        for id in ids {
+            self.index.insert(id, position);
+        }
+        Ok(())
+    }
+    /// Returns whether a schema with the given `$id` or credential id is in the registry
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.contains_key(id)
+    }
+}
+
+impl SchemaRegistry {
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a registry from every `.json` schema credential in a local directory
+    pub fn from_dir(path: &Path, trusted_keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        let mut registry = Self {
+            trusted_keys,
+            ..Self::default()
+        };
+        for entry in path.read_dir().map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let schema: Value = serde_json::from_str(
+                    &read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?,
+                )
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+                VerifiableCredential::new(schema, None)
+                    .and_then(|schema| registry.insert(schema))
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(registry)
+    }
+    /// Returns the positions of the schemas a credential's credentialSchema entries name, and of every schema
+    /// they reach through `$ref`s
+    fn positions_for(&self, verifiable_credential: &VerifiableCredential) -> Vec<usize> {
+        let mut ids: Vec<String> = verifiable_credential
+            .credential_schema
+            .iter()
+            .map(|credential_schema| credential_schema.id.to_string())
+            .collect();
+        let mut positions = Vec::new();
+        while let Some(id) = ids.pop() {
+            let Some(&position) = self.index.get(&id) else {
+                continue;
+            };
+            if positions.contains(&position) {
+                continue;
+            }
+            positions.push(position);
+            if let Ok(json_schema) = self.schemas[position].0.json_schema() {
+                references(json_schema, schema_id(json_schema), &mut ids);
+            }
+        }
+        positions
+    }
+    /// Returns the schemas a credential refers to, directly or through `$ref`s, along with the key they were signed with
+    #[cfg(not(target_family = "wasm"))]
+    pub(crate) fn schemas_for(
+        &self,
+        verifiable_credential: &VerifiableCredential,
+    ) -> Vec<SignedSchema<'_>> {
+        self.positions_for(verifiable_credential)
+            .into_iter()
+            .map(|position| {
+                let (schema, public_key) = &self.schemas[position];
+                SignedSchema::new(schema.clone(), public_key)
+            })
+            .collect()
+    }
+    /// Returns the schemas a credential refers to, directly or through `$ref`s, along with the key they were signed with
+    #[cfg(target_family = "wasm")]
+    pub(crate) fn schemas_for(
+        &self,
+        verifiable_credential: &VerifiableCredential,
+    ) -> Vec<SignedSchema> {
+        self.positions_for(verifiable_credential)
+            .into_iter()
+            .map(|position| {
+                let (schema, public_key) = &self.schemas[position];
+                SignedSchema::new(schema.clone(), public_key.clone())
+            })
+            .collect()
+    }
+}
+
+/// Collects the ids of the documents a json schema's `$ref`s point into, resolved against the schema's own id
+fn references(json_schema: &Value, base: Option<&str>, ids: &mut Vec<String>) {
+    match json_schema {
+        Value::Object(object) => {
+            if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                let document = reference.split('#').next().unwrap_or_default();
+                if !document.is_empty() {
+                    ids.push(match Url::parse(document) {
+                        Ok(_) => document.to_string(),
+                        Err(_) => base
+                            .and_then(|base| Url::parse(base).ok())
+                            .and_then(|base| base.join(document).ok())
+                            .map_or_else(|| document.to_string(), |url| url.to_string()),
+                    });
+                }
+            }
+            for value in object.values() {
+                references(value, base, ids);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                references(value, base, ids);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,438 @@
+use crate::jose::{open_compact, sign_compact};
+use crate::VerifiableCredential;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Media type of an SD-JWT secured credential
+pub const VC_SD_JWT_MEDIA_TYPE: &str = "application/vc+sd-jwt";
+/// `typ` of a key binding JWT
+const KB_JWT_TYPE: &str = "kb+jwt";
+/// Name of the digest algorithm, the only one supported
+const SD_ALG: &str = "sha-256";
+const SD: &str = "_sd";
+const ARRAY_ELEMENT: &str = "...";
+/// Seconds a key binding JWT may be issued in the future, to allow for clock skew
+const MAX_CLOCK_SKEW: i64 = 300;
+/// Seconds a key binding JWT is accepted for after it is issued
+const MAX_KEY_BINDING_AGE: i64 = 300;
+
+/// An SD-JWT split into its issuer signed JWT, disclosures and optional key binding JWT
+struct SdJwt<'a> {
+    jwt: &'a str,
+    disclosures: Vec<&'a str>,
+    key_binding: Option<&'a str>,
+}
+
+impl<'a> SdJwt<'a> {
+    fn parse(sd_jwt: &'a str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = sd_jwt.trim().split('~').collect();
+        if parts.len() < 2 {
+            return Err("Not an SD-JWT".into());
+        }
+        let key_binding = parts.pop().filter(|kb| !kb.is_empty());
+        Ok(Self {
+            jwt: parts.remove(0),
+            disclosures: parts,
+            key_binding,
+        })
+    }
+    /// Serializes the issuer signed JWT and disclosures, ending with `~`
+    fn presentation(&self) -> String {
+        let mut presentation = format!("{}~", self.jwt);
+        for disclosure in &self.disclosures {
+            presentation.push_str(disclosure);
+            presentation.push('~');
+        }
+        presentation
+    }
+    /// Returns each disclosure by its digest, decoded
+    fn decode_disclosures(&self) -> Result<HashMap<String, Vec<Value>>, String> {
+        let mut disclosures = HashMap::new();
+        for disclosure in &self.disclosures {
+            let decoded: Vec<Value> = serde_json::from_slice(
+                &BASE64_URL_SAFE_NO_PAD
+                    .decode(disclosure)
+                    .map_err(|e| e.to_string())?,
+            )
+            .map_err(|e| e.to_string())?;
+            if !(2..=3).contains(&decoded.len()) {
+                return Err("Disclosure is not a 2 or 3 element array".into());
+            }
+            if disclosures.insert(hash(disclosure), decoded).is_some() {
+                return Err("Disclosure given more than once".into());
+            }
+        }
+        Ok(disclosures)
+    }
+}
+
+fn hash(data: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, data.as_bytes()))
+}
+
+fn salt() -> Result<String, String> {
+    let mut salt = [0; 16];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| "Error generating salt")?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(salt))
+}
+
+/// Splits a JSON pointer into its unescaped reference tokens
+pub(crate) fn tokens(pointer: &str) -> Result<Vec<String>, String> {
+    match pointer.strip_prefix('/') {
+        Some(pointer) => Ok(pointer
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect()),
+        None => Err(format!(
+            "{} is not a JSON pointer to a subject claim",
+            pointer
+        )),
+    }
+}
+
+pub(crate) fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Replaces the claim at the pointer with a digest, returning its disclosure
+fn conceal(subject: &mut Value, pointer: &str) -> Result<String, String> {
+    let tokens = tokens(pointer)?;
+    let (name, parents) = tokens.split_last().ok_or("Empty JSON pointer")?;
+    let parent = parents
+        .iter()
+        .try_fold(subject, |value, token| match value {
+            Value::Object(object) => object.get_mut(token),
+            Value::Array(array) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get_mut(index)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No subject claim at {}", pointer))?;
+    match parent {
+        Value::Object(object) => {
+            if name == SD || name == ARRAY_ELEMENT {
+                return Err(format!("{} cannot be selectively disclosed", name));
+            }
+            let value = object
+                .remove(name)
+                .ok_or_else(|| format!("No subject claim at {}", pointer))?;
+            let disclosure = BASE64_URL_SAFE_NO_PAD.encode(
+                serde_json::to_vec(&json!([salt()?, name, value])).map_err(|e| e.to_string())?,
+            );
+            let digests = object
+                .entry(SD)
+                .or_insert_with(|| Value::Array(Vec::new()))
+                .as_array_mut()
+                .ok_or("_sd is not an array")?;
+            digests.push(hash(&disclosure).into());
+            // Sorting hides the original order of the claims
+            digests.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+            Ok(disclosure)
+        }
+        Value::Array(array) => {
+            let element = name
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get_mut(index))
+                .ok_or_else(|| format!("No subject claim at {}", pointer))?;
+            let disclosure = BASE64_URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&json!([salt()?, element])).map_err(|e| e.to_string())?);
+            *element = json!({ARRAY_ELEMENT: hash(&disclosure)});
+            Ok(disclosure)
+        }
+        _ => Err(format!("No subject claim at {}", pointer)),
+    }
+}
+
+/// Records the JSON pointer of a disclosed claim by its digest, rejecting digests that appear twice
+fn record(
+    revealed: &mut HashMap<String, String>,
+    digest: &str,
+    claim: String,
+) -> Result<(), String> {
+    match revealed.insert(digest.to_string(), claim) {
+        Some(_) => Err("Digest appears more than once".into()),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the digests in a value with the claims of the matching disclosures
+///
+/// Records the JSON pointer of each disclosed claim by its digest, and removes the digests of undisclosed claims
+fn reveal(
+    value: &mut Value,
+    pointer: &str,
+    disclosures: &HashMap<String, Vec<Value>>,
+    revealed: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    match value {
+        Value::Object(object) => {
+            if let Some(digests) = object.remove(SD) {
+                for digest in digests.as_array().ok_or("_sd is not an array")? {
+                    let digest = digest.as_str().ok_or("_sd digest is not a string")?;
+                    let Some(disclosure) = disclosures.get(digest) else {
+                        continue;
+                    };
+                    let [_, Value::String(name), claim] = disclosure.as_slice() else {
+                        return Err("Object disclosure is not a 3 element array".into());
+                    };
+                    if name == SD || name == ARRAY_ELEMENT || object.contains_key(name) {
+                        return Err(format!("Disclosure cannot set {}", name));
+                    }
+                    record(revealed, digest, format!("{}/{}", pointer, escape(name)))?;
+                    object.insert(name.clone(), claim.clone());
+                }
+            }
+            for (name, claim) in object.iter_mut() {
+                reveal(
+                    claim,
+                    &format!("{}/{}", pointer, escape(name)),
+                    disclosures,
+                    revealed,
+                )?;
+            }
+        }
+        Value::Array(array) => {
+            let mut elements = Vec::new();
+            for (index, mut element) in std::mem::take(array).into_iter().enumerate() {
+                let element_pointer = format!("{}/{}", pointer, index);
+                if let Some(Value::String(digest)) = element
+                    .as_object()
+                    .filter(|object| object.len() == 1)
+                    .and_then(|object| object.get(ARRAY_ELEMENT))
+                {
+                    let Some(disclosure) = disclosures.get(digest) else {
+                        continue;
+                    };
+                    let [_, claim] = disclosure.as_slice() else {
+                        return Err("Array element disclosure is not a 2 element array".into());
+                    };
+                    record(revealed, digest, element_pointer.clone())?;
+                    element = claim.clone();
+                }
+                reveal(&mut element, &element_pointer, disclosures, revealed)?;
+                elements.push(element);
+            }
+            *array = elements;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns the credential subject of an issuer signed JWT with the given disclosures applied,
+/// and the JSON pointer of each disclosed claim by its digest
+fn reveal_subject(
+    payload: &mut Map<String, Value>,
+    disclosures: &HashMap<String, Vec<Value>>,
+) -> Result<HashMap<String, String>, String> {
+    if payload
+        .get("_sd_alg")
+        .is_some_and(|alg| alg.as_str() != Some(SD_ALG))
+    {
+        return Err("Unsupported _sd_alg".into());
+    }
+    let mut revealed = HashMap::new();
+    reveal(
+        payload
+            .get_mut("credentialSubject")
+            .ok_or("SD-JWT has no credentialSubject")?,
+        "",
+        disclosures,
+        &mut revealed,
+    )?;
+    if revealed.len() != disclosures.len() {
+        return Err("Disclosure is not referenced by the SD-JWT".into());
+    }
+    Ok(revealed)
+}
+
+#[cfg_attr(target_family = "wasm", wasm_bindgen)]
+impl VerifiableCredential {
+    /// Secures a VerifiableCredential as an `application/vc+sd-jwt` SD-JWT, signed with the given private key
+    ///
+    /// Each subject claim named by a JSON pointer in `disclosable` is replaced with a salted digest and given as a
+    /// disclosure. A holder public key binds the SD-JWT to the holder, who must then sign a key binding JWT to present it
+    pub fn issue_sd_jwt(
+        &self,
+        private_key: &[u8],
+        disclosable: Vec<String>,
+        holder_public_key: Option<Vec<u8>>,
+    ) -> Result<String, String> {
+        let mut payload = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let payload = payload
+            .as_object_mut()
+            .ok_or("Credential is not an object")?;
+        // The proof would not verify once claims are withheld
+        payload.remove("proof");
+        let subject = payload
+            .get_mut("credentialSubject")
+            .ok_or("Credential has no credentialSubject")?;
+        let mut pointers = disclosable
+            .iter()
+            .map(|pointer| Ok((tokens(pointer)?.len(), pointer)))
+            .collect::<Result<Vec<_>, String>>()?;
+        // Nested claims are concealed first, so they stay disclosable within their concealed parents
+        pointers.sort_by(|(a, _), (b, _)| b.cmp(a));
+        pointers.dedup_by(|(_, a), (_, b)| a == b);
+        let mut disclosures = Vec::new();
+        for (_, pointer) in pointers {
+            disclosures.push(conceal(subject, pointer)?);
+        }
+        payload.insert("_sd_alg".into(), SD_ALG.into());
+        if let Some(holder_public_key) = holder_public_key {
+            payload.insert(
+                "cnf".into(),
+                json!({"jwk": {
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": BASE64_URL_SAFE_NO_PAD.encode(holder_public_key),
+                }}),
+            );
+        }
+        Ok(SdJwt {
+            jwt: &sign_compact(
+                payload,
+                VC_SD_JWT_MEDIA_TYPE.trim_start_matches("application/"),
+                Some("vc"),
+                private_key,
+            )?,
+            disclosures: disclosures.iter().map(String::as_str).collect(),
+            key_binding: None,
+        }
+        .presentation())
+    }
+    /// Returns the JSON pointers of the subject claims an SD-JWT can disclose
+    pub fn sd_jwt_claims(sd_jwt: &str) -> Result<Vec<String>, String> {
+        let sd_jwt = SdJwt::parse(sd_jwt)?;
+        let mut payload: Map<String, Value> =
+            open_compact(sd_jwt.jwt, VC_SD_JWT_MEDIA_TYPE)?.document()?;
+        let mut claims: Vec<String> = reveal_subject(&mut payload, &sd_jwt.decode_disclosures()?)?
+            .into_values()
+            .collect();
+        claims.sort();
+        Ok(claims)
+    }
+    /// Presents an SD-JWT, keeping only the disclosures of the subject claims named by a JSON pointer in `disclose`
+    ///
+    /// Disclosures of the parents and children of the chosen claims are kept too. A holder private key adds a
+    /// key binding JWT for the given audience and nonce
+    pub fn present_sd_jwt(
+        sd_jwt: &str,
+        disclose: Vec<String>,
+        holder_private_key: Option<Vec<u8>>,
+        audience: Option<String>,
+        nonce: Option<String>,
+    ) -> Result<String, String> {
+        let issued = SdJwt::parse(sd_jwt)?;
+        let mut payload: Map<String, Value> =
+            open_compact(issued.jwt, VC_SD_JWT_MEDIA_TYPE)?.document()?;
+        let revealed = reveal_subject(&mut payload, &issued.decode_disclosures()?)?;
+        let disclosures = issued
+            .disclosures
+            .iter()
+            .filter(|disclosure| {
+                let claim = &revealed[&hash(disclosure)];
+                disclose.iter().any(|chosen| {
+                    chosen == claim
+                        || chosen.starts_with(&format!("{}/", claim))
+                        || claim.starts_with(&format!("{}/", chosen))
+                })
+            })
+            .copied()
+            .collect();
+        let mut presentation = SdJwt {
+            jwt: issued.jwt,
+            disclosures,
+            key_binding: None,
+        }
+        .presentation();
+        if let Some(holder_private_key) = holder_private_key {
+            let key_binding = sign_compact(
+                &json!({
+                    "iat": Utc::now().timestamp(),
+                    "aud": audience.ok_or("Key binding needs an audience")?,
+                    "nonce": nonce.ok_or("Key binding needs a nonce")?,
+                    "sd_hash": hash(&presentation),
+                }),
+                KB_JWT_TYPE,
+                None,
+                &holder_private_key,
+            )?;
+            presentation.push_str(&key_binding);
+        }
+        Ok(presentation)
+    }
+    /// Verifies an SD-JWT was signed by the owner of the given public key, returning the credential with only the disclosed subject claims
+    ///
+    /// When the SD-JWT is bound to a holder key, its key binding JWT must be signed by that key, be recently issued, and
+    /// name the given audience and nonce, which are then required
+    pub fn verify_sd_jwt(
+        sd_jwt: &str,
+        public_key: &[u8],
+        audience: Option<String>,
+        nonce: Option<String>,
+    ) -> Result<Self, String> {
+        let sd_jwt = SdJwt::parse(sd_jwt)?;
+        let jws = open_compact(sd_jwt.jwt, VC_SD_JWT_MEDIA_TYPE)?;
+        jws.verify(public_key)?;
+        let mut payload: Map<String, Value> = jws.document()?;
+        reveal_subject(&mut payload, &sd_jwt.decode_disclosures()?)?;
+        payload.remove("_sd_alg");
+        let holder_public_key = payload
+            .remove("cnf")
+            .map(|cnf| match &cnf["jwk"] {
+                jwk if jwk["kty"] == "OKP" && jwk["crv"] == "Ed25519" => BASE64_URL_SAFE_NO_PAD
+                    .decode(jwk["x"].as_str().unwrap_or_default())
+                    .map_err(|e| e.to_string()),
+                _ => Err("Unsupported cnf key".to_string()),
+            })
+            .transpose()?;
+        match (holder_public_key, sd_jwt.key_binding) {
+            (Some(holder_public_key), Some(key_binding)) => {
+                let jws = open_compact(key_binding, KB_JWT_TYPE)?;
+                jws.verify(&holder_public_key)?;
+                let claims: Value = jws.document()?;
+                if claims["sd_hash"] != hash(&sd_jwt.presentation()) {
+                    return Err("Key binding JWT is for a different presentation".into());
+                }
+                let now = Utc::now().timestamp();
+                match claims["iat"].as_i64() {
+                    Some(iat) if iat > now + MAX_CLOCK_SKEW => {
+                        return Err("Key binding JWT has an invalid iat".into())
+                    }
+                    Some(iat) if iat < now - MAX_KEY_BINDING_AGE - MAX_CLOCK_SKEW => {
+                        return Err("Key binding JWT has expired".into())
+                    }
+                    Some(_) => {}
+                    None => return Err("Key binding JWT has an invalid iat".into()),
+                }
+                if claims["aud"] != audience.ok_or("Key binding needs an audience")? {
+                    return Err("Key binding JWT is for a different audience".into());
+                }
+                if claims["nonce"] != nonce.ok_or("Key binding needs a nonce")? {
+                    return Err("Key binding JWT has a different nonce".into());
+                }
+            }
+            (Some(_), None) => {
+                return Err("SD-JWT is bound to a holder key but has no key binding JWT".into())
+            }
+            (None, Some(_)) => return Err("SD-JWT has a key binding JWT but no holder key".into()),
+            (None, None) if audience.is_some() || nonce.is_some() => {
+                return Err("SD-JWT is not bound to a holder key".into())
+            }
+            (None, None) => {}
+        }
+        serde_json::from_value(Value::Object(payload)).map_err(|e| e.to_string())
+    }
+}
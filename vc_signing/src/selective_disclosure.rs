@@ -0,0 +1,496 @@
+use crate::did::did_key_from_public_key;
+use crate::jsonld::{skolemize, to_nquads, to_rdf, Quad};
+use crate::rdfc::canonical_labels;
+use crate::sd_jwt::{escape, tokens};
+use crate::{Proof, VerifiableCredential};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const SUBJECT: &str = "/credentialSubject";
+/// Deepest nesting of arrays and maps accepted in a proof value, which only ever nests 2 levels
+const MAX_DEPTH: usize = 4;
+
+/// Minimal cbor, for the components of a proof value
+pub(crate) enum Cbor {
+    Unsigned(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+}
+
+impl Cbor {
+    fn write(&self, out: &mut Vec<u8>) {
+        let head = |out: &mut Vec<u8>, major: u8, argument: u64| match argument {
+            0..=23 => out.push(major << 5 | argument as u8),
+            24..=0xff => out.extend([major << 5 | 24, argument as u8]),
+            0x100..=0xffff => {
+                out.push(major << 5 | 25);
+                out.extend((argument as u16).to_be_bytes());
+            }
+            0x10000..=0xffff_ffff => {
+                out.push(major << 5 | 26);
+                out.extend((argument as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(major << 5 | 27);
+                out.extend(argument.to_be_bytes());
+            }
+        };
+        match self {
+            Cbor::Unsigned(value) => head(out, 0, *value),
+            Cbor::Bytes(bytes) => {
+                head(out, 2, bytes.len() as u64);
+                out.extend(bytes);
+            }
+            Cbor::Text(text) => {
+                head(out, 3, text.len() as u64);
+                out.extend(text.as_bytes());
+            }
+            Cbor::Array(items) => {
+                head(out, 4, items.len() as u64);
+                for item in items {
+                    item.write(out);
+                }
+            }
+            Cbor::Map(entries) => {
+                head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.write(out);
+                    value.write(out);
+                }
+            }
+        }
+    }
+    fn read(input: &mut &[u8], depth: usize) -> Result<Self, String> {
+        let mut take = |len: usize| -> Result<Vec<u8>, String> {
+            let (taken, rest) = input.split_at_checked(len).ok_or("Truncated proof value")?;
+            *input = rest;
+            Ok(taken.to_vec())
+        };
+        let initial = take(1)?[0];
+        let argument = match initial & 0x1f {
+            argument @ 0..=23 => argument as u64,
+            size @ 24..=27 => take(1 << (size - 24))?
+                .iter()
+                .fold(0, |argument, byte| argument << 8 | *byte as u64),
+            _ => return Err("Unsupported proof value encoding".into()),
+        };
+        let len = usize::try_from(argument).map_err(|e| e.to_string())?;
+        Ok(match initial >> 5 {
+            0 => Cbor::Unsigned(argument),
+            2 => Cbor::Bytes(take(len)?),
+            3 => Cbor::Text(String::from_utf8(take(len)?).map_err(|e| e.to_string())?),
+            4 | 5 if depth >= MAX_DEPTH => return Err("Proof value is nested too deeply".into()),
+            // Every item takes at least a byte, so a longer array or map can only be truncated
+            4 | 5 if len > input.len() => return Err("Truncated proof value".into()),
+            4 => Cbor::Array(
+                (0..len)
+                    .map(|_| Cbor::read(input, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            ),
+            5 => Cbor::Map(
+                (0..len)
+                    .map(|_| Ok((Cbor::read(input, depth + 1)?, Cbor::read(input, depth + 1)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            _ => return Err("Unsupported proof value encoding".into()),
+        })
+    }
+    pub(crate) fn into_bytes(self) -> Result<Vec<u8>, String> {
+        match self {
+            Cbor::Bytes(bytes) => Ok(bytes),
+            _ => Err("Invalid proof value".into()),
+        }
+    }
+    pub(crate) fn into_array(self) -> Result<Vec<Cbor>, String> {
+        match self {
+            Cbor::Array(items) => Ok(items),
+            _ => Err("Invalid proof value".into()),
+        }
+    }
+    pub(crate) fn into_map(self) -> Result<Vec<(Cbor, Cbor)>, String> {
+        match self {
+            Cbor::Map(entries) => Ok(entries),
+            _ => Err("Invalid proof value".into()),
+        }
+    }
+    pub(crate) fn into_unsigned(self) -> Result<u64, String> {
+        match self {
+            Cbor::Unsigned(value) => Ok(value),
+            _ => Err("Invalid proof value".into()),
+        }
+    }
+    pub(crate) fn into_indexes(self) -> Result<Vec<usize>, String> {
+        self.into_array()?
+            .into_iter()
+            .map(|item| match item {
+                Cbor::Unsigned(index) => usize::try_from(index).map_err(|e| e.to_string()),
+                _ => Err("Invalid proof value".into()),
+            })
+            .collect()
+    }
+    pub(crate) fn into_texts(self) -> Result<Vec<String>, String> {
+        self.into_array()?
+            .into_iter()
+            .map(|item| match item {
+                Cbor::Text(text) => Ok(text),
+                _ => Err("Invalid proof value".into()),
+            })
+            .collect()
+    }
+}
+
+/// Encodes the components of a proof value after its header
+pub(crate) fn encode_proof_value(
+    cryptosuite: &str,
+    header: [u8; 3],
+    components: Vec<Cbor>,
+) -> String {
+    let mut bytes = header.to_vec();
+    Cbor::Array(components).write(&mut bytes);
+    Proof::encode_proof_value(cryptosuite, &bytes)
+}
+
+/// Returns the header and components of a proof value
+pub(crate) fn decode_proof_value(proof: &Proof) -> Result<([u8; 3], Vec<Cbor>), String> {
+    let bytes = proof.proof_value_bytes()?;
+    let (header, mut components) = bytes.split_at_checked(3).ok_or("Invalid proof value")?;
+    let components = Cbor::read(&mut components, 0)?.into_array()?;
+    Ok((
+        header.try_into().map_err(|_| "Invalid proof value")?,
+        components,
+    ))
+}
+
+/// Returns absolute JSON pointers for subject relative ones
+pub(crate) fn subject_pointers(pointers: &[String]) -> Vec<String> {
+    pointers
+        .iter()
+        .map(|pointer| format!("{}{}", SUBJECT, pointer))
+        .collect()
+}
+
+/// Returns the JSON pointers a base proof always discloses: every claim outside the credentialSubject, and the
+/// subject claims at the subject relative pointers
+pub(crate) fn absolute_mandatory_pointers(document: &Value, pointers: &[String]) -> Vec<String> {
+    document
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, value)| {
+            !value.is_null() && !["@context", "credentialSubject", "proof"].contains(&name.as_str())
+        })
+        .map(|(name, _)| format!("/{}", escape(name)))
+        .chain(subject_pointers(pointers))
+        .collect()
+}
+
+/// Returns whether any of the absolute JSON pointers is within the credentialSubject
+pub(crate) fn selects_subject(pointers: &[String]) -> bool {
+    pointers.iter().any(|pointer| {
+        pointer
+            .strip_prefix(SUBJECT)
+            .is_some_and(|claim| claim.is_empty() || claim.starts_with('/'))
+    })
+}
+
+/// Returns a credential as a JSON-LD document, without its proof
+pub(crate) fn unsecured_document(vc: &VerifiableCredential) -> Result<Value, String> {
+    let mut document = serde_json::to_value(vc).map_err(|e| e.to_string())?;
+    document
+        .as_object_mut()
+        .ok_or("Invalid credential")?
+        .remove("proof");
+    Ok(document)
+}
+
+/// Part of a JSON value that JSON pointers select
+enum Selection {
+    Whole(Value),
+    Object(Vec<(String, Selection)>),
+    Array(BTreeMap<usize, Selection>),
+}
+
+impl Selection {
+    /// Starts the selection of a value, with the id and type of an object, as createInitialSelection
+    fn new(value: &Value) -> Self {
+        match value {
+            Value::Object(object) => Selection::Object(
+                ["id", "type"]
+                    .into_iter()
+                    .filter_map(|name| match object.get(name) {
+                        None | Some(Value::Null) => None,
+                        Some(Value::String(id)) if name == "id" && id.starts_with("_:") => None,
+                        Some(value) => Some((name.to_string(), Selection::Whole(value.clone()))),
+                    })
+                    .collect(),
+            ),
+            Value::Array(_) => Selection::Array(BTreeMap::new()),
+            value => Selection::Whole(value.clone()),
+        }
+    }
+    /// Adds the value at the JSON pointer tokens to the selection, returning None when there is no such value
+    fn select(&mut self, source: &Value, tokens: &[String]) -> Option<()> {
+        let Some((token, tokens)) = tokens.split_first() else {
+            *self = Selection::Whole(source.clone());
+            return Some(());
+        };
+        let source = match source {
+            Value::Object(object) => object.get(token),
+            Value::Array(array) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get(index)),
+            _ => None,
+        }?;
+        let selection = match self {
+            Selection::Whole(_) => return Some(()),
+            Selection::Object(entries) => {
+                let index = match entries.iter().position(|(name, _)| name == token) {
+                    Some(index) => index,
+                    None => {
+                        entries.push((token.clone(), Selection::new(source)));
+                        entries.len() - 1
+                    }
+                };
+                &mut entries[index].1
+            }
+            Selection::Array(items) => items
+                .entry(token.parse().ok()?)
+                .or_insert_with(|| Selection::new(source)),
+        };
+        selection.select(source, tokens)
+    }
+    /// Returns the selected value, leaving out the array items that weren't selected
+    fn into_value(self) -> Value {
+        match self {
+            Selection::Whole(value) => value,
+            Selection::Object(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(name, selection)| (name, selection.into_value()))
+                    .collect(),
+            ),
+            Selection::Array(items) => {
+                Value::Array(items.into_values().map(Selection::into_value).collect())
+            }
+        }
+    }
+}
+
+/// Selects the parts of a JSON-LD document at absolute JSON pointers, with the id and type of every object on the
+/// way to them, as the selectJsonLd algorithm
+pub(crate) fn select(document: &Value, pointers: &[String]) -> Result<Option<Value>, String> {
+    if pointers.is_empty() {
+        return Ok(None);
+    }
+    let mut selection = Selection::new(document);
+    for pointer in pointers {
+        let tokens = match pointer.is_empty() {
+            true => Vec::new(),
+            false => tokens(pointer)?,
+        };
+        selection
+            .select(document, &tokens)
+            .ok_or_else(|| format!("No claim at {}", pointer))?;
+    }
+    let mut selection = selection.into_value();
+    if let (Some(selection), Some(context)) = (selection.as_object_mut(), document.get("@context"))
+    {
+        selection.insert("@context".to_string(), context.clone());
+    }
+    Ok(Some(selection))
+}
+
+/// Maps blank node labels to the labels they are given instead
+pub(crate) type LabelMap = HashMap<String, String>;
+
+/// Labels blank nodes with the HMAC of their canonical labels, so the labels of a selection reveal nothing about
+/// the rest of the credential
+pub(crate) fn hmac_labels(hmac_key: &[u8], canonical: LabelMap) -> LabelMap {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, hmac_key);
+    canonical
+        .into_iter()
+        .map(|(blank, label)| {
+            let hmac = hmac::sign(&key, label.as_bytes());
+            (blank, format!("u{}", BASE64_URL_SAFE_NO_PAD.encode(hmac)))
+        })
+        .collect()
+}
+
+/// Returns the index of a label such as c14n0 or b0
+pub(crate) fn label_index(label: &str, prefix: &str) -> Result<u64, String> {
+    label
+        .strip_prefix(prefix)
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| format!("Invalid blank node label {}", label))
+}
+
+/// Generates a random key for the HMAC of blank node labels
+pub(crate) fn generate_hmac_key() -> Result<Vec<u8>, String> {
+    let mut hmac_key = vec![0; 32];
+    SystemRandom::new()
+        .fill(&mut hmac_key)
+        .map_err(|_| "Error generating HMAC key")?;
+    Ok(hmac_key)
+}
+
+/// Labels blank nodes with the labels a derived proof gives their canonical labels
+pub(crate) fn replace_labels(
+    label_map: &LabelMap,
+    canonical: LabelMap,
+) -> Result<LabelMap, String> {
+    canonical
+        .into_iter()
+        .map(|(blank, label)| match label_map.get(&label) {
+            Some(label) => Ok((blank, label.clone())),
+            None => Err("Derived proof does not match the credential".to_string()),
+        })
+        .collect()
+}
+
+/// N-Quads of a group of JSON pointers
+pub(crate) struct Group {
+    /// Indexes of the N-Quads the pointers select
+    pub(crate) matching: Vec<usize>,
+    /// Indexes of the other N-Quads
+    pub(crate) non_matching: Vec<usize>,
+    /// Statements the pointers select, with the blank node labels of the skolemized credential
+    pub(crate) quads: Vec<Quad>,
+}
+
+/// Canonical N-Quads of a credential, with its blank nodes relabelled
+pub(crate) struct Canonicalized {
+    /// Label of each blank node of the skolemized credential
+    pub(crate) labels: LabelMap,
+    pub(crate) nquads: Vec<String>,
+    /// Groups in the order of the groups of JSON pointers
+    pub(crate) groups: Vec<Group>,
+}
+
+/// Canonicalizes a JSON-LD document, relabelling its blank nodes with `label_map`, and finds the N-Quads each
+/// group of JSON pointers selects, as the canonicalizeAndGroup algorithm
+pub(crate) fn canonicalize_and_group(
+    document: &Value,
+    label_map: impl FnOnce(LabelMap) -> Result<LabelMap, String>,
+    groups: &[&[String]],
+) -> Result<Canonicalized, String> {
+    let skolemized = skolemize(document)?;
+    let quads = to_rdf(&skolemized)?;
+    let labels = label_map(canonical_labels(&quads)?)?;
+    let nquads = to_nquads(&quads, &labels)?;
+    let groups = groups
+        .iter()
+        .map(|pointers| {
+            let quads = match select(&skolemized, pointers)? {
+                Some(selection) => to_rdf(&selection)?,
+                None => Vec::new(),
+            };
+            let selected: HashSet<String> = to_nquads(&quads, &labels)?.into_iter().collect();
+            let (matching, non_matching) =
+                (0..nquads.len()).partition(|index| selected.contains(&nquads[*index]));
+            Ok(Group {
+                matching,
+                non_matching,
+                quads,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(Canonicalized {
+        labels,
+        nquads,
+        groups,
+    })
+}
+
+/// Maps the canonical labels of the blank nodes in a selection to their labels in the whole credential, so the
+/// verifier of a derived proof can relabel the blank nodes of the disclosed credential
+pub(crate) fn verifier_label_map(quads: &[Quad], labels: &LabelMap) -> Result<LabelMap, String> {
+    canonical_labels(quads)?
+        .into_iter()
+        .map(|(blank, canonical)| match labels.get(&blank) {
+            Some(label) => Ok((canonical, label.clone())),
+            None => Err("Blank node has no label".to_string()),
+        })
+        .collect()
+}
+
+/// Splits N-Quads into those at the mandatory indexes of a derived proof, and the rest
+pub(crate) fn partition(
+    nquads: Vec<String>,
+    mandatory_indexes: &[usize],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    if mandatory_indexes.iter().any(|index| *index >= nquads.len()) {
+        return Err("Derived proof does not match the credential".into());
+    }
+    let (mandatory, non_mandatory): (Vec<_>, Vec<_>) = nquads
+        .into_iter()
+        .enumerate()
+        .partition(|(index, _)| mandatory_indexes.contains(index));
+    Ok((
+        mandatory.into_iter().map(|(_, nquad)| nquad).collect(),
+        non_mandatory.into_iter().map(|(_, nquad)| nquad).collect(),
+    ))
+}
+
+/// Hashes N-Quads with SHA-256
+pub(crate) fn hash_nquads(nquads: &[impl AsRef<str>]) -> Vec<u8> {
+    let nquads: String = nquads.iter().map(AsRef::as_ref).collect();
+    digest(&SHA256, nquads.as_bytes()).as_ref().to_vec()
+}
+
+/// Hashes the canonical N-Quads of the options of a proof, with the context of the credential it secures
+pub(crate) fn proof_hash(proof: &Proof, document: &Value) -> Result<Vec<u8>, String> {
+    let mut options = serde_json::to_value(proof).map_err(|e| e.to_string())?;
+    let options_object = options.as_object_mut().ok_or("Invalid proof")?;
+    options_object.remove("proofValue");
+    if let Some(context) = document.get("@context") {
+        options_object.insert("@context".to_string(), context.clone());
+    }
+    let quads = to_rdf(&options)?;
+    let nquads = to_nquads(&quads, &canonical_labels(&quads)?)?;
+    Ok(hash_nquads(&nquads))
+}
+
+/// Returns the options of a new proof, without its proof value
+pub(crate) fn new_proof(cryptosuite: &str, verification_method: String) -> Proof {
+    Proof {
+        proof_type: "DataIntegrityProof".to_string(),
+        created: Utc::now(),
+        cryptosuite: cryptosuite.to_string(),
+        proof_purpose: "assertionMethod".to_string(),
+        proof_value: String::new(),
+        verification_method: Some(verification_method),
+    }
+}
+
+/// Returns the did:key verification method of a public key
+pub(crate) fn verification_method(public_key: &[u8]) -> Result<String, String> {
+    let did = did_key_from_public_key(public_key)?;
+    let fragment = did.as_str().trim_start_matches("did:key:");
+    Ok(format!("{}#{}", did, fragment))
+}
+
+/// Builds the credential a derived proof discloses, from the parts of the credential at the combined JSON pointers
+pub(crate) fn derive_document(
+    document: &Value,
+    combined_pointers: &[String],
+    proof: Proof,
+) -> Result<VerifiableCredential, String> {
+    let mut revealed = select(document, combined_pointers)?.ok_or("Nothing to disclose")?;
+    revealed
+        .as_object_mut()
+        .ok_or("Invalid credential")?
+        .insert(
+            "proof".to_string(),
+            serde_json::to_value(proof).map_err(|e| e.to_string())?,
+        );
+    serde_json::from_value(revealed).map_err(|e| e.to_string())
+}
@@ -0,0 +1,57 @@
+use crate::compiled_schema::CompiledSchema;
+use crate::verifiable_credential::SignedSchema;
+use crate::VerifiableCredential;
+use serde::de::DeserializeOwned;
+use serde_json::{from_value, Value};
+
+/// View of a VerifiableCredential with its credentialSubject deserialized into a Rust type
+///
+/// Subject types can be generated from schema credentials with the `vc_schema_codegen` crate
+#[derive(Clone, Debug)]
+pub struct TypedVerifiableCredential<S> {
+    credential: VerifiableCredential,
+    subject: S,
+}
+
+impl<S: DeserializeOwned> TypedVerifiableCredential<S> {
+    /// Creates a typed credential from a json value, validating it against every schema listed in its credentialSchema
+    pub fn new(verifiable_credential: Value, schemas: Vec<SignedSchema>) -> Result<Self, String> {
+        VerifiableCredential::new_with_schemas(verifiable_credential, schemas)?.try_into()
+    }
+    /// Creates a typed credential from a json value, validating it against precompiled schemas
+    pub fn new_with_compiled(
+        verifiable_credential: Value,
+        schemas: &[CompiledSchema],
+    ) -> Result<Self, String> {
+        VerifiableCredential::new_with_compiled(verifiable_credential, schemas)?.try_into()
+    }
+}
+
+impl<S> TypedVerifiableCredential<S> {
+    /// Returns the typed credentialSubject
+    pub fn subject(&self) -> &S {
+        &self.subject
+    }
+    /// Returns the underlying VerifiableCredential, for signing, verifying or encoding
+    pub fn credential(&self) -> &VerifiableCredential {
+        &self.credential
+    }
+    /// Splits the view into the underlying VerifiableCredential and its typed credentialSubject
+    pub fn into_parts(self) -> (VerifiableCredential, S) {
+        (self.credential, self.subject)
+    }
+}
+
+impl<S: DeserializeOwned> TryFrom<VerifiableCredential> for TypedVerifiableCredential<S> {
+    type Error = String;
+
+    /// Deserializes the credentialSubject of an already validated VerifiableCredential
+    fn try_from(credential: VerifiableCredential) -> Result<Self, Self::Error> {
+        let subject = from_value(credential.credential_subject.clone())
+            .map_err(|e| format!("credentialSubject: {}", e))?;
+        Ok(Self {
+            credential,
+            subject,
+        })
+    }
+}
@@ -1,3 +1,11 @@
+#[cfg(feature = "bbs")]
+use crate::bbs::BBS_CRYPTOSUITE;
+use crate::compiled_schema::CompiledSchema;
+use crate::ecdsa_sd::ECDSA_SD_CRYPTOSUITE;
+use crate::schema::{
+    compile, digest_sri, meta_schema_id, meta_validator, schema_id, validate,
+    SchemaValidationError, ValidationOptions, SCHEMA_SCHEMA_ID,
+};
 use crate::{CredentialSchema, SchemaEnum, TypeEnum};
 use crate::{Proof, VerifiableCredential};
 use base64::{prelude::BASE64_STANDARD, Engine};
@@ -5,9 +13,9 @@ use chrono::Utc;
 use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::{from_str, to_string};
 #[cfg(not(target_family = "wasm"))]
-use serde_json::{from_value, Value};
+use serde_json::from_value;
+use serde_json::{to_string, Value};
 use std::error::Error;
 use url::Url;
 use uuid::Uuid;
@@ -18,11 +26,9 @@ use {
     wasm_bindgen::{prelude::wasm_bindgen, JsError, JsValue},
 };
 
-const SCHEMA_SCHEMA: &str = include_str!("../schema_schema.json");
-
 #[cfg(not(target_family = "wasm"))]
 pub struct SignedSchema<'a> {
-    vc: VerifiableCredential,
+    pub(crate) vc: VerifiableCredential,
     public_key: &'a [u8],
 }
 
@@ -31,12 +37,15 @@ impl<'a> SignedSchema<'a> {
     pub fn new(vc: VerifiableCredential, public_key: &'a [u8]) -> Self {
         Self { vc, public_key }
     }
+    pub(crate) fn public_key(&self) -> &[u8] {
+        self.public_key
+    }
 }
 
 #[cfg(target_family = "wasm")]
 #[wasm_bindgen]
 pub struct SignedSchema {
-    vc: VerifiableCredential,
+    pub(crate) vc: VerifiableCredential,
     public_key: Vec<u8>,
 }
 
@@ -49,76 +58,224 @@ impl SignedSchema {
     }
 }
 
+#[cfg(target_family = "wasm")]
+impl SignedSchema {
+    pub(crate) fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+/// Returns the credentialSchema entry for a credential generated against the given schema
+///
+/// Without a schema the subject is a raw json schema, checked against the meta-schema named by its `$schema`
+fn credential_schema_for(
+    schema: Option<&VerifiableCredential>,
+    subject: &Value,
+) -> Result<CredentialSchema, String> {
+    match schema {
+        None => Ok(CredentialSchema {
+            id: Url::parse(
+                subject
+                    .get("$schema")
+                    .and_then(Value::as_str)
+                    .and_then(meta_schema_id)
+                    .unwrap_or(SCHEMA_SCHEMA_ID),
+            )
+            .map_err(|e| e.to_string())?,
+            credential_type: "JsonSchema".to_string(),
+            digest_sri: None,
+        }),
+        Some(schema) if schema.has_type("JsonSchemaCredential") => Ok(CredentialSchema {
+            id: schema
+                .id
+                .clone()
+                .ok_or("No id field in schema credential")?,
+            credential_type: "JsonSchemaCredential".to_string(),
+            digest_sri: Some(digest_sri(
+                "sha384",
+                to_string(schema).map_err(|e| e.to_string())?.as_bytes(),
+            )?),
+        }),
+        Some(schema) => Ok(CredentialSchema {
+            id: Url::parse(schema_id(&schema.credential_subject).ok_or("No $id field in schema")?)
+                .map_err(|e| e.to_string())?,
+            credential_type: "JsonSchema".to_string(),
+            digest_sri: None,
+        }),
+    }
+}
+
+/// Returns json with the keys of every object inserted in sorted order
+///
+/// Signatures cover compact json, so its text mustn't depend on whether serde_json preserves insertion order
+pub(crate) fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Array(array) => Value::Array(array.iter().map(sorted).collect()),
+        Value::Object(object) => {
+            let mut keys: Vec<_> = object.keys().collect();
+            keys.sort();
+            Value::Object(
+                keys.into_iter()
+                    .map(|key| (key.clone(), sorted(&object[key])))
+                    .collect(),
+            )
+        }
+        value => value.clone(),
+    }
+}
+
 #[cfg_attr(target_family = "wasm", wasm_bindgen)]
 impl VerifiableCredential {
-    fn schema_check(
+    /// Returns the bytes a proof signs, the compact json of the credential without its proof and with sorted subject keys
+    ///
+    /// Every supported encoding decodes back to the same bytes, so signatures survive binary round trips
+    pub(crate) fn signing_input(&self) -> Result<Vec<u8>, String> {
+        let mut unsigned = self.clone();
+        unsigned.proof = None;
+        unsigned.credential_subject = sorted(&unsigned.credential_subject);
+        Ok(to_string(&unsigned)
+            .map_err(|e| e.to_string())?
+            .into_bytes())
+    }
+    /// Validates a credential against each of its credentialSchema entries, resolved by `resolve`
+    ///
+    /// Entries `resolve` has no schema for are checked against the bundled meta-schemas
+    fn check_each(
+        verifiable_credential: &Self,
+        resolve: impl Fn(&CredentialSchema, usize) -> Option<Result<Vec<SchemaValidationError>, String>>,
+    ) -> Vec<SchemaValidationError> {
+        let credential_schemas: Vec<_> = verifiable_credential.credential_schema.iter().collect();
+        if credential_schemas.is_empty() {
+            return vec![SchemaValidationError::credential_schema("Missing schema")];
+        }
+        let mut errors = Vec::new();
+        for credential_schema in &credential_schemas {
+            let result = match resolve(credential_schema, credential_schemas.len()) {
+                Some(result) => result,
+                None => match meta_validator(credential_schema, verifiable_credential) {
+                    Some(meta_validator) => meta_validator.and_then(|meta_validator| {
+                        verifiable_credential
+                            .json_schema()
+                            .map(|json_schema| validate(meta_validator, json_schema))
+                    }),
+                    None => Err("Missing schema".to_string()),
+                },
+            };
+            errors.extend(
+                result
+                    .unwrap_or_else(|e| vec![SchemaValidationError::credential_schema(e)])
+                    .into_iter()
+                    .map(|error| SchemaValidationError {
+                        schema_id: credential_schema.id.to_string(),
+                        ..error
+                    }),
+            );
+        }
+        errors
+    }
+    /// Validates a credential against its credentialSchema entries, looked up in a bundle of signed schemas
+    ///
+    /// Every schema in the bundle, or in the options' registry, that verifies can be the target of a `$ref`,
+    /// with `use_single_schema` a lone schema is used even if its id doesn't match
+    fn validation_errors(
+        verifiable_credential: &Self,
+        schemas: &[SignedSchema],
+        use_single_schema: bool,
+        options: &ValidationOptions,
+    ) -> Vec<SchemaValidationError> {
+        let registered = options
+            .registry()
+            .map(|registry| registry.schemas_for(verifiable_credential))
+            .unwrap_or_default();
+        let schemas: Vec<_> = schemas.iter().chain(&registered).collect();
+        let references: Vec<_> = schemas
+            .iter()
+            .filter(|schema| schema.vc.verify(schema.public_key()).is_ok())
+            .map(|schema| &schema.vc)
+            .collect();
+        Self::check_each(verifiable_credential, |credential_schema, count| {
+            let matching = schemas
+                .iter()
+                .find(|schema| schema.vc.is_schema_for(credential_schema));
+            let matching = match (matching, count, schemas.as_slice()) {
+                (None, 1, [schema]) if use_single_schema => Some(schema),
+                (matching, _, _) => matching,
+            };
+            matching.map(|schema| match schema.vc.verify(schema.public_key()) {
+                Ok(()) => schema
+                    .vc
+                    .check_digest_sri(credential_schema)
+                    .and_then(|_| schema.vc.json_schema())
+                    .and_then(|json_schema| compile(json_schema, &references, options))
+                    .map(|validator| {
+                        validate(&validator, &verifiable_credential.credential_subject)
+                    }),
+                Err(_) => Err("Failed to verify schema signature".to_string()),
+            })
+        })
+    }
+    /// Validates a credential against its credentialSchema entries, looked up in a set of compiled schemas
+    fn compiled_errors(
+        verifiable_credential: &Self,
+        schemas: &[CompiledSchema],
+    ) -> Vec<SchemaValidationError> {
+        Self::check_each(verifiable_credential, |credential_schema, _| {
+            schemas
+                .iter()
+                .find(|schema| schema.schema().is_schema_for(credential_schema))
+                .map(|schema| {
+                    schema.errors(credential_schema, &verifiable_credential.credential_subject)
+                })
+        })
+    }
+    fn checked(
         verifiable_credential: Self,
-        schema: Option<SignedSchema>,
+        errors: Vec<SchemaValidationError>,
     ) -> Result<Self, String> {
-        match schema {
-            None => {
-                if let SchemaEnum::Single(CredentialSchema {
-                    id,
-                    credential_type: _,
-                }) = &verifiable_credential.credential_schema
-                {
-                    if id.to_string() == "https://json-schema.org/draft/2020-12/schema" {
-                        let schema = from_str(SCHEMA_SCHEMA).map_err(|e| e.to_string())?;
-                        let validator =
-                            jsonschema::validator_for(&schema).map_err(|e| e.to_string())?;
-                        let errors: Vec<_> = validator
-                            .iter_errors(&verifiable_credential.credential_subject)
-                            .collect();
-                        match errors.is_empty() {
-                            true => Ok(verifiable_credential),
-                            false => {
-                                let errors: Vec<_> = errors
-                                    .iter()
-                                    .map(|e| {
-                                        format!(
-                                            "Schema validation error: {} At: {}",
-                                            e, e.instance_path
-                                        )
-                                    })
-                                    .collect();
-                                Err(errors.join("\n"))
-                            }
-                        }
-                    } else {
-                        Err("Missing schema".into())
-                    }
-                } else {
-                    Err("Missing schema".into())
-                }
-            }
-            Some(schema) => {
-                if schema.vc.verify(&schema.public_key).is_ok() {
-                    let validator = jsonschema::validator_for(&schema.vc.credential_subject)
-                        .map_err(|e| e.to_string())?;
-                    let errors: Vec<_> = validator
-                        .iter_errors(&verifiable_credential.credential_subject)
-                        .collect();
-                    match errors.is_empty() {
-                        true => Ok(verifiable_credential),
-                        false => {
-                            let errors: Vec<_> = errors
-                                .iter()
-                                .map(|e| {
-                                    format!(
-                                        "Schema validation error: {} At: {}",
-                                        e, e.instance_path
-                                    )
-                                })
-                                .collect();
-                            Err(errors.join("\n"))
-                        }
-                    }
-                } else {
-                    Err("Failed to verify schema signature".into())
-                }
-            }
+        match errors.is_empty() {
+            true => Ok(verifiable_credential),
+            false => Err(errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")),
         }
     }
+    fn schema_check(
+        verifiable_credential: Self,
+        schemas: Vec<SignedSchema>,
+        use_single_schema: bool,
+    ) -> Result<Self, String> {
+        let errors = Self::validation_errors(
+            &verifiable_credential,
+            &schemas,
+            use_single_schema,
+            &ValidationOptions::default(),
+        );
+        Self::checked(verifiable_credential, errors)
+    }
+    pub(crate) fn generate(
+        subject: Value,
+        credential_schema: CredentialSchema,
+    ) -> Result<Self, String> {
+        let generate = || -> Result<Self, Box<dyn Error>> {
+            Ok(Self {
+                context: vec![Url::parse("https://www.w3.org/ns/credentials/v2")?],
+                id: Some(Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?),
+                vc_type: TypeEnum::Single("VerifiableCredential".to_string()),
+                name: None,
+                description: None,
+                issuer: Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?,
+                valid_from: None,
+                valid_until: None,
+                credential_status: None,
+                credential_schema: SchemaEnum::Single(credential_schema),
+                credential_subject: subject,
+                proof: None,
+            })
+        };
+        generate().map_err(|e| e.to_string())
+    }
     #[cfg(not(target_family = "wasm"))]
     /// Creates a VerifiableCredential structure from a json value
     pub fn new(verifiable_credential: Value, schema: Option<SignedSchema>) -> Result<Self, String>
@@ -127,9 +284,72 @@ impl VerifiableCredential {
     {
         Self::schema_check(
             from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?,
-            schema,
+            schema.into_iter().collect(),
+            true,
+        )
+    }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a VerifiableCredential structure from a json value, validating it against every schema listed in its credentialSchema
+    pub fn new_with_schemas(
+        verifiable_credential: Value,
+        schemas: Vec<SignedSchema>,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        Self::schema_check(
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?,
+            schemas,
+            false,
         )
     }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a VerifiableCredential structure from a json value, validating it against a schema with the given options
+    ///
+    /// As with `new`, a lone schema is used even if its id doesn't match the credentialSchema
+    pub fn new_with_schema(
+        verifiable_credential: Value,
+        schema: Option<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let schemas: Vec<_> = schema.into_iter().collect();
+        let errors = Self::validation_errors(&verifiable_credential, &schemas, true, options);
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a VerifiableCredential structure from a json value, validating it against every schema listed in its credentialSchema with the given options
+    pub fn new_with_options(
+        verifiable_credential: Value,
+        schemas: Vec<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let errors = Self::validation_errors(&verifiable_credential, &schemas, false, options);
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a VerifiableCredential structure from a json value, validating it against precompiled schemas
+    pub fn new_with_compiled(
+        verifiable_credential: Value,
+        schemas: &[CompiledSchema],
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let errors = Self::compiled_errors(&verifiable_credential, schemas);
+        Self::checked(verifiable_credential, errors)
+    }
     #[cfg(target_family = "wasm")]
     #[wasm_bindgen(constructor)]
     pub fn new(
@@ -142,132 +362,184 @@ impl VerifiableCredential {
     {
         match create {
             true => {
-                let create = |input, schema_id| -> Result<Self, Box<dyn Error>> {
-                    Ok(Self {
-                        context: vec![Url::parse("https://www.w3.org/ns/credentials/v2")?],
-                        id: Some(Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?),
-                        vc_type: TypeEnum::Single("VerifiableCredential".to_string()),
-                        name: None,
-                        description: None,
-                        issuer: Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?,
-                        valid_from: None,
-                        valid_until: None,
-                        credential_status: None,
-                        credential_schema: SchemaEnum::Single(CredentialSchema {
-                            id: Url::parse(schema_id)?,
-                            credential_type: "JsonSchema".to_string(),
-                        }),
-                        credential_subject: from_value(input)?,
-                        proof: None,
-                    })
-                };
-                match schema {
-                    None => Self::schema_check(
-                        create(
-                            verifiable_credential,
-                            "https://json-schema.org/draft/2020-12/schema",
-                        )
-                        .map_err(|e| e.to_string())?,
-                        None,
-                    ),
-                    Some(schema) => Self::schema_check(
-                        create(
-                            verifiable_credential,
-                            schema
-                                .vc
-                                .credential_subject
-                                .get("$id")
-                                .ok_or("No $id field in schema")?
-                                .as_str()
-                                .ok_or("$id is not str")?,
-                        )
-                        .map_err(|e| e.to_string())?,
-                        Some(schema),
-                    ),
-                }
+                let subject = from_value(verifiable_credential).map_err(|e| e.to_string())?;
+                let credential_schema =
+                    credential_schema_for(schema.as_ref().map(|schema| &schema.vc), &subject)?;
+                Self::schema_check(
+                    Self::generate(subject, credential_schema)?,
+                    schema.into_iter().collect(),
+                    true,
+                )
             }
             false => Self::schema_check(
                 from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?,
-                schema,
+                schema.into_iter().collect(),
+                true,
             ),
         }
     }
+    #[cfg(target_family = "wasm")]
+    /// Creates a VerifiableCredential structure from a javascript object, validating it against every schema listed in its credentialSchema
+    pub fn new_with_schemas(
+        verifiable_credential: JsValue,
+        schemas: Vec<SignedSchema>,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        Self::schema_check(
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?,
+            schemas,
+            false,
+        )
+    }
+    #[cfg(target_family = "wasm")]
+    /// Creates a VerifiableCredential structure from a javascript object, validating it against a schema with the given options
+    ///
+    /// As with `new`, a lone schema is used even if its id doesn't match the credentialSchema
+    pub fn new_with_schema(
+        verifiable_credential: JsValue,
+        schema: Option<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let schemas: Vec<_> = schema.into_iter().collect();
+        let errors = Self::validation_errors(&verifiable_credential, &schemas, true, options);
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(target_family = "wasm")]
+    /// Creates a VerifiableCredential structure from a javascript object, validating it against every schema listed in its credentialSchema with the given options
+    pub fn new_with_options(
+        verifiable_credential: JsValue,
+        schemas: Vec<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let errors = Self::validation_errors(&verifiable_credential, &schemas, false, options);
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(target_family = "wasm")]
+    /// Creates a VerifiableCredential structure from a javascript object, validating it against precompiled schemas
+    pub fn new_with_compiled(
+        verifiable_credential: JsValue,
+        schemas: Vec<CompiledSchema>,
+    ) -> Result<Self, String>
+    where
+        Self: DeserializeOwned,
+    {
+        let verifiable_credential =
+            from_value::<Self>(verifiable_credential).map_err(|e| e.to_string())?;
+        let errors = Self::compiled_errors(&verifiable_credential, &schemas);
+        Self::checked(verifiable_credential, errors)
+    }
     /// Signs a VerifiableCredential with the given private key
     pub fn sign(mut self, private_key: &[u8]) -> Result<Self, String>
     where
         Self: Serialize + Sized,
     {
         let private_key = Ed25519KeyPair::from_pkcs8(private_key).map_err(|e| e.to_string())?;
-        let proof_value = private_key.sign(to_string(&self).map_err(|e| e.to_string())?.as_bytes());
+        let proof_value = private_key.sign(&self.signing_input()?);
         self.proof = Some(Proof {
             proof_type: "DataIntegrityProof".to_string(),
             created: Utc::now(),
             cryptosuite: "eddsa-rdfc-2022".to_string(),
             proof_purpose: "assertionMethod".to_string(),
             proof_value: BASE64_STANDARD.encode(proof_value.as_ref()),
+            verification_method: None,
         });
         Ok(self)
     }
     /// Verifies a VerifiableCredential was signed by the owner of the given public key
+    ///
+    /// BBS proofs are verified with a BLS12-381 public key, ECDSA-SD proofs with a P-256 public key,
+    /// and other proofs with an ED25519 public key. Derived BBS proofs made with a presentation header are
+    /// verified with `verify_bbs`
     pub fn verify(&self, public_key: &[u8]) -> Result<(), String>
     where
         Self: Serialize + Clone,
     {
-        let mut clone = self.clone();
+        match self.proof.as_ref().map(|proof| proof.cryptosuite.as_str()) {
+            #[cfg(feature = "bbs")]
+            Some(BBS_CRYPTOSUITE) => return self.verify_bbs(public_key, &[]),
+            Some(ECDSA_SD_CRYPTOSUITE) => return self.verify_ecdsa_sd(public_key),
+            _ => {}
+        }
         let public_key = UnparsedPublicKey::new(&ED25519, public_key);
         let proof = BASE64_STANDARD
-            .decode(clone.proof.take().ok_or("VC is unsigned")?.proof_value)
+            .decode(&self.proof.as_ref().ok_or("VC is unsigned")?.proof_value)
             .map_err(|e| e.to_string())?;
         public_key
-            .verify(
-                to_string(&clone).map_err(|e| e.to_string())?.as_bytes(),
-                &proof,
-            )
+            .verify(&self.signing_input()?, &proof)
             .map_err(|_| "Failed to verify".into())
     }
     #[cfg(not(target_family = "wasm"))]
+    /// Returns every way a VerifiableCredential fails to match the schemas listed in its credentialSchema, validated with the given options
+    pub fn schema_errors(
+        &self,
+        schemas: Vec<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Vec<SchemaValidationError> {
+        Self::validation_errors(self, &schemas, false, options)
+    }
+    #[cfg(target_family = "wasm")]
+    /// Returns every way a VerifiableCredential fails to match the schemas listed in its credentialSchema, validated with the given options, as an array of objects
+    pub fn schema_errors(
+        &self,
+        schemas: Vec<SignedSchema>,
+        options: &ValidationOptions,
+    ) -> Result<JsValue, JsError> {
+        Ok(Self::validation_errors(self, &schemas, false, options)
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+    #[cfg(not(target_family = "wasm"))]
     /// Creates a VerifiableCredential structure from json raw subject & schema with random UUIDs
     pub fn create(subject: Value, schema: Option<SignedSchema>) -> Result<Self, String> {
-        let create = |input, schema_id| -> Result<Self, Box<dyn Error>> {
-            Ok(Self {
-                context: vec![Url::parse("https://www.w3.org/ns/credentials/v2")?],
-                id: Some(Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?),
-                vc_type: TypeEnum::Single("VerifiableCredential".to_string()),
-                name: None,
-                description: None,
-                issuer: Url::parse(&format!("urn:uuid:{}", Uuid::new_v4()))?,
-                valid_from: None,
-                valid_until: None,
-                credential_status: None,
-                credential_schema: SchemaEnum::Single(CredentialSchema {
-                    id: Url::parse(schema_id)?,
-                    credential_type: "JsonSchema".to_string(),
-                }),
-                credential_subject: input,
-                proof: None,
-            })
-        };
-        match schema {
-            None => Self::schema_check(
-                create(subject, "https://json-schema.org/draft/2020-12/schema")
-                    .map_err(|e| e.to_string())?,
-                None,
-            ),
-            Some(schema) => Self::schema_check(
-                create(
-                    subject,
-                    schema
-                        .vc
-                        .credential_subject
-                        .get("$id")
-                        .ok_or("No $id field in schema")?
-                        .as_str()
-                        .ok_or("$id is not str")?,
-                )
-                .map_err(|e| e.to_string())?,
-                Some(schema),
-            ),
-        }
+        let credential_schema =
+            credential_schema_for(schema.as_ref().map(|schema| &schema.vc), &subject)?;
+        Self::schema_check(
+            Self::generate(subject, credential_schema)?,
+            schema.into_iter().collect(),
+            true,
+        )
+    }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a VerifiableCredential structure from json raw subject & a precompiled schema with random UUIDs
+    pub fn create_with_compiled(subject: Value, schema: &CompiledSchema) -> Result<Self, String> {
+        let credential_schema = credential_schema_for(Some(schema.schema()), &subject)?;
+        let verifiable_credential = Self::generate(subject, credential_schema)?;
+        let errors = Self::compiled_errors(&verifiable_credential, std::slice::from_ref(schema));
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(target_family = "wasm")]
+    /// Creates a VerifiableCredential structure from a raw javascript subject & a precompiled schema with random UUIDs
+    pub fn create_with_compiled(subject: JsValue, schema: &CompiledSchema) -> Result<Self, String> {
+        let subject = from_value(subject).map_err(|e| e.to_string())?;
+        let credential_schema = credential_schema_for(Some(schema.schema()), &subject)?;
+        let verifiable_credential = Self::generate(subject, credential_schema)?;
+        let errors = Self::compiled_errors(&verifiable_credential, std::slice::from_ref(schema));
+        Self::checked(verifiable_credential, errors)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    /// Creates a JsonSchemaCredential wrapping a raw json schema with random UUIDs
+    pub fn create_schema(json_schema: Value) -> Result<Self, String> {
+        Self::schema_check(Self::generate_schema(json_schema)?, Vec::new(), false)
+    }
+    #[cfg(target_family = "wasm")]
+    /// Creates a JsonSchemaCredential wrapping a raw json schema with random UUIDs
+    pub fn create_schema(json_schema: JsValue) -> Result<Self, String> {
+        Self::schema_check(
+            Self::generate_schema(from_value(json_schema).map_err(|e| e.to_string())?)?,
+            Vec::new(),
+            false,
+        )
     }
     #[cfg(target_family = "wasm")]
     /// Converts a VerifiableCredential to a JavaScript object
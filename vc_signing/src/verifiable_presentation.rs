@@ -1,12 +1,14 @@
-use crate::{Proof, VerifiablePresentation};
+use crate::did::public_key_from_did_key;
+use crate::verifiable_credential::sorted;
+use crate::{Proof, VerifiableCredentialEnum, VerifiablePresentation};
 use base64::{prelude::BASE64_STANDARD, Engine};
 use chrono::Utc;
 use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::to_string;
 #[cfg(not(target_family = "wasm"))]
-use serde_json::{from_value, Value};
+use serde_json::from_value;
+use serde_json::{to_string, Value};
 #[cfg(target_family = "wasm")]
 use {
     serde::Serializer,
@@ -16,6 +18,21 @@ use {
 
 #[cfg_attr(target_family = "wasm", wasm_bindgen)]
 impl VerifiablePresentation {
+    /// Returns the bytes a proof signs, the compact json of the presentation without its proof and with sorted subject keys
+    pub(crate) fn signing_input(&self) -> Result<Vec<u8>, String> {
+        let mut unsigned = self.clone();
+        unsigned.proof = None;
+        let credentials = match &mut unsigned.verifiable_credential {
+            VerifiableCredentialEnum::Single(vc) => vec![vc.as_mut()],
+            VerifiableCredentialEnum::Multiple(vcs) => vcs.iter_mut().collect(),
+        };
+        for vc in credentials {
+            vc.credential_subject = sorted(&vc.credential_subject);
+        }
+        Ok(to_string(&unsigned)
+            .map_err(|e| e.to_string())?
+            .into_bytes())
+    }
     #[cfg(not(target_family = "wasm"))]
     /// Creates a VerifiablePresentation structure from a json value
     pub fn new(verifiable_presentation: Value) -> Result<Self, String>
@@ -38,13 +55,14 @@ impl VerifiablePresentation {
         Self: Serialize + Sized,
     {
         let private_key = Ed25519KeyPair::from_pkcs8(private_key).map_err(|e| e.to_string())?;
-        let proof_value = private_key.sign(to_string(&self).map_err(|e| e.to_string())?.as_bytes());
+        let proof_value = private_key.sign(&self.signing_input()?);
         self.proof = Some(Proof {
             proof_type: "DataIntegrityProof".to_string(),
             created: Utc::now(),
             cryptosuite: "eddsa-rdfc-2022".to_string(),
             proof_purpose: "assertionMethod".to_string(),
             proof_value: BASE64_STANDARD.encode(proof_value.as_ref()),
+            verification_method: None,
         });
         Ok(self)
     }
@@ -53,18 +71,53 @@ impl VerifiablePresentation {
     where
         Self: Serialize + Clone,
     {
-        let mut clone = self.clone();
         let public_key = UnparsedPublicKey::new(&ED25519, public_key);
         let proof = BASE64_STANDARD
-            .decode(clone.proof.take().ok_or("VP is unsigned")?.proof_value)
+            .decode(&self.proof.as_ref().ok_or("VP is unsigned")?.proof_value)
             .map_err(|e| e.to_string())?;
         public_key
-            .verify(
-                to_string(&clone).map_err(|e| e.to_string())?.as_bytes(),
-                &proof,
-            )
+            .verify(&self.signing_input()?, &proof)
             .map_err(|_| "Failed to verify".into())
     }
+    /// Verifies a VerifiablePresentation was signed by its holder, and that the holder is the subject of every credential it contains
+    pub fn verify_holder_binding(&self, public_key: &[u8]) -> Result<(), String>
+    where
+        Self: Serialize + Clone,
+    {
+        self.verify(public_key)?;
+        let holder = self.holder.as_ref().ok_or("VP has no holder")?;
+        if public_key_from_did_key(holder)? != public_key {
+            return Err("Signing key does not resolve to holder".into());
+        }
+        let credentials = match &self.verifiable_credential {
+            VerifiableCredentialEnum::Single(vc) => vec![vc.as_ref()],
+            VerifiableCredentialEnum::Multiple(vcs) => vcs.iter().collect(),
+        };
+        let unbound: Vec<_> = credentials
+            .iter()
+            .enumerate()
+            .filter(|(_, vc)| {
+                let subjects = match &vc.credential_subject {
+                    Value::Array(subjects) => subjects.iter().collect(),
+                    subject => vec![subject],
+                };
+                !subjects.iter().any(|subject| {
+                    subject.get("id").and_then(Value::as_str) == Some(holder.as_str())
+                })
+            })
+            .map(|(index, vc)| match &vc.id {
+                Some(id) => id.to_string(),
+                None => format!("#{}", index),
+            })
+            .collect();
+        match unbound.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "Credentials not bound to holder: {}",
+                unbound.join(", ")
+            )),
+        }
+    }
     #[cfg(target_family = "wasm")]
     /// Converts a VerifiablePresentation to a JavaScript object
     pub fn to_object(&self) -> Result<JsValue, JsError> {
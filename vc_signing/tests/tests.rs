@@ -1,6 +1,14 @@
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use vc_signing::compiled_schema::{CompiledSchema, SchemaCache};
+use vc_signing::schema::ValidationOptions;
+use vc_signing::schema_diff::{self, SchemaChangeKind};
+use vc_signing::schema_registry::SchemaRegistry;
+use vc_signing::typed::TypedVerifiableCredential;
 use vc_signing::verifiable_credential::SignedSchema;
-use vc_signing::{SignatureKeyPair, VerifiableCredential};
+use vc_signing::{SignatureKeyPair, VerifiableCredential, VerifiablePresentation};
 
 fn vc_one() -> Value {
     json!({
@@ -64,6 +72,34 @@ fn schema() -> Value {
     })
 }
 
+fn schema_two() -> Value {
+    json!({
+        "@context": ["https://www.w3.org/ns/credentials/v2"],
+        "credentialSchema": {
+            "id": "https://json-schema.org/draft/2020-12/schema",
+            "type": "JsonSchema"
+        },
+        "credentialSubject": {
+            "$id": "urn:uuid:0f3f5a0e-7c62-4a8f-8a53-37c2b1f4e5d9",
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "description": "A second example schema",
+            "properties": {
+                "field": {
+                    "description": "field",
+                    "type": "string"
+                }
+            },
+            "required": ["field"],
+            "title": "example_two",
+            "type": "object"
+        },
+        "id": "urn:uuid:c1b8f9d4-56a3-4f0e-b6f7-2a9d3e8c1f40",
+        "issuer": "urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671",
+        "type": ["VerifiableCredential", "Schema"],
+        "validFrom": "2024-11-15T15:21:33.057078058Z"
+    })
+}
+
 #[test]
 fn basic_test() {
     let SignatureKeyPair {
@@ -164,3 +200,1565 @@ fn wrong_key() {
     .unwrap();
     assert!(vc.verify(&keys_two.public_key).is_err());
 }
+
+#[test]
+fn multiple_schemas() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schemas = || {
+        vec![
+            SignedSchema::new(
+                VerifiableCredential::new(schema(), None)
+                    .unwrap()
+                    .sign(&private_key)
+                    .unwrap(),
+                &public_key,
+            ),
+            SignedSchema::new(
+                VerifiableCredential::new(schema_two(), None)
+                    .unwrap()
+                    .sign(&private_key)
+                    .unwrap(),
+                &public_key,
+            ),
+        ]
+    };
+    let mut vc = vc_one();
+    vc["credentialSchema"] = json!([
+        {"id": "urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3", "type": "JsonSchema"},
+        {"id": "urn:uuid:0f3f5a0e-7c62-4a8f-8a53-37c2b1f4e5d9", "type": "JsonSchema"}
+    ]);
+    let error = VerifiableCredential::new_with_schemas(vc.clone(), schemas()).unwrap_err();
+    assert!(error.starts_with("urn:uuid:0f3f5a0e-7c62-4a8f-8a53-37c2b1f4e5d9: "));
+    assert!(!error.contains("urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3"));
+    vc["credentialSubject"]["field"] = json!("example_field");
+    assert!(VerifiableCredential::new_with_schemas(vc.clone(), schemas()).is_ok());
+    assert!(VerifiableCredential::new_with_schemas(vc, schemas().split_off(1)).is_err());
+}
+
+#[test]
+fn structured_errors() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schemas = || {
+        vec![SignedSchema::new(
+            VerifiableCredential::new(schema(), None)
+                .unwrap()
+                .sign(&private_key)
+                .unwrap(),
+            &public_key,
+        )]
+    };
+    let mut vc = vc_one();
+    vc["credentialSubject"]["id"] = json!(5);
+    vc["credentialSchema"] = json!([
+        {"id": "urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3", "type": "JsonSchema"},
+        {"id": "urn:uuid:0f3f5a0e-7c62-4a8f-8a53-37c2b1f4e5d9", "type": "JsonSchema"}
+    ]);
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let errors = vc.schema_errors(schemas(), &ValidationOptions::new());
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0].schema_id,
+        "urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3"
+    );
+    assert_eq!(errors[0].instance_path, "/id");
+    assert_eq!(errors[0].schema_path, "/properties/id/type");
+    assert_eq!(errors[0].keyword, "type");
+    assert_eq!(
+        errors[1].schema_id,
+        "urn:uuid:0f3f5a0e-7c62-4a8f-8a53-37c2b1f4e5d9"
+    );
+    assert_eq!(errors[1].keyword, "credentialSchema");
+    assert_eq!(errors[1].message, "Missing schema");
+    assert_eq!(
+        serde_json::to_value(&errors[0]).unwrap()["instancePath"],
+        "/id"
+    );
+}
+
+#[test]
+fn compiled_schema_cache() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let cache = SchemaCache::new();
+    let compiled = cache
+        .get_or_compile(SignedSchema::new(schema_vc.clone(), &public_key), vec![])
+        .unwrap();
+    cache
+        .get_or_compile(SignedSchema::new(schema_vc.clone(), &public_key), vec![])
+        .unwrap();
+    assert_eq!(cache.len(), 1);
+    let wrong_key = SignatureKeyPair::new().unwrap().public_key;
+    assert!(cache
+        .get_or_compile(SignedSchema::new(schema_vc, &wrong_key), vec![])
+        .is_err());
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                assert!(VerifiableCredential::new_with_compiled(
+                    vc_one(),
+                    std::slice::from_ref(&compiled)
+                )
+                .is_ok());
+                assert!(VerifiableCredential::new_with_compiled(
+                    vc_two(),
+                    std::slice::from_ref(&compiled)
+                )
+                .is_err());
+                assert!(VerifiableCredential::create_with_compiled(
+                    json!({"id": "example_id"}),
+                    &compiled
+                )
+                .is_ok());
+            });
+        }
+    });
+}
+
+#[test]
+fn format_validation() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let mut schema = schema();
+    schema["credentialSubject"]["properties"]["issued"] =
+        json!({"type": "string", "format": "date-time"});
+    schema["credentialSubject"]["properties"]["nhs"] =
+        json!({"type": "string", "format": "nhs-number"});
+    let schemas = || {
+        vec![SignedSchema::new(
+            VerifiableCredential::new(schema.clone(), None)
+                .unwrap()
+                .sign(&private_key)
+                .unwrap(),
+            &public_key,
+        )]
+    };
+    let options = ValidationOptions::new()
+        .should_validate_formats(true)
+        .with_format_pattern("nhs-number".to_string(), r"^\d{3} \d{3} \d{4}$")
+        .unwrap();
+    let mut vc = vc_one();
+    vc["credentialSubject"]["issued"] = json!("not a date");
+    assert!(VerifiableCredential::new_with_schemas(vc.clone(), schemas()).is_ok());
+    let error =
+        VerifiableCredential::new_with_options(vc.clone(), schemas(), &options).unwrap_err();
+    assert!(error.contains("/issued"));
+    vc["credentialSubject"]["issued"] = json!("2024-11-15T15:21:33Z");
+    vc["credentialSubject"]["nhs"] = json!("4010232137");
+    assert!(VerifiableCredential::new_with_options(vc.clone(), schemas(), &options).is_err());
+    vc["credentialSubject"]["nhs"] = json!("401 023 2137");
+    assert!(VerifiableCredential::new_with_options(vc.clone(), schemas(), &options).is_ok());
+    vc["credentialSchema"]["id"] = json!("urn:uuid:00000000-0000-0000-0000-000000000000");
+    assert!(VerifiableCredential::new_with_options(vc.clone(), schemas(), &options).is_err());
+    let schema = schemas().pop();
+    assert!(VerifiableCredential::new_with_schema(vc.clone(), schema, &options).is_ok());
+    vc["credentialSubject"]["issued"] = json!("not a date");
+    let schema = schemas().pop();
+    assert!(VerifiableCredential::new_with_schema(vc, schema, &options).is_err());
+}
+
+#[test]
+fn schema_evolution() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let old = VerifiableCredential::new(schema(), None).unwrap();
+    let mut new_schema = schema();
+    new_schema["credentialSubject"]["properties"]["id"]["enum"] = json!(["example_id"]);
+    new_schema["credentialSubject"]["properties"]["level"] =
+        json!({"type": "integer", "enum": [1, 2]});
+    let new = VerifiableCredential::new(new_schema.clone(), None).unwrap();
+    let changes = schema_diff::diff(&old, &new).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].kind, SchemaChangeKind::EnumAdded);
+    assert_eq!(changes[0].path, "/properties/id");
+    assert!(changes[0].breaking);
+    assert_eq!(changes[1].kind, SchemaChangeKind::PropertyAdded);
+    assert!(changes[1].breaking);
+    let mut closed_schema = schema();
+    closed_schema["credentialSubject"]["additionalProperties"] = json!(false);
+    let mut opened_schema = new_schema.clone();
+    opened_schema["credentialSubject"]["properties"]["id"] = json!({"type": "string"});
+    let added = schema_diff::diff(
+        &VerifiableCredential::new(closed_schema, None).unwrap(),
+        &VerifiableCredential::new(opened_schema, None).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].kind, SchemaChangeKind::PropertyAdded);
+    assert!(!added[0].breaking);
+
+    let mut bounded_schema = schema();
+    bounded_schema["credentialSubject"]["properties"]["id"] =
+        json!({"type": "string", "minLength": 1, "maxLength": 10, "format": "uri"});
+    let mut rebounded_schema = schema();
+    rebounded_schema["credentialSubject"]["properties"]["id"] =
+        json!({"type": "string", "minLength": 2, "maxLength": 20, "pattern": "^did:"});
+    let kinds: Vec<_> = schema_diff::diff(
+        &VerifiableCredential::new(bounded_schema, None).unwrap(),
+        &VerifiableCredential::new(rebounded_schema, None).unwrap(),
+    )
+    .unwrap()
+    .into_iter()
+    .map(|change| (change.kind, change.breaking, change.message))
+    .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                SchemaChangeKind::ConstraintTightened,
+                true,
+                "Constraint minLength was tightened".to_string()
+            ),
+            (
+                SchemaChangeKind::ConstraintLoosened,
+                false,
+                "Constraint maxLength was loosened".to_string()
+            ),
+            (
+                SchemaChangeKind::ConstraintTightened,
+                true,
+                "Constraint pattern was tightened".to_string()
+            ),
+            (
+                SchemaChangeKind::ConstraintLoosened,
+                false,
+                "Constraint format was loosened".to_string()
+            ),
+        ]
+    );
+
+    let mut newer_schema = new_schema.clone();
+    newer_schema["credentialSubject"]["required"] = json!(["id", "level"]);
+    newer_schema["credentialSubject"]["properties"]["level"] =
+        json!({"type": ["integer", "string"], "enum": [1]});
+    let newer = VerifiableCredential::new(newer_schema, None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let kinds: Vec<_> = schema_diff::diff(&new, &newer)
+        .unwrap()
+        .into_iter()
+        .map(|change| (change.kind, change.breaking))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (SchemaChangeKind::RequiredAdded, true),
+            (SchemaChangeKind::TypeWidened, false),
+            (SchemaChangeKind::EnumValueRemoved, true),
+        ]
+    );
+
+    let compiled = CompiledSchema::new(SignedSchema::new(newer, &public_key), vec![]).unwrap();
+    let vc: VerifiableCredential = serde_json::from_value(vc_one()).unwrap();
+    let errors = compiled.subject_errors(&vc);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].keyword, "required");
+}
+
+#[test]
+fn sample_subjects() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let mut schema = schema();
+    schema["credentialSubject"]["$defs"] = json!({
+        "address": {
+            "type": "object",
+            "properties": {"postcode": {"type": "string", "minLength": 5, "maxLength": 8}},
+            "required": ["postcode"]
+        }
+    });
+    schema["credentialSubject"]["properties"]["email"] =
+        json!({"type": "string", "format": "email"});
+    schema["credentialSubject"]["properties"]["level"] =
+        json!({"type": "integer", "minimum": 1, "maximum": 9, "multipleOf": 3});
+    schema["credentialSubject"]["properties"]["role"] = json!({"enum": ["admin", "user"]});
+    schema["credentialSubject"]["properties"]["address"] = json!({"$ref": "#/$defs/address"});
+    schema["credentialSubject"]["properties"]["tags"] =
+        json!({"type": "array", "items": {"type": "string"}, "minItems": 2});
+    let schema_vc = VerifiableCredential::new(schema, None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let signed_schema = SignedSchema::new(schema_vc.clone(), &public_key);
+    let minimal = signed_schema.sample_subject(false, 7).unwrap();
+    assert_eq!(minimal.as_object().unwrap().len(), 1);
+    assert!(minimal["id"].is_string());
+    let full = signed_schema.sample_subject(true, 7).unwrap();
+    assert_eq!(full, signed_schema.sample_subject(true, 7).unwrap());
+    assert_eq!(full.as_object().unwrap().len(), 6);
+    assert_eq!(full["tags"].as_array().unwrap().len(), 2);
+    assert_eq!(full["level"].as_i64().unwrap() % 3, 0);
+    for subject in [minimal, full] {
+        assert!(VerifiableCredential::create(
+            subject,
+            Some(SignedSchema::new(schema_vc.clone(), &public_key))
+        )
+        .is_ok());
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct ExampleSubject {
+    id: String,
+}
+
+#[test]
+fn typed_credential() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let typed: TypedVerifiableCredential<ExampleSubject> = TypedVerifiableCredential::new(
+        vc_one(),
+        vec![SignedSchema::new(schema_vc.clone(), &public_key)],
+    )
+    .unwrap();
+    assert_eq!(typed.subject().id, "example_id");
+    assert!(TypedVerifiableCredential::<ExampleSubject>::new(
+        vc_two(),
+        vec![SignedSchema::new(schema_vc, &public_key)],
+    )
+    .is_err());
+    let mut vc = vc_one();
+    vc["credentialSubject"] = json!({"field": "example_field"});
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let error = TypedVerifiableCredential::<ExampleSubject>::try_from(vc).unwrap_err();
+    assert!(error.starts_with("credentialSubject: "));
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn protobuf_subject() {
+    use prost::Message;
+    use vc_signing::protobuf::verifiable_credentials;
+
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let mut vc = vc_one();
+    vc["credentialSubject"]["nested"] =
+        json!({"count": 3, "ratio": 0.25, "list": [true, null, "a"]});
+    let vc = VerifiableCredential::new(vc, Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let proto: verifiable_credentials::VerifiableCredential = vc.clone().into();
+    assert_eq!(proto.subject_encoding, 1);
+    assert!(proto.credential_subject.is_none());
+    let decoded =
+        VerifiableCredential::deserialize_protobuf(vc.clone().serialize_protobuf()).unwrap();
+    assert!(decoded.verify(&public_key).is_ok());
+
+    let mut legacy = proto;
+    legacy.subject_value = None;
+    legacy.subject_encoding = 0;
+    legacy.credential_subject = Some(prost_types::Any {
+        type_url: "Value.to_string()".to_string(),
+        value: serde_json::to_value(&vc).unwrap()["credentialSubject"]
+            .to_string()
+            .encode_to_vec(),
+    });
+    let decoded = VerifiableCredential::deserialize_protobuf(legacy.encode_to_vec()).unwrap();
+    assert!(decoded.verify(&public_key).is_ok());
+
+    let mut vc = serde_json::to_value(&vc).unwrap();
+    vc["credentialSubject"]["nested"]["count"] = json!(3.0);
+    vc["credentialSubject"]["nested"]["large"] = json!(u64::MAX);
+    vc["credentialSubject"]["nested"]["tag"] = json!("\u{0}3.0");
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let proto: verifiable_credentials::VerifiableCredential = vc.clone().into();
+    assert_eq!(proto.subject_encoding, 3);
+    assert!(proto.credential_subject.is_none());
+    let decoded =
+        VerifiableCredential::deserialize_protobuf(vc.clone().serialize_protobuf()).unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn compact_protobuf_subject() {
+    use vc_signing::format::{self, document_type, sniff, DocumentType, Encoding};
+    use vc_signing::protobuf_schema::{annotate_field_numbers, SubjectDescriptor};
+
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let mut old_schema = schema();
+    old_schema["credentialSubject"]["properties"]["retired"] = json!({"type": "string"});
+    annotate_field_numbers(&mut old_schema["credentialSubject"], None);
+    let mut schema = schema();
+    schema["credentialSubject"]["properties"]["scores"] =
+        json!({"type": "array", "items": {"type": "integer"}});
+    schema["credentialSubject"]["properties"]["address"] = json!({
+        "type": "object",
+        "properties": {"postCode": {"type": "string"}, "ratio": {"type": "number"}}
+    });
+    annotate_field_numbers(
+        &mut schema["credentialSubject"],
+        Some(&old_schema["credentialSubject"]),
+    );
+    let properties = &schema["credentialSubject"]["properties"];
+    assert_eq!(
+        properties["id"]["x-protobuf-field"],
+        old_schema["credentialSubject"]["properties"]["id"]["x-protobuf-field"]
+    );
+    assert_eq!(
+        schema["credentialSubject"]["x-protobuf-reserved"],
+        json!([2])
+    );
+    assert!(properties["address"]["x-protobuf-field"].as_u64().unwrap() > 2);
+
+    let schema_vc = VerifiableCredential::new(schema, None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let descriptor = SubjectDescriptor::new(&schema_vc).unwrap();
+    let proto = descriptor.to_proto("ExampleSubject");
+    assert!(proto.contains("repeated sint64 scores = "));
+    assert!(proto.contains("reserved 2;"));
+    assert!(proto.contains("message Address {"));
+    let defaults = json!({"scores": [0], "address": {"postCode": "", "ratio": 0}});
+    assert_eq!(
+        descriptor.decode(&descriptor.encode(&defaults).unwrap()),
+        Ok(defaults)
+    );
+
+    let mut vc = vc_one();
+    vc["credentialSubject"]["scores"] = json!([1, -2, 300]);
+    vc["credentialSubject"]["address"] = json!({"postCode": "SW1A 1AA", "ratio": 2});
+    vc["credentialSubject"]["note"] = json!({"unlisted": [null]});
+    let vc = VerifiableCredential::new(vc, Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let compact = vc.clone().serialize_protobuf_compact(&descriptor).unwrap();
+    assert!(compact.len() < vc.clone().serialize_protobuf().len());
+    assert!(VerifiableCredential::deserialize_protobuf(compact.clone()).is_err());
+    assert!(VerifiableCredential::deserialize_protobuf(compact[6..].to_vec()).is_err());
+    assert_eq!(sniff(&compact), Ok(Encoding::CompactProtobuf));
+    assert_eq!(document_type(&compact), Ok(DocumentType::Credential));
+    let error = VerifiableCredential::deserialize_any(compact.clone()).unwrap_err();
+    assert!(error.contains("schema"));
+    assert!(VerifiableCredential::verify_any(compact.clone(), &public_key).is_err());
+    let compressed = format::compress(compact.clone()).unwrap();
+    assert_eq!(sniff(&compressed), Ok(Encoding::CompactProtobuf));
+    let decoded =
+        VerifiableCredential::deserialize_protobuf_compact(compressed, &descriptor).unwrap();
+    assert!(decoded.verify(&public_key).is_ok());
+    let decoded = VerifiableCredential::deserialize_protobuf_compact(compact, &descriptor).unwrap();
+    assert!(decoded.verify(&public_key).is_ok());
+
+    let mut vc = serde_json::to_value(&vc).unwrap();
+    vc["credentialSubject"]["scores"] = json!([]);
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    assert!(vc.clone().serialize_protobuf_compact(&descriptor).is_err());
+    let decoded = VerifiableCredential::deserialize_protobuf_compact(
+        vc.clone().serialize_protobuf(),
+        &descriptor,
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_ld() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let mut vc = vc_one();
+    vc["credentialSubject"]["validFrom"] = json!("2024-01-01T00:00:00Z");
+    vc["credentialSubject"]["extra"] = json!([1, 2.5, null, "urn:uuid:NOT-A-UUID"]);
+    let vc = VerifiableCredential::new(vc, Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let cbor_ld = vc.serialize_cbor_ld().unwrap();
+    assert_eq!(&cbor_ld[6..11], &[0xda, 0x56, 0x43, 0x4c, 0x44]);
+    assert!(cbor_ld.len() < vc.serialize_cbor().unwrap().len() * 2 / 3);
+    let decoded = VerifiableCredential::deserialize_cbor_ld(cbor_ld).unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+    assert!(decoded.verify(&public_key).is_ok());
+
+    let vp: VerifiablePresentation =
+        serde_json::from_value(vp("urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671", &vc)).unwrap();
+    let decoded =
+        VerifiablePresentation::deserialize_cbor_ld(vp.serialize_cbor_ld().unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vp).unwrap()
+    );
+
+    let mut vc = serde_json::to_value(&vc).unwrap();
+    vc["credentialSubject"]["type"] = json!(7);
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    assert!(vc.serialize_cbor_ld().is_err());
+}
+
+#[cfg(feature = "qr")]
+#[test]
+fn qr_transport() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let vc: VerifiableCredential = serde_json::from_value(vc_one()).unwrap();
+    let vc = vc.sign(&private_key).unwrap();
+    let qr = vc.serialize_qr().unwrap();
+    assert!(qr.starts_with(vc_signing::format::QR_PREFIX));
+    // Base45 only uses characters of the QR alphanumeric mode
+    assert!(qr
+        .chars()
+        .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase() || " $%*+-./:".contains(c)));
+    let decoded = VerifiableCredential::verify_qr(&format!("{}\n", qr), &public_key).unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+    assert!(VerifiableCredential::deserialize_qr(&qr[1..]).is_err());
+    assert!(VerifiableCredential::deserialize_qr("VC1:0000").is_err());
+
+    assert_eq!(&vc.qr_png().unwrap()[..4], b"\x89PNG");
+    assert!(vc.qr_svg().unwrap().contains("<svg"));
+
+    let vp: VerifiablePresentation =
+        serde_json::from_value(vp("urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671", &vc)).unwrap();
+    let decoded = VerifiablePresentation::deserialize_qr(&vp.serialize_qr().unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        serde_json::to_string(&vp).unwrap()
+    );
+}
+
+#[cfg(all(feature = "cbor", feature = "protobuf", feature = "qr"))]
+#[test]
+fn format_sniffing() {
+    use vc_signing::format::{sniff, Encoding};
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let other = SignatureKeyPair::new().unwrap();
+    let vc: VerifiableCredential = serde_json::from_value(vc_one()).unwrap();
+    let vc = vc.sign(&private_key).unwrap();
+    let protobuf = vc.clone().serialize_protobuf();
+    let cbor = vc.serialize_cbor().unwrap();
+    assert_eq!(&cbor[6..9], &[0xd9, 0xd9, 0xf7]);
+    let encodings = [
+        (serde_json::to_vec_pretty(&vc).unwrap(), Encoding::Json),
+        (
+            vc.sign_jwt(&private_key).unwrap().into_bytes(),
+            Encoding::Jwt,
+        ),
+        (vc.serialize_qr().unwrap().into_bytes(), Encoding::Qr),
+        (cbor.clone(), Encoding::Cbor),
+        (vc.serialize_cbor_ld().unwrap(), Encoding::CborLd),
+        (vc.sign_cose(&private_key).unwrap(), Encoding::Cose),
+        (protobuf.clone(), Encoding::Protobuf),
+        // Legacy documents without an envelope or tag
+        (cbor[6..].to_vec(), Encoding::Cbor),
+        (cbor[9..].to_vec(), Encoding::Cbor),
+        (protobuf[6..].to_vec(), Encoding::Protobuf),
+    ];
+    for (reader, encoding) in encodings {
+        assert_eq!(sniff(&reader).unwrap(), encoding);
+        let decoded = VerifiableCredential::verify_any(reader.clone(), &public_key).unwrap();
+        assert_eq!(
+            serde_json::to_string(&decoded).unwrap(),
+            serde_json::to_string(&vc).unwrap()
+        );
+        assert!(VerifiableCredential::verify_any(reader, &other.public_key).is_err());
+    }
+
+    let mut newer = protobuf.clone();
+    newer[4] += 1;
+    assert!(VerifiableCredential::deserialize_any(newer)
+        .unwrap_err()
+        .contains("version"));
+    assert!(VerifiablePresentation::deserialize_any(protobuf).is_err());
+    assert!(sniff(b"not a credential").is_err());
+    // The real error of the sniffed encoding is returned
+    assert!(
+        VerifiableCredential::deserialize_any(b"{\"id\": 1}".to_vec())
+            .unwrap_err()
+            .contains("invalid type")
+    );
+}
+
+#[cfg(all(feature = "cbor", feature = "protobuf", feature = "qr"))]
+#[test]
+fn document_type_detection() {
+    use vc_signing::format::{document_type, DocumentType};
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let vc: VerifiableCredential = serde_json::from_value(vc_one()).unwrap();
+    let vc = vc.sign(&private_key).unwrap();
+    let vp = VerifiablePresentation::new(vp("urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671", &vc))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let credentials = [
+        serde_json::to_vec(&vc).unwrap(),
+        vc.sign_jwt(&private_key).unwrap().into_bytes(),
+        vc.serialize_qr().unwrap().into_bytes(),
+        vc.serialize_cbor().unwrap(),
+        vc.serialize_cbor().unwrap()[6..].to_vec(),
+        vc.serialize_cbor_ld().unwrap(),
+        vc.sign_cose(&private_key).unwrap(),
+        vc.clone().serialize_protobuf(),
+        vc.clone().serialize_protobuf()[6..].to_vec(),
+    ];
+    for reader in credentials {
+        assert_eq!(document_type(&reader).unwrap(), DocumentType::Credential);
+    }
+    let presentations = [
+        serde_json::to_vec(&vp).unwrap(),
+        vp.sign_jwt(&private_key).unwrap().into_bytes(),
+        vp.serialize_qr().unwrap().into_bytes(),
+        vp.serialize_cbor().unwrap(),
+        vp.serialize_cbor().unwrap()[6..].to_vec(),
+        vp.serialize_cbor_ld().unwrap(),
+        vp.sign_cose(&private_key).unwrap(),
+        vp.clone().serialize_protobuf(),
+    ];
+    for reader in presentations {
+        assert_eq!(document_type(&reader).unwrap(), DocumentType::Presentation);
+        let decoded = VerifiablePresentation::verify_any(reader, &public_key).unwrap();
+        assert_eq!(
+            serde_json::to_string(&decoded).unwrap(),
+            serde_json::to_string(&vp).unwrap()
+        );
+    }
+    assert!(document_type(b"not a presentation").is_err());
+}
+
+#[cfg(all(feature = "cbor", feature = "protobuf"))]
+#[test]
+fn binary_envelope() {
+    use vc_signing::format::compress;
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let vc: VerifiableCredential = serde_json::from_value(vc_one()).unwrap();
+    let vc = vc.sign(&private_key).unwrap();
+    let vp: VerifiablePresentation =
+        serde_json::from_value(vp("urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671", &vc)).unwrap();
+    let protobuf = vc.clone().serialize_protobuf();
+    let cbor = vc.serialize_cbor().unwrap();
+    let cbor_ld = vc.serialize_cbor_ld().unwrap();
+    // Magic number, kind, schema version and flags
+    assert_eq!(&protobuf[..6], &[0x00, b'V', b'C', 1, 1, 0]);
+    assert_eq!(
+        &vp.clone().serialize_protobuf()[..6],
+        &[0x00, b'V', b'C', 2, 1, 0]
+    );
+    assert_eq!(&cbor[..6], &[0x00, b'V', b'C', 3, 1, 0]);
+    assert_eq!(
+        &vp.serialize_cbor().unwrap()[..6],
+        &[0x00, b'V', b'C', 4, 1, 0]
+    );
+    assert_eq!(&cbor_ld[..6], &[0x00, b'V', b'C', 5, 1, 0]);
+    assert_eq!(
+        &vp.serialize_cbor_ld().unwrap()[..6],
+        &[0x00, b'V', b'C', 6, 1, 0]
+    );
+
+    let compressed = compress(cbor.clone()).unwrap();
+    assert_eq!(&compressed[..6], &[0x00, b'V', b'C', 3, 1, 1]);
+    assert!(compressed.len() < cbor.len());
+    VerifiableCredential::verify_cbor(compressed.clone(), &public_key).unwrap();
+    assert!(compress(compressed).is_err());
+    VerifiableCredential::verify_protobuf(compress(protobuf.clone()).unwrap(), &public_key)
+        .unwrap();
+    VerifiableCredential::verify_cbor_ld(compress(cbor_ld.clone()).unwrap(), &public_key).unwrap();
+    assert!(compress(protobuf[6..].to_vec()).is_err());
+
+    // Legacy bare payloads are still accepted
+    VerifiableCredential::verify_cbor(cbor[6..].to_vec(), &public_key).unwrap();
+    VerifiableCredential::verify_cbor_ld(cbor_ld[6..].to_vec(), &public_key).unwrap();
+    VerifiableCredential::verify_protobuf(protobuf[6..].to_vec(), &public_key).unwrap();
+
+    assert!(VerifiableCredential::deserialize_cbor(protobuf.clone())
+        .unwrap_err()
+        .contains("CredentialProtobuf"));
+    assert!(VerifiablePresentation::deserialize_cbor(cbor.clone()).is_err());
+    let mut flagged = cbor.clone();
+    flagged[5] = 0x80;
+    assert!(VerifiableCredential::deserialize_cbor(flagged)
+        .unwrap_err()
+        .contains("flags"));
+    let mut newer = cbor_ld;
+    newer[4] = 2;
+    assert!(VerifiableCredential::deserialize_cbor_ld(newer)
+        .unwrap_err()
+        .contains("version"));
+    assert!(VerifiableCredential::deserialize_protobuf(protobuf[..5].to_vec()).is_err());
+}
+
+#[cfg(all(feature = "cbor", feature = "protobuf"))]
+#[test]
+fn binary_round_trips() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let mut vc = vc_one();
+    vc["issuer"] = json!("HTTPS://Example.COM/a/../issuer");
+    vc["validFrom"] = json!("2024-11-15T16:21:33.5+01:00");
+    vc["credentialSubject"]["zeta"] = json!({"b": 1, "a": [1.0, 18446744073709551615u64, "é"]});
+    vc["credentialSubject"]["alpha"] = json!(true);
+    let vc = VerifiableCredential::new(vc, Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+
+    let cbor = vc.serialize_cbor().unwrap();
+    let decoded = VerifiableCredential::verify_cbor(cbor.clone(), &public_key).unwrap();
+    assert_eq!(decoded.serialize_cbor().unwrap(), cbor);
+    let cbor_ld = vc.serialize_cbor_ld().unwrap();
+    let decoded = VerifiableCredential::verify_cbor_ld(cbor_ld.clone(), &public_key).unwrap();
+    assert_eq!(decoded.serialize_cbor_ld().unwrap(), cbor_ld);
+    let protobuf = vc.clone().serialize_protobuf();
+    let decoded = VerifiableCredential::verify_protobuf(protobuf.clone(), &public_key).unwrap();
+    assert_eq!(decoded.serialize_protobuf(), protobuf);
+
+    let mut tampered = cbor.clone();
+    let position = tampered
+        .windows(5)
+        .position(|window| window == b"alpha")
+        .unwrap();
+    tampered[position] = b'b';
+    assert!(VerifiableCredential::verify_cbor(tampered, &public_key).is_err());
+
+    let vp = VerifiablePresentation::new(vp("urn:uuid:67cddd6f-727f-4aea-91d4-e5f314252671", &vc))
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let cbor = vp.serialize_cbor().unwrap();
+    let decoded = VerifiablePresentation::verify_cbor(cbor.clone(), &public_key).unwrap();
+    assert_eq!(decoded.serialize_cbor().unwrap(), cbor);
+    assert!(VerifiablePresentation::verify_protobuf(vp.serialize_protobuf(), &public_key).is_ok());
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cose_sign1() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let other = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let vc = VerifiableCredential::new(vc_one(), Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap();
+    let cose = vc.sign_cose(&private_key).unwrap();
+    assert_eq!(cose[0], 0xd2);
+    let did = vc_signing::did::did_key_from_public_key(&public_key)
+        .unwrap()
+        .to_string();
+    let kid = format!("{}#{}", did, did.trim_start_matches("did:key:"));
+    assert!(cose
+        .windows(kid.len())
+        .any(|window| window == kid.as_bytes()));
+    let verified = VerifiableCredential::verify_cose(cose.clone(), &public_key).unwrap();
+    assert_eq!(
+        serde_json::to_string(&verified).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+    assert!(VerifiableCredential::verify_cose(cose.clone(), &other.public_key).is_err());
+    assert!(VerifiableCredential::decode_cose(cose.clone()).is_ok());
+    assert!(VerifiablePresentation::decode_cose(cose.clone()).is_err());
+
+    let mut tampered = cose;
+    let position = tampered
+        .windows(10)
+        .position(|window| window == b"example_id")
+        .unwrap();
+    tampered[position] = b'E';
+    assert!(VerifiableCredential::verify_cose(tampered, &public_key).is_err());
+
+    let vp = VerifiablePresentation::new(vp(&other.did_key().unwrap(), &vc)).unwrap();
+    let cose = vp.sign_cose(&other.private_key).unwrap();
+    assert!(VerifiablePresentation::verify_cose(cose.clone(), &other.public_key).is_ok());
+    assert!(VerifiableCredential::verify_cose(cose, &other.public_key).is_err());
+}
+
+#[test]
+fn jose_jwt() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let other = SignatureKeyPair::new().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&private_key)
+        .unwrap();
+    let vc = VerifiableCredential::new(vc_one(), Some(SignedSchema::new(schema_vc, &public_key)))
+        .unwrap();
+    let jwt = vc.sign_jwt(&private_key).unwrap();
+    let header: serde_json::Value = serde_json::from_slice(
+        &BASE64_URL_SAFE_NO_PAD
+            .decode(jwt.split('.').next().unwrap())
+            .unwrap(),
+    )
+    .unwrap();
+    let did = SignatureKeyPair {
+        private_key: private_key.clone(),
+        public_key: public_key.clone(),
+    }
+    .did_key()
+    .unwrap();
+    assert_eq!(
+        header,
+        json!({
+            "alg": "EdDSA",
+            "kid": format!("{}#{}", did, did.trim_start_matches("did:key:")),
+            "typ": "vc+jwt",
+            "cty": "vc"
+        })
+    );
+    let verified = VerifiableCredential::verify_jwt(&jwt, &public_key).unwrap();
+    assert_eq!(
+        serde_json::to_string(&verified).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+    assert!(VerifiableCredential::verify_jwt(&jwt, &other.public_key).is_err());
+    assert!(VerifiableCredential::decode_jwt(&jwt).is_ok());
+    assert!(VerifiablePresentation::decode_jwt(&jwt).is_err());
+
+    let (header, rest) = jwt.split_once('.').unwrap();
+    let (_, signature) = rest.split_once('.').unwrap();
+    let tampered = format!(
+        "{}.{}.{}",
+        header,
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&vc_two()).unwrap()),
+        signature
+    );
+    assert!(VerifiableCredential::verify_jwt(&tampered, &public_key).is_err());
+
+    let vp = VerifiablePresentation::new(vp(&other.did_key().unwrap(), &vc)).unwrap();
+    let jwt = vp.sign_jwt(&other.private_key).unwrap();
+    assert!(VerifiablePresentation::verify_jwt(&jwt, &other.public_key).is_ok());
+    assert!(VerifiableCredential::verify_jwt(&jwt, &other.public_key).is_err());
+}
+
+#[test]
+fn sd_jwt() {
+    let issuer = SignatureKeyPair::new().unwrap();
+    let holder = SignatureKeyPair::new().unwrap();
+    let mut vc = vc_one();
+    vc["credentialSubject"] = json!({
+        "id": "example_id",
+        "birthDate": "1990-01-01",
+        "address": {"street": "1 Example Road", "city": "London"},
+        "nationalities": ["GB", "FR"]
+    });
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let issued = vc
+        .issue_sd_jwt(
+            &issuer.private_key,
+            vec![
+                "/birthDate".into(),
+                "/address".into(),
+                "/address/street".into(),
+                "/nationalities/1".into(),
+            ],
+            Some(holder.public_key.clone()),
+        )
+        .unwrap();
+    assert_eq!(issued.matches('~').count(), 5);
+    assert_eq!(
+        VerifiableCredential::sd_jwt_claims(&issued).unwrap(),
+        vec![
+            "/address",
+            "/address/street",
+            "/birthDate",
+            "/nationalities/1"
+        ]
+    );
+    assert!(vc
+        .issue_sd_jwt(&issuer.private_key, vec!["/missing".into()], None)
+        .is_err());
+
+    let presented = VerifiableCredential::present_sd_jwt(
+        &issued,
+        vec!["/address/city".into()],
+        Some(holder.private_key.clone()),
+        Some("https://verifier.example".into()),
+        Some("1234".into()),
+    )
+    .unwrap();
+    let verified = VerifiableCredential::verify_sd_jwt(
+        &presented,
+        &issuer.public_key,
+        Some("https://verifier.example".into()),
+        Some("1234".into()),
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&verified).unwrap()["credentialSubject"],
+        json!({
+            "id": "example_id",
+            "address": {"city": "London"},
+            "nationalities": ["GB"]
+        })
+    );
+    assert!(VerifiableCredential::verify_sd_jwt(
+        &presented,
+        &issuer.public_key,
+        Some("https://verifier.example".into()),
+        Some("5678".into()),
+    )
+    .is_err());
+    assert!(
+        VerifiableCredential::verify_sd_jwt(&presented, &holder.public_key, None, None).is_err()
+    );
+    // Bound SD-JWTs need the audience and nonce
+    for (audience, nonce) in [
+        (None, Some("1234".to_string())),
+        (Some("https://verifier.example".to_string()), None),
+    ] {
+        let error =
+            VerifiableCredential::verify_sd_jwt(&presented, &issuer.public_key, audience, nonce)
+                .unwrap_err();
+        assert!(error.starts_with("Key binding needs"));
+    }
+    // Key binding JWTs expire
+    let (presentation, _) = presented.rsplit_once('~').unwrap();
+    let key_binding = |iat: i64| {
+        let signing_input = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(json!({"alg": "EdDSA", "typ": "kb+jwt"}).to_string()),
+            BASE64_URL_SAFE_NO_PAD.encode(
+                json!({
+                    "iat": iat,
+                    "aud": "https://verifier.example",
+                    "nonce": "1234",
+                    "sd_hash": BASE64_URL_SAFE_NO_PAD.encode(ring::digest::digest(
+                        &ring::digest::SHA256,
+                        format!("{}~", presentation).as_bytes()
+                    )),
+                })
+                .to_string()
+            )
+        );
+        let signature = ring::signature::Ed25519KeyPair::from_pkcs8(&holder.private_key)
+            .unwrap()
+            .sign(signing_input.as_bytes());
+        format!(
+            "{}~{}.{}",
+            presentation,
+            signing_input,
+            BASE64_URL_SAFE_NO_PAD.encode(signature)
+        )
+    };
+    let now = chrono::Utc::now().timestamp();
+    for (iat, fresh) in [(now - 60, true), (now - 3600, false)] {
+        let verified = VerifiableCredential::verify_sd_jwt(
+            &key_binding(iat),
+            &issuer.public_key,
+            Some("https://verifier.example".into()),
+            Some("1234".into()),
+        );
+        assert_eq!(verified.is_ok(), fresh);
+    }
+    // Bound SD-JWTs need a key binding JWT
+    assert!(VerifiableCredential::verify_sd_jwt(&issued, &issuer.public_key, None, None).is_err());
+    // Key binding JWTs cover the disclosures
+    let (presentation, key_binding) = presented.rsplit_once('~').unwrap();
+    let extra = issued.split('~').nth(1).unwrap();
+    assert!(VerifiableCredential::verify_sd_jwt(
+        &format!("{}{}~{}", presentation, extra, key_binding),
+        &issuer.public_key,
+        Some("https://verifier.example".into()),
+        Some("1234".into()),
+    )
+    .is_err());
+
+    let unbound = vc
+        .issue_sd_jwt(&issuer.private_key, vec!["/birthDate".into()], None)
+        .unwrap();
+    let presented =
+        VerifiableCredential::present_sd_jwt(&unbound, vec!["/birthDate".into()], None, None, None)
+            .unwrap();
+    let verified =
+        VerifiableCredential::verify_sd_jwt(&presented, &issuer.public_key, None, None).unwrap();
+    assert_eq!(
+        serde_json::to_string(&verified).unwrap(),
+        serde_json::to_string(&vc).unwrap()
+    );
+}
+
+#[cfg(feature = "bbs")]
+#[test]
+fn bbs_selective_disclosure() {
+    let issuer = SignatureKeyPair::new_bls12_381().unwrap();
+    let other = SignatureKeyPair::new_bls12_381().unwrap();
+    assert_eq!(issuer.public_key.len(), 96);
+    assert!(issuer.did_key().unwrap().starts_with("did:key:zUC7"));
+    let mut vc = vc_one();
+    vc["credentialSubject"] = json!({
+        "id": "did:example:alice",
+        "birthDate": "1990-01-01",
+        "address": {"street": "1 Example Road", "city": "London"},
+        "nationalities": ["GB", "FR"]
+    });
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let signed = vc
+        .clone()
+        .sign_bbs(&issuer.private_key, vec!["/id".into()])
+        .unwrap();
+    let proof = serde_json::to_value(&signed).unwrap()["proof"].clone();
+    assert_eq!(proof["cryptosuite"], "bbs-2023");
+    assert_eq!(
+        proof["verificationMethod"],
+        format!(
+            "{}#{}",
+            issuer.did_key().unwrap(),
+            &issuer.did_key().unwrap()["did:key:".len()..]
+        )
+    );
+    let proof_value = BASE64_URL_SAFE_NO_PAD
+        .decode(&proof["proofValue"].as_str().unwrap()[1..])
+        .unwrap();
+    assert_eq!(proof_value[..3], [0xd9, 0x5d, 0x02]);
+    signed.verify(&issuer.public_key).unwrap();
+    assert!(signed.verify(&other.public_key).is_err());
+    assert!(vc
+        .clone()
+        .sign_bbs(&issuer.private_key, vec!["/missing".into()])
+        .is_err());
+
+    let derived = signed
+        .derive_bbs(
+            vec!["/address/city".into(), "/nationalities".into()],
+            b"nonce".to_vec(),
+        )
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&derived).unwrap()["credentialSubject"],
+        json!({
+            "id": "did:example:alice",
+            "address": {"city": "London"},
+            "nationalities": ["GB", "FR"]
+        })
+    );
+    derived.verify_bbs(&issuer.public_key, b"nonce").unwrap();
+    assert!(derived.verify_bbs(&other.public_key, b"nonce").is_err());
+    // A derived proof can't be presented with another presentation header
+    assert!(derived.verify_bbs(&issuer.public_key, b"other").is_err());
+    assert!(derived.verify(&issuer.public_key).is_err());
+    // Derived proofs are randomised, so presentations are unlinkable
+    let again = signed
+        .derive_bbs(
+            vec!["/address/city".into(), "/nationalities".into()],
+            b"nonce".to_vec(),
+        )
+        .unwrap();
+    assert_ne!(
+        serde_json::to_value(&derived).unwrap()["proof"]["proofValue"],
+        serde_json::to_value(&again).unwrap()["proof"]["proofValue"]
+    );
+    again.verify_bbs(&issuer.public_key, b"nonce").unwrap();
+
+    let mut tampered = serde_json::to_value(&derived).unwrap();
+    tampered["credentialSubject"]["address"]["city"] = json!("Paris");
+    let tampered: VerifiableCredential = serde_json::from_value(tampered).unwrap();
+    assert!(tampered.verify_bbs(&issuer.public_key, b"nonce").is_err());
+    let mut tampered = serde_json::to_value(&derived).unwrap();
+    tampered["credentialSubject"]["birthDate"] = json!("1990-01-01");
+    let tampered: VerifiableCredential = serde_json::from_value(tampered).unwrap();
+    assert!(tampered.verify_bbs(&issuer.public_key, b"nonce").is_err());
+
+    let minimal = signed.derive_bbs(vec![], vec![]).unwrap();
+    assert_eq!(
+        serde_json::to_value(&minimal).unwrap()["credentialSubject"],
+        json!({"id": "did:example:alice"})
+    );
+    minimal.verify(&issuer.public_key).unwrap();
+    let first = signed
+        .derive_bbs(vec!["/nationalities/0".into()], vec![])
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&first).unwrap()["credentialSubject"],
+        json!({"id": "did:example:alice", "nationalities": ["GB"]})
+    );
+    first.verify(&issuer.public_key).unwrap();
+    assert!(signed
+        .derive_bbs(vec!["/nationalities/2".into()], vec![])
+        .is_err());
+    #[cfg(feature = "protobuf")]
+    {
+        let decoded =
+            VerifiableCredential::deserialize_protobuf(derived.serialize_protobuf()).unwrap();
+        decoded.verify_bbs(&issuer.public_key, b"nonce").unwrap();
+    }
+}
+
+#[cfg(feature = "bbs")]
+#[test]
+fn bbs_known_answers() {
+    use vc_signing::bbs::{key_gen, sign_messages, verify_messages};
+
+    // BLS12-381-SHA-256 fixtures of draft-irtf-cfrg-bbs-signatures
+    let hex = |hex: &str| -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    };
+    let (secret_key, public_key) = key_gen(
+        &hex("746869732d49532d6a7573742d616e2d546573742d494b4d2d746f2d67656e65726174652d246528724074232d6b6579"),
+        &hex("746869732d49532d736f6d652d6b65792d6d657461646174612d746f2d62652d757365642d696e2d746573742d6b65792d67656e"),
+    )
+    .unwrap();
+    assert_eq!(
+        secret_key,
+        hex("60e55110f76883a13d030b2f6bd11883422d5abde717569fc0731f51237169fc")
+    );
+    assert_eq!(
+        public_key,
+        hex("a820f230f6ae38503b86c70dc50b61c58a77e45c39ab25c0652bbaa8fa136f2851bd4781c9dcde39fc9d1d52c9e60268061e7d7632171d91aa8d460acee0e96f1e7c4cfb12d3ff9ab5d5dc91c277db75c845d649ef3c4f63aebc364cd55ded0c")
+    );
+    assert!(key_gen(&[0; 31], &[]).is_err());
+
+    let header = hex("11223344556677889900aabbccddeeff");
+    let messages = vec![hex(
+        "9872ad089e452c7b6e283dfac2a80d58e8d0ff71cc4d5e310a1debdda4a45f02",
+    )];
+    let signature = sign_messages(&secret_key, &header, &messages).unwrap();
+    assert_eq!(
+        signature,
+        hex("84773160b824e194073a57493dac1a20b667af70cd2352d8af241c77658da5253aa8458317cca0eae615690d55b1f27164657dcafee1d5c1973947aa70e2cfbb4c892340be5969920d0916067b4565a0")
+    );
+    assert!(verify_messages(&public_key, &signature, &header, &messages));
+    assert!(!verify_messages(&public_key, &signature, &header, &[]));
+    assert!(!verify_messages(&public_key, &signature, &[], &messages));
+}
+
+#[test]
+fn ecdsa_sd_selective_disclosure() {
+    let issuer = SignatureKeyPair::new_p256().unwrap();
+    let other = SignatureKeyPair::new_p256().unwrap();
+    assert_eq!(issuer.public_key.len(), 65);
+    assert!(issuer.did_key().unwrap().starts_with("did:key:zDn"));
+    let compressed = [
+        &[0x02 | (issuer.public_key[64] & 1)],
+        &issuer.public_key[1..33],
+    ]
+    .concat();
+    assert_eq!(
+        vc_signing::did::did_key_from_public_key(&compressed)
+            .unwrap()
+            .to_string(),
+        issuer.did_key().unwrap()
+    );
+    for unsupported in [&compressed[2..], &issuer.public_key[..48], &[]] {
+        assert!(vc_signing::did::did_key_from_public_key(unsupported).is_err());
+    }
+    let mut vc = vc_one();
+    vc["credentialSubject"] = json!({
+        "id": "did:example:alice",
+        "birthDate": "1990-01-01",
+        "address": {"street": "1 Example Road", "city": "London"}
+    });
+    let vc: VerifiableCredential = serde_json::from_value(vc).unwrap();
+    let signed = vc
+        .clone()
+        .sign_ecdsa_sd(&issuer.private_key, vec!["/id".into()])
+        .unwrap();
+    let proof = serde_json::to_value(&signed).unwrap()["proof"].clone();
+    assert_eq!(proof["cryptosuite"], "ecdsa-sd-2023");
+    let proof_value = BASE64_URL_SAFE_NO_PAD
+        .decode(&proof["proofValue"].as_str().unwrap()[1..])
+        .unwrap();
+    assert_eq!(proof_value[..3], [0xd9, 0x5d, 0x00]);
+    signed.verify(&issuer.public_key).unwrap();
+    assert!(signed.verify(&other.public_key).is_err());
+    assert!(vc
+        .sign_ecdsa_sd(&issuer.private_key, vec!["/missing".into()])
+        .is_err());
+
+    let derived = signed
+        .derive_ecdsa_sd(vec!["/address/city".into()])
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&derived).unwrap()["credentialSubject"],
+        json!({"id": "did:example:alice", "address": {"city": "London"}})
+    );
+    derived.verify(&issuer.public_key).unwrap();
+    assert!(derived.verify(&other.public_key).is_err());
+    let proof_value = BASE64_URL_SAFE_NO_PAD
+        .decode(
+            &serde_json::to_value(&derived).unwrap()["proof"]["proofValue"]
+                .as_str()
+                .unwrap()[1..],
+        )
+        .unwrap();
+    assert_eq!(proof_value[..3], [0xd9, 0x5d, 0x01]);
+
+    let mut tampered = serde_json::to_value(&derived).unwrap();
+    tampered["credentialSubject"]["address"]["city"] = json!("Paris");
+    let tampered: VerifiableCredential = serde_json::from_value(tampered).unwrap();
+    assert!(tampered.verify(&issuer.public_key).is_err());
+    let mut tampered = serde_json::to_value(&derived).unwrap();
+    tampered["credentialSubject"]["id"] = json!("did:example:mallory");
+    let tampered: VerifiableCredential = serde_json::from_value(tampered).unwrap();
+    assert!(tampered.verify(&issuer.public_key).is_err());
+    let mut tampered = serde_json::to_value(&derived).unwrap();
+    let proof_value = BASE64_URL_SAFE_NO_PAD
+        .decode(&tampered["proof"]["proofValue"].as_str().unwrap()[1..])
+        .unwrap();
+    for components in [
+        vec![0x81; 500_000],
+        [0x9b].iter().chain(&[0xff; 8]).copied().collect(),
+    ] {
+        tampered["proof"]["proofValue"] = json!(format!(
+            "u{}",
+            BASE64_URL_SAFE_NO_PAD.encode([&proof_value[..3], &components].concat())
+        ));
+        let tampered: VerifiableCredential = serde_json::from_value(tampered.clone()).unwrap();
+        assert!(tampered.verify(&issuer.public_key).is_err());
+    }
+
+    let minimal = signed.derive_ecdsa_sd(vec![]).unwrap();
+    assert_eq!(
+        serde_json::to_value(&minimal).unwrap()["credentialSubject"],
+        json!({"id": "did:example:alice"})
+    );
+    minimal.verify(&issuer.public_key).unwrap();
+    assert!(derived.derive_ecdsa_sd(vec![]).is_err());
+}
+
+#[test]
+fn schema_registry() {
+    let trusted = SignatureKeyPair::new().unwrap();
+    let untrusted = SignatureKeyPair::new().unwrap();
+    let mut registry = SchemaRegistry::new();
+    registry.add_trusted_key(trusted.public_key.clone());
+    let untrusted_schema = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&untrusted.private_key)
+        .unwrap();
+    assert!(registry.insert(untrusted_schema).is_err());
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&trusted.private_key)
+        .unwrap();
+    registry.insert(schema_vc.clone()).unwrap();
+    assert!(registry.contains("urn:uuid:9a2dc235-17a2-471c-b1f3-a8b29ed4a3d3"));
+    let options = ValidationOptions::new().with_registry(registry);
+    let vc = VerifiableCredential::new_with_options(vc_one(), vec![], &options)
+        .unwrap()
+        .sign(&trusted.private_key)
+        .unwrap();
+    vc.verify(&trusted.public_key).unwrap();
+    assert!(vc.schema_errors(vec![], &options).is_empty());
+    assert!(VerifiableCredential::new_with_options(vc_two(), vec![], &options).is_err());
+    assert!(
+        VerifiableCredential::new_with_options(vc_one(), vec![], &ValidationOptions::new())
+            .is_err()
+    );
+
+    let dir = std::env::temp_dir().join(format!(
+        "vc_signing_schema_registry_{}",
+        uuid::Uuid::new_v4()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("schema.json"),
+        serde_json::to_string(&schema_vc).unwrap(),
+    )
+    .unwrap();
+    let registry = SchemaRegistry::from_dir(&dir, vec![trusted.public_key.clone()]).unwrap();
+    assert!(vc
+        .schema_errors(vec![], &ValidationOptions::new().with_registry(registry))
+        .is_empty());
+    assert!(SchemaRegistry::from_dir(&dir, vec![untrusted.public_key]).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn json_schema_credential() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    let schema_vc =
+        VerifiableCredential::create_schema(schema().get("credentialSubject").unwrap().clone())
+            .unwrap()
+            .sign(&private_key)
+            .unwrap();
+    let schema_json = serde_json::to_value(&schema_vc).unwrap();
+    assert_eq!(schema_json["credentialSubject"]["type"], "JsonSchema");
+    assert_eq!(
+        schema_json["type"],
+        json!(["VerifiableCredential", "JsonSchemaCredential"])
+    );
+
+    let vc = VerifiableCredential::create(
+        json!({"id": "example_id"}),
+        Some(SignedSchema::new(schema_vc.clone(), &public_key)),
+    )
+    .unwrap();
+    let mut vc_json = serde_json::to_value(&vc).unwrap();
+    assert_eq!(vc_json["credentialSchema"]["type"], "JsonSchemaCredential");
+    assert_eq!(vc_json["credentialSchema"]["id"], schema_json["id"]);
+    assert!(vc_json["credentialSchema"]["digestSRI"]
+        .as_str()
+        .unwrap()
+        .starts_with("sha384-"));
+
+    let mut registry = SchemaRegistry::new();
+    registry.add_trusted_key(public_key.clone());
+    registry.insert(schema_vc.clone()).unwrap();
+    let options = ValidationOptions::new().with_registry(registry);
+    assert!(VerifiableCredential::new_with_options(vc_json.clone(), vec![], &options).is_ok());
+    assert!(VerifiableCredential::new_with_options(vc_one(), vec![], &options).is_ok());
+
+    vc_json["credentialSchema"]["digestSRI"] = json!("sha384-AAAA");
+    assert!(VerifiableCredential::new_with_options(vc_json, vec![], &options).is_err());
+}
+
+#[test]
+fn older_drafts() {
+    let SignatureKeyPair {
+        private_key,
+        public_key,
+    } = SignatureKeyPair::new().unwrap();
+    for (meta_schema, id_field) in [
+        ("https://json-schema.org/draft/2019-09/schema", "$id"),
+        ("http://json-schema.org/draft-07/schema#", "$id"),
+        ("http://json-schema.org/draft-04/schema#", "id"),
+    ] {
+        let mut json_schema = schema()["credentialSubject"].clone();
+        json_schema["$schema"] = json!(meta_schema);
+        let id = json_schema.as_object_mut().unwrap().remove("$id").unwrap();
+        json_schema[id_field] = id;
+        json_schema["properties"]["issued"] = json!({"type": "string", "format": "date-time"});
+        let schema_vc = VerifiableCredential::create(json_schema.clone(), None)
+            .unwrap()
+            .sign(&private_key)
+            .unwrap();
+        assert!(VerifiableCredential::new(
+            vc_one(),
+            Some(SignedSchema::new(schema_vc.clone(), &public_key))
+        )
+        .is_ok());
+        let mut undated = vc_one();
+        undated["credentialSubject"]["issued"] = json!("not a date");
+        assert!(VerifiableCredential::new(
+            undated.clone(),
+            Some(SignedSchema::new(schema_vc.clone(), &public_key))
+        )
+        .is_ok());
+        assert!(VerifiableCredential::new_with_schema(
+            undated,
+            Some(SignedSchema::new(schema_vc.clone(), &public_key)),
+            &ValidationOptions::new().should_validate_formats(true)
+        )
+        .is_err());
+        assert!(VerifiableCredential::new(
+            vc_two(),
+            Some(SignedSchema::new(schema_vc, &public_key))
+        )
+        .is_err());
+        assert!(VerifiableCredential::create_schema(json_schema.clone()).is_ok());
+
+        json_schema["required"] = json!("id");
+        assert!(VerifiableCredential::create(json_schema, None).is_err());
+    }
+    let mut json_schema = schema()["credentialSubject"].clone();
+    json_schema["$schema"] = json!("https://example.com/unknown-draft");
+    assert!(VerifiableCredential::create(json_schema, None).is_err());
+}
+
+#[test]
+fn schema_references() {
+    let trusted = SignatureKeyPair::new().unwrap();
+    let untrusted = SignatureKeyPair::new().unwrap();
+    let definitions = VerifiableCredential::create_schema(json!({
+        "$id": "urn:uuid:5d0c4a4e-2f1b-4c8e-9a57-6b1f0e3d2c19",
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$defs": {
+            "name": {"type": "string", "minLength": 1}
+        }
+    }))
+    .unwrap();
+    let mut json_schema = schema()["credentialSubject"].clone();
+    json_schema["properties"]["id"] =
+        json!({"$ref": "urn:uuid:5d0c4a4e-2f1b-4c8e-9a57-6b1f0e3d2c19#/$defs/name"});
+    let schema_vc = VerifiableCredential::create_schema(json_schema)
+        .unwrap()
+        .sign(&trusted.private_key)
+        .unwrap();
+    let bundle = |definitions_key: &[u8]| {
+        vec![
+            SignedSchema::new(schema_vc.clone(), &trusted.public_key),
+            SignedSchema::new(
+                definitions.clone().sign(definitions_key).unwrap(),
+                &trusted.public_key,
+            ),
+        ]
+    };
+    assert!(VerifiableCredential::new_with_schemas(vc_one(), bundle(&trusted.private_key)).is_ok());
+    let mut vc = vc_one();
+    vc["credentialSubject"]["id"] = json!("");
+    assert!(VerifiableCredential::new_with_schemas(vc, bundle(&trusted.private_key)).is_err());
+    let error = VerifiableCredential::new_with_schemas(vc_one(), bundle(&untrusted.private_key))
+        .unwrap_err();
+    assert!(error.contains("urn:uuid:5d0c4a4e-2f1b-4c8e-9a57-6b1f0e3d2c19"));
+    let cache = SchemaCache::new();
+    let trusted_definitions = SignedSchema::new(
+        definitions.clone().sign(&trusted.private_key).unwrap(),
+        &trusted.public_key,
+    );
+    let compiled = cache
+        .get_or_compile(
+            SignedSchema::new(schema_vc.clone(), &trusted.public_key),
+            vec![trusted_definitions],
+        )
+        .unwrap();
+    assert!(compiled
+        .subject_errors(&serde_json::from_value(vc_one()).unwrap())
+        .is_empty());
+    assert!(cache
+        .get_or_compile(
+            SignedSchema::new(schema_vc.clone(), &trusted.public_key),
+            vec![]
+        )
+        .is_err());
+    let mut registry = SchemaRegistry::new();
+    registry.add_trusted_key(trusted.public_key.clone());
+    registry.insert(schema_vc.clone()).unwrap();
+    let mut complete = SchemaRegistry::new();
+    complete.add_trusted_key(trusted.public_key.clone());
+    complete.insert(schema_vc.clone()).unwrap();
+    complete
+        .insert(definitions.clone().sign(&trusted.private_key).unwrap())
+        .unwrap();
+    assert!(VerifiableCredential::new_with_options(
+        vc_one(),
+        vec![],
+        &ValidationOptions::new().with_registry(registry)
+    )
+    .is_err());
+    assert!(VerifiableCredential::new_with_options(
+        vc_one(),
+        vec![],
+        &ValidationOptions::new().with_registry(complete)
+    )
+    .is_ok());
+    assert!(VerifiableCredential::new_with_schemas(
+        vc_one(),
+        vec![SignedSchema::new(schema_vc, &trusted.public_key)]
+    )
+    .is_err());
+}
+
+fn vp(holder: &str, vc: &VerifiableCredential) -> Value {
+    json!({
+        "id": "urn:uuid:3c9fd6a1-0a5b-4a4e-9d61-0d1f5bd2c3b7",
+        "type": "VerifiablePresentation",
+        "verifiableCredential": [vc],
+        "holder": holder
+    })
+}
+
+#[test]
+fn holder_binding() {
+    let issuer = SignatureKeyPair::new().unwrap();
+    let holder = SignatureKeyPair::new().unwrap();
+    let holder_did = holder.did_key().unwrap();
+    let schema_vc = VerifiableCredential::new(schema(), None)
+        .unwrap()
+        .sign(&issuer.private_key)
+        .unwrap();
+    let mut subject = vc_one();
+    subject["credentialSubject"]["id"] = json!(holder_did);
+    let vc = VerifiableCredential::new(
+        subject,
+        Some(SignedSchema::new(schema_vc.clone(), &issuer.public_key)),
+    )
+    .unwrap()
+    .sign(&issuer.private_key)
+    .unwrap();
+    let vp_bound = VerifiablePresentation::new(vp(&holder_did, &vc))
+        .unwrap()
+        .sign(&holder.private_key)
+        .unwrap();
+    assert!(vp_bound.verify_holder_binding(&holder.public_key).is_ok());
+    assert!(vp_bound.verify_holder_binding(&issuer.public_key).is_err());
+
+    let other_vc = VerifiableCredential::new(
+        vc_one(),
+        Some(SignedSchema::new(schema_vc, &issuer.public_key)),
+    )
+    .unwrap()
+    .sign(&issuer.private_key)
+    .unwrap();
+    let vp_unbound = VerifiablePresentation::new(vp(&holder_did, &other_vc))
+        .unwrap()
+        .sign(&holder.private_key)
+        .unwrap();
+    assert!(vp_unbound.verify(&holder.public_key).is_ok());
+    assert_eq!(
+        vp_unbound.verify_holder_binding(&holder.public_key),
+        Err(
+            "Credentials not bound to holder: urn:uuid:a8059f21-dc57-4684-a88f-9d2457e21631".into()
+        )
+    );
+}
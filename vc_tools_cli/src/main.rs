@@ -1,13 +1,18 @@
 use clap::{Parser, Subcommand, ValueEnum};
-use serde_json::{from_str, Value};
+use serde_json::{from_slice, json, Value};
 use std::error::Error;
 use std::path::Path;
 use std::{
     fs::{read, read_to_string},
     path::PathBuf,
 };
+use vc_signing::compiled_schema::CompiledSchema;
+use vc_signing::format::{self, document_type, sniff, DocumentType, Encoding};
+use vc_signing::protobuf_schema::{annotate_field_numbers, SubjectDescriptor};
+use vc_signing::schema::ValidationOptions;
+use vc_signing::schema_diff;
 use vc_signing::verifiable_credential::SignedSchema;
-use vc_signing::{SignatureKeyPair, VerifiableCredential};
+use vc_signing::{SignatureKeyPair, VerifiableCredential, VerifiablePresentation};
 
 #[derive(Parser)]
 struct Args {
@@ -26,6 +31,8 @@ enum Function {
         format: Format,
         #[clap(long, short)]
         generate: bool,
+        #[clap(long)]
+        strict_formats: bool,
     },
     SignSchema {
         vc_path: PathBuf,
@@ -35,18 +42,109 @@ enum Function {
         #[clap(long, short)]
         generate: bool,
     },
+    SignVP {
+        vp_path: PathBuf,
+        signing_key_path: PathBuf,
+        output_path: PathBuf,
+        format: Format,
+    },
     Verify {
         vc_path: PathBuf,
         public_key_path: PathBuf,
+        #[clap(long)]
+        schema_path: Option<PathBuf>,
+    },
+    VerifyVP {
+        vp_path: PathBuf,
+        public_key_path: PathBuf,
+        #[clap(long)]
+        holder_binding: bool,
+    },
+    Validate {
+        vc_path: PathBuf,
+        schema_path: PathBuf,
+        schema_key_path: PathBuf,
+        #[clap(long)]
+        strict_formats: bool,
+    },
+    SchemaDiff {
+        old_schema_path: PathBuf,
+        new_schema_path: PathBuf,
+        #[clap(long, requires = "schema_key_path")]
+        corpus: Option<PathBuf>,
+        #[clap(long)]
+        schema_key_path: Option<PathBuf>,
+    },
+    SampleSubject {
+        schema_path: PathBuf,
+        schema_key_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long, short)]
+        full: bool,
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    AnnotateProtoFields {
+        schema_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long)]
+        previous: Option<PathBuf>,
+    },
+    ProtoSchema {
+        schema_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long, default_value = "CredentialSubject")]
+        message_name: String,
     },
     Encode {
         vc_path: PathBuf,
         output_path: PathBuf,
         format: Format,
+        #[clap(long)]
+        schema_path: Option<PathBuf>,
+        #[clap(long)]
+        signing_key_path: Option<PathBuf>,
+        #[clap(long)]
+        compress: bool,
     },
     Decode {
         vc_path: PathBuf,
         output_path: PathBuf,
+        #[clap(long)]
+        schema_path: Option<PathBuf>,
+    },
+    IssueSdJwt {
+        vc_path: PathBuf,
+        signing_key_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long)]
+        disclosable: Vec<String>,
+        #[clap(long)]
+        holder_key_path: Option<PathBuf>,
+    },
+    SdJwtClaims {
+        sd_jwt_path: PathBuf,
+    },
+    PresentSdJwt {
+        sd_jwt_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long)]
+        disclose: Vec<String>,
+        #[clap(long, requires_all = ["audience", "nonce"])]
+        holder_key_path: Option<PathBuf>,
+        #[clap(long)]
+        audience: Option<String>,
+        #[clap(long)]
+        nonce: Option<String>,
+    },
+    VerifySdJwt {
+        sd_jwt_path: PathBuf,
+        public_key_path: PathBuf,
+        output_path: PathBuf,
+        #[clap(long)]
+        audience: Option<String>,
+        #[clap(long)]
+        nonce: Option<String>,
     },
     GenKeys {
         private_key_path: PathBuf,
@@ -57,23 +155,147 @@ enum Function {
 #[derive(ValueEnum, Clone)]
 enum Format {
     Protobuf,
+    CompactProtobuf,
     Cbor,
+    CborLd,
+    Cose,
+    Jwt,
+    Qr,
     Json,
 }
 
+/// A credential or presentation, as read from any supported format
+enum Document {
+    Credential(Box<VerifiableCredential>),
+    Presentation(Box<VerifiablePresentation>),
+}
+
+impl Document {
+    /// Loads a credential or presentation in any supported format, detecting which it is
+    fn load(reader: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        Ok(match document_type(&reader)? {
+            DocumentType::Credential => {
+                Document::Credential(Box::new(VerifiableCredential::deserialize_any(reader)?))
+            }
+            DocumentType::Presentation => {
+                Document::Presentation(Box::new(VerifiablePresentation::deserialize_any(reader)?))
+            }
+        })
+    }
+    fn to_json(&self) -> Result<Value, serde_json::Error> {
+        match self {
+            Document::Credential(vc) => serde_json::to_value(vc),
+            Document::Presentation(vp) => serde_json::to_value(vp),
+        }
+    }
+    fn to_string(&self, pretty: bool) -> Result<String, serde_json::Error> {
+        match (self, pretty) {
+            (Document::Credential(vc), true) => serde_json::to_string_pretty(vc),
+            (Document::Credential(vc), false) => serde_json::to_string(vc),
+            (Document::Presentation(vp), true) => serde_json::to_string_pretty(vp),
+            (Document::Presentation(vp), false) => serde_json::to_string(vp),
+        }
+    }
+}
+
+/// Decodes a protobuf credential with its schema credential, for compact protobuf, or returns None for other documents
+fn compact_credential(
+    reader: &[u8],
+    schema_path: Option<&Path>,
+) -> Result<Option<VerifiableCredential>, Box<dyn Error>> {
+    let compact = matches!(
+        sniff(reader),
+        Ok(Encoding::CompactProtobuf | Encoding::Protobuf)
+    ) && document_type(reader) == Ok(DocumentType::Credential);
+    match schema_path {
+        Some(schema_path) if compact => {
+            let schema = VerifiableCredential::new(read_json(schema_path)?, None)?;
+            Ok(Some(VerifiableCredential::deserialize_protobuf_compact(
+                reader.to_vec(),
+                &SubjectDescriptor::new(&schema)?,
+            )?))
+        }
+        _ => Ok(None),
+    }
+}
+
 fn write_format(
     format: Format,
     path: &Path,
-    vc: VerifiableCredential,
+    document: Document,
+    schema: Option<&VerifiableCredential>,
+    signing_key: Option<&[u8]>,
+    compress: bool,
 ) -> Result<(), Box<dyn Error>> {
-    match format {
-        Format::Protobuf => std::fs::write(path, vc.serialize_protobuf())?,
-        Format::Cbor => std::fs::write(path, vc.serialize_cbor()?)?,
-        Format::Json => std::fs::write(path, serde_json::to_string_pretty(&vc)?)?,
+    let enveloped = matches!(
+        format,
+        Format::Protobuf | Format::CompactProtobuf | Format::Cbor | Format::CborLd
+    );
+    let image = path.extension().and_then(|extension| extension.to_str());
+    let encoded = match (format, document) {
+        (Format::Protobuf, Document::Credential(vc)) => vc.serialize_protobuf(),
+        (Format::Protobuf, Document::Presentation(vp)) => vp.serialize_protobuf(),
+        (Format::CompactProtobuf, Document::Credential(vc)) => {
+            let schema = schema.ok_or("Compact protobuf needs the schema credential")?;
+            vc.serialize_protobuf_compact(&SubjectDescriptor::new(schema)?)?
+        }
+        (Format::CompactProtobuf, Document::Presentation(_)) => {
+            return Err("Compact protobuf only encodes credentials".into())
+        }
+        (Format::Cbor, Document::Credential(vc)) => vc.serialize_cbor()?,
+        (Format::Cbor, Document::Presentation(vp)) => vp.serialize_cbor()?,
+        (Format::CborLd, Document::Credential(vc)) => vc.serialize_cbor_ld()?,
+        (Format::CborLd, Document::Presentation(vp)) => vp.serialize_cbor_ld()?,
+        (Format::Cose, document) => {
+            let signing_key = signing_key.ok_or("COSE needs a signing key")?;
+            match document {
+                Document::Credential(vc) => vc.sign_cose(signing_key)?,
+                Document::Presentation(vp) => vp.sign_cose(signing_key)?,
+            }
+        }
+        (Format::Jwt, document) => {
+            let signing_key = signing_key.ok_or("JWT needs a signing key")?;
+            match document {
+                Document::Credential(vc) => vc.sign_jwt(signing_key)?,
+                Document::Presentation(vp) => vp.sign_jwt(signing_key)?,
+            }
+            .into_bytes()
+        }
+        (Format::Qr, Document::Credential(vc)) => match image {
+            Some("png") => vc.qr_png()?,
+            Some("svg") => vc.qr_svg()?.into_bytes(),
+            _ => vc.serialize_qr()?.into_bytes(),
+        },
+        (Format::Qr, Document::Presentation(vp)) => match image {
+            Some("png") => vp.qr_png()?,
+            Some("svg") => vp.qr_svg()?.into_bytes(),
+            _ => vp.serialize_qr()?.into_bytes(),
+        },
+        (Format::Json, document) => document.to_string(true)?.into_bytes(),
+    };
+    let encoded = match (compress, enveloped) {
+        (true, true) => format::compress(encoded)?,
+        (true, false) => return Err("Only Protobuf, CBOR and CBOR-LD can be compressed".into()),
+        (false, _) => encoded,
     };
+    std::fs::write(path, encoded)?;
     Ok(())
 }
 
+/// Reads a credential in any supported format
+fn read_vc(path: &Path) -> Result<VerifiableCredential, Box<dyn Error>> {
+    Ok(VerifiableCredential::deserialize_any(read(path)?)?)
+}
+
+/// Reads a JSON document, or a credential or presentation in any other supported format as JSON
+fn read_json(path: &Path) -> Result<Value, Box<dyn Error>> {
+    let reader = read(path)?;
+    match sniff(&reader) {
+        Ok(Encoding::Json) | Err(_) => Ok(from_slice(&reader)?),
+        Ok(_) => Ok(Document::load(reader)?.to_json()?),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
     match args.function {
@@ -85,27 +307,30 @@ fn main() -> Result<(), Box<dyn Error>> {
             output_path,
             format,
             generate,
+            strict_formats,
         } => {
-            let vc: Value = from_str(&read_to_string(vc_path)?)?;
-            let schema: Value = from_str(&read_to_string(schema_path)?)?;
+            let vc = read_json(&vc_path)?;
+            let schema = read_json(&schema_path)?;
+            let schema_key = read(schema_key_path)?;
+            let schema_vc = VerifiableCredential::new(schema, None)?;
+            let schema = SignedSchema::new(schema_vc.clone(), &schema_key);
+            let options = ValidationOptions::new().should_validate_formats(strict_formats);
             let vc = match generate {
-                true => VerifiableCredential::create(
+                true => VerifiableCredential::create_with_compiled(
                     vc,
-                    Some(SignedSchema::new(
-                        VerifiableCredential::new(schema, None)?,
-                        &read(schema_key_path)?,
-                    )),
-                )?,
-                false => VerifiableCredential::new(
-                    vc,
-                    Some(SignedSchema::new(
-                        VerifiableCredential::new(schema, None)?,
-                        &read(schema_key_path)?,
-                    )),
+                    &CompiledSchema::new_with_options(schema, vec![], &options)?,
                 )?,
+                false => VerifiableCredential::new_with_schema(vc, Some(schema), &options)?,
             }
-            .sign(&read(signing_key_path)?)?;
-            write_format(format, &output_path, vc)?;
+            .sign(&read(&signing_key_path)?)?;
+            write_format(
+                format,
+                &output_path,
+                Document::Credential(Box::new(vc)),
+                Some(&schema_vc),
+                Some(&read(signing_key_path)?),
+                false,
+            )?;
         }
         Function::SignSchema {
             vc_path,
@@ -114,43 +339,270 @@ fn main() -> Result<(), Box<dyn Error>> {
             format,
             generate,
         } => {
-            let schema: Value = from_str(&read_to_string(vc_path)?)?;
+            let schema = read_json(&vc_path)?;
             let vc = match generate {
                 true => VerifiableCredential::create(schema, None)?,
                 false => VerifiableCredential::new(schema, None)?,
             }
-            .sign(&read(private_key_path)?)?;
-            write_format(format, &output_path, vc)?;
+            .sign(&read(&private_key_path)?)?;
+            let schema = vc.clone();
+            write_format(
+                format,
+                &output_path,
+                Document::Credential(Box::new(vc)),
+                Some(&schema),
+                Some(&read(private_key_path)?),
+                false,
+            )?;
+        }
+        Function::SignVP {
+            vp_path,
+            signing_key_path,
+            output_path,
+            format,
+        } => {
+            let signing_key = read(signing_key_path)?;
+            let vp = VerifiablePresentation::new(read_json(&vp_path)?)?.sign(&signing_key)?;
+            write_format(
+                format,
+                &output_path,
+                Document::Presentation(Box::new(vp)),
+                None,
+                Some(&signing_key),
+                false,
+            )?;
         }
         Function::Verify {
             vc_path,
             public_key_path,
+            schema_path,
         } => {
-            let vc: VerifiableCredential = from_str(&read_to_string(vc_path)?)?;
-            println!("{:?}", vc.verify(&read(public_key_path)?));
+            let vc = read(vc_path)?;
+            let public_key = read(public_key_path)?;
+            let verified = match compact_credential(&vc, schema_path.as_deref()) {
+                Ok(Some(compact)) => compact.verify(&public_key),
+                Ok(None) => match document_type(&vc) {
+                    Ok(DocumentType::Presentation) => {
+                        VerifiablePresentation::verify_any(vc, &public_key).map(|_| ())
+                    }
+                    _ => VerifiableCredential::verify_any(vc, &public_key).map(|_| ()),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            println!("{:?}", verified);
+        }
+        Function::VerifyVP {
+            vp_path,
+            public_key_path,
+            holder_binding,
+        } => {
+            let vp = read(vp_path)?;
+            let public_key = read(public_key_path)?;
+            let verified = match holder_binding {
+                true => VerifiablePresentation::deserialize_any(vp)
+                    .and_then(|vp| vp.verify_holder_binding(&public_key)),
+                false => VerifiablePresentation::verify_any(vp, &public_key).map(|_| ()),
+            };
+            println!("{:?}", verified);
+        }
+        Function::Validate {
+            vc_path,
+            schema_path,
+            schema_key_path,
+            strict_formats,
+        } => {
+            let vc = read_vc(&vc_path)?;
+            let schema = read_json(&schema_path)?;
+            let errors = vc.schema_errors(
+                vec![SignedSchema::new(
+                    VerifiableCredential::new(schema, None)?,
+                    &read(schema_key_path)?,
+                )],
+                &ValidationOptions::new().should_validate_formats(strict_formats),
+            );
+            println!("{}", serde_json::to_string_pretty(&errors)?);
+            if !errors.is_empty() {
+                return Err("Credential does not match the schema".into());
+            }
+        }
+        Function::SchemaDiff {
+            old_schema_path,
+            new_schema_path,
+            corpus,
+            schema_key_path,
+        } => {
+            let old = VerifiableCredential::new(read_json(&old_schema_path)?, None)?;
+            let new = VerifiableCredential::new(read_json(&new_schema_path)?, None)?;
+            let changes = schema_diff::diff(&old, &new)?;
+            let mut breaking = changes.iter().any(|change| change.breaking);
+            let mut failures = Vec::new();
+            if let (Some(corpus), Some(schema_key_path)) = (corpus, schema_key_path) {
+                let schema =
+                    CompiledSchema::new(SignedSchema::new(new, &read(schema_key_path)?), vec![])?;
+                for entry in corpus.read_dir()? {
+                    let path = entry?.path();
+                    if path.is_file() && sniff(&read(&path)?).is_ok() {
+                        let vc = read_vc(&path)?;
+                        let errors = schema.subject_errors(&vc);
+                        if !errors.is_empty() {
+                            failures.push(json!({"path": path, "errors": errors}));
+                        }
+                    }
+                }
+                breaking |= !failures.is_empty();
+            }
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "breaking": breaking,
+                    "changes": changes,
+                    "corpusFailures": failures,
+                }))?
+            );
+            if breaking {
+                return Err("New schema is not backwards compatible".into());
+            }
+        }
+        Function::SampleSubject {
+            schema_path,
+            schema_key_path,
+            output_path,
+            full,
+            seed,
+        } => {
+            let schema = VerifiableCredential::new(read_json(&schema_path)?, None)?;
+            let subject =
+                SignedSchema::new(schema, &read(schema_key_path)?).sample_subject(full, seed)?;
+            std::fs::write(output_path, serde_json::to_string_pretty(&subject)?)?;
+        }
+        Function::AnnotateProtoFields {
+            schema_path,
+            output_path,
+            previous,
+        } => {
+            let mut schema = read_json(&schema_path)?;
+            let previous = match previous {
+                Some(previous) => {
+                    let previous = read_json(&previous)?;
+                    match serde_json::from_value::<VerifiableCredential>(previous.clone()) {
+                        Ok(previous) => Some(previous.json_schema()?.clone()),
+                        Err(_) => Some(previous),
+                    }
+                }
+                None => None,
+            };
+            annotate_field_numbers(&mut schema, previous.as_ref());
+            std::fs::write(output_path, serde_json::to_string_pretty(&schema)?)?;
+        }
+        Function::ProtoSchema {
+            schema_path,
+            output_path,
+            message_name,
+        } => {
+            let schema = VerifiableCredential::new(read_json(&schema_path)?, None)?;
+            std::fs::write(
+                output_path,
+                SubjectDescriptor::new(&schema)?.to_proto(&message_name),
+            )?;
         }
         Function::Encode {
             vc_path,
             output_path,
             format,
+            schema_path,
+            signing_key_path,
+            compress,
         } => {
-            let vc: VerifiableCredential = from_str(&read_to_string(vc_path)?)?;
-            write_format(format, &output_path, vc)?;
+            let document = Document::load(read(vc_path)?)?;
+            let schema = match schema_path {
+                Some(schema_path) => {
+                    Some(VerifiableCredential::new(read_json(&schema_path)?, None)?)
+                }
+                None => None,
+            };
+            let signing_key = match signing_key_path {
+                Some(signing_key_path) => Some(read(signing_key_path)?),
+                None => None,
+            };
+            write_format(
+                format,
+                &output_path,
+                document,
+                schema.as_ref(),
+                signing_key.as_deref(),
+                compress,
+            )?;
         }
         Function::Decode {
             vc_path,
             output_path,
+            schema_path,
         } => {
-            let vc = read(vc_path)?;
-            match VerifiableCredential::deserialize_protobuf(vc.clone()) {
-                Ok(decoded_vc) => std::fs::write(output_path, serde_json::to_string(&decoded_vc)?)?,
-                Err(_) => match VerifiableCredential::deserialize_cbor(vc) {
-                    Ok(decoded_vc) => {
-                        std::fs::write(output_path, serde_json::to_string(&decoded_vc)?)?
-                    }
-                    Err(_) => return Err("Failed to deserialize credential".into()),
-                },
-            }
+            let reader = read(vc_path)?;
+            let decoded = match compact_credential(&reader, schema_path.as_deref())? {
+                Some(vc) => Document::Credential(Box::new(vc)),
+                None => Document::load(reader)?,
+            };
+            std::fs::write(output_path, decoded.to_string(false)?)?;
+        }
+        Function::IssueSdJwt {
+            vc_path,
+            signing_key_path,
+            output_path,
+            disclosable,
+            holder_key_path,
+        } => {
+            let vc = read_vc(&vc_path)?;
+            let holder_key = match holder_key_path {
+                Some(holder_key_path) => Some(read(holder_key_path)?),
+                None => None,
+            };
+            std::fs::write(
+                output_path,
+                vc.issue_sd_jwt(&read(signing_key_path)?, disclosable, holder_key)?,
+            )?;
+        }
+        Function::SdJwtClaims { sd_jwt_path } => {
+            let claims = VerifiableCredential::sd_jwt_claims(&read_to_string(sd_jwt_path)?)?;
+            println!("{}", serde_json::to_string_pretty(&claims)?);
+        }
+        Function::PresentSdJwt {
+            sd_jwt_path,
+            output_path,
+            disclose,
+            holder_key_path,
+            audience,
+            nonce,
+        } => {
+            let holder_key = match holder_key_path {
+                Some(holder_key_path) => Some(read(holder_key_path)?),
+                None => None,
+            };
+            std::fs::write(
+                output_path,
+                VerifiableCredential::present_sd_jwt(
+                    &read_to_string(sd_jwt_path)?,
+                    disclose,
+                    holder_key,
+                    audience,
+                    nonce,
+                )?,
+            )?;
+        }
+        Function::VerifySdJwt {
+            sd_jwt_path,
+            public_key_path,
+            output_path,
+            audience,
+            nonce,
+        } => {
+            let vc = VerifiableCredential::verify_sd_jwt(
+                &read_to_string(sd_jwt_path)?,
+                &read(public_key_path)?,
+                audience,
+                nonce,
+            )?;
+            std::fs::write(output_path, serde_json::to_string_pretty(&vc)?)?;
         }
         Function::GenKeys {
             private_key_path,